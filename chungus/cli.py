@@ -3,12 +3,20 @@ from pathlib import Path
 
 import numpy as np
 import tensorflow as tf
-import chessers
+import chessers.games, chessers.data
 import model, dataset
 
 
 from util import fmtsize, num_cores
 
+def print_progress(progress):
+    line = f'{progress.games_accepted} games accepted, {progress.games_rejected} rejected'
+    if progress.positions:
+        line += f', {progress.positions} positions'
+    if progress.eta is not None:
+        line += f', {fmtsize(progress.bytes_processed)} done, ETA {progress.eta:.0f}s'
+    print(f'\r{line}', end='\n' if progress.done else '', flush=True)
+
 @click.group()
 def cli():
     pass
@@ -21,10 +29,10 @@ def cli():
 def pgn_convert(srcpath, dstpath, min_elo, max_elo_diff):
     if srcpath.endswith(".pgn") and dstpath.endswith(".bin"):
         print(f'Converting {srcpath} from PGN to binary {dstpath}')
-        chessers.pgn.pgn_to_bin(srcpath, dstpath, min_elo, max_elo_diff)
+        chessers.games.pgn_to_bin(srcpath, dstpath, min_elo, max_elo_diff, progress=print_progress)
     elif srcpath.endswith(".bin") and dstpath.endswith(".pgn"):
         print(f'Converting {srcpath} from binary to PGN {dstpath}')
-        chessers.pgn.bin_to_pgn(srcpath, dstpath, min_elo, max_elo_diff)
+        chessers.games.bin_to_pgn(srcpath, dstpath, min_elo, max_elo_diff, progress=print_progress)
     else:
         print(f"Invalid combination of inputs, you can only convert .bin to .pgn or vice versa")
 
@@ -37,7 +45,7 @@ def pgn_stat(filepath):
         return
 
     size = os.stat(path).st_size
-    games = [*chessers.pgn.GameLoader(str(path))]
+    games = [*chessers.games.GameLoader(str(path))]
     moves = sum([len(game.moves()) for game in games])
     
     print(f'name:  {path.name}')
@@ -50,16 +58,25 @@ def pgn_stat(filepath):
 @click.argument('filepath')
 @click.argument('name')
 @click.option('--games', default='1000')
-def encode(filepath, name, games):
+@click.option('--encoder', default='v1')
+@click.option('--val_ratio', default=0.0)
+@click.option('--test_ratio', default=0.0)
+@click.option('--split_by', default='moves', type=click.Choice(['moves', 'players']))
+def encode(filepath, name, games, encoder, val_ratio, test_ratio, split_by):
+    if val_ratio or test_ratio:
+        split = chessers.data.Split(validation=val_ratio, test=test_ratio, by=split_by)
+        train, val, test = chessers.data.TrainData.convert_games_split_and_save(filepath, int(games), name, split, encoder=encoder, progress=print_progress)
+        print(f'Wrote {train} train, {val} val and {test} test shards')
+        return
     dir = 'data/train' / Path(name)
     if not dir.exists():
         dir.mkdir()
-    chessers.TrainData.convert_games_and_save(filepath, int(games), name) 
+    chessers.data.TrainData.convert_games_and_save(filepath, int(games), name, encoder=encoder, progress=print_progress)
 
 @cli.command('bench_dataset')
 def bench_dataset():
     start = time.time()
-    d = chessers.TrainData.load("data/train/2019_07/000.bin")
+    d = chessers.data.TrainData.load("data/train/2019_07/000.bin")
     positions = d.get_ins().shape[0]
     print(f"Loaded {positions} positions in {time.time() - start:.2f} seconds")
     
@@ -1,13 +1,13 @@
 import numpy as np
 import tensorflow as tf
 import os
-import chessers
+import chessers.data
 from pathlib import Path
 
 def get_train_data(prefetch_data_files: int):
     files = list(Path('.').glob('data/train/*/*.bin'))
     files.sort()
-    return chessers.TrainDataLoader(files, prefetch_data_files)
+    return chessers.data.TrainDataLoader(files, prefetch_data_files)
   
         
 
@@ -21,17 +21,31 @@ def get_train_data(prefetch_data_files: int):
 #            if end_idx < num_samples:
 #                yield (x[start_idx:end_idx], y[start_idx:end_idx])
 
+def load_split(split: str):
+    # Shards written by `encode --val_ratio/--test_ratio` hold disjoint games,
+    # older setups only have a hand-made data/test.bin
+    files = sorted(Path('.').glob(f'data/{split}/*/*.bin'))
+    if not files:
+        files = [Path('data/test.bin')]
+    shards = [chessers.data.TrainData.load(str(f)) for f in files]
+    ins = np.concatenate([d.get_ins() for d in shards])
+    outs = np.concatenate([d.get_outs() for d in shards])
+    return tf.data.Dataset.from_tensor_slices((ins, outs))
+
+def get_validation_data():
+    return load_split('val')
+
 def get_test_data():
-    data = chessers.TrainData.load('data/test.bin')
-    return tf.data.Dataset.from_tensor_slices((data.get_ins(), data.get_outs()))
+    return load_split('test')
 
-def get_data(batch_size: int, prefetch_data_files: int):
+def get_data(batch_size: int, prefetch_data_files: int, encoder: str = 'v1'):
+    planes = len(chessers.data.plane_names(encoder))
     train_dataset = tf.data.Dataset.from_generator(
         lambda: get_train_data(prefetch_data_files),
         output_signature=(
-            tf.TensorSpec(shape=(None, 8, 8, 37), dtype=tf.float32), 
+            tf.TensorSpec(shape=(None, 8, 8, planes), dtype=tf.float32), 
             tf.TensorSpec(shape=(None, 3,), dtype=tf.float32)
         ),
     ).flat_map(lambda x, y: tf.data.Dataset.from_tensor_slices((x, y))).shuffle(10_000).batch(batch_size, drop_remainder=True).prefetch(tf.data.AUTOTUNE)
     
-    return train_dataset, get_test_data().batch(batch_size)
+    return train_dataset, get_validation_data().batch(batch_size)
@@ -4,6 +4,7 @@ from tensorflow import keras
 from keras import layers, losses, optimizers, metrics, callbacks, regularizers
 import dataset, chessers
 
+CZECHERNET_ENCODER = "v1"
 CZECHERNET_FILTERS = 256
 CZECHERNET_TRAIN_BATCH_SIZE = 64
 CZECHERNET_TRAIN_EPOCHS = 50
@@ -12,7 +13,7 @@ CZECHERNET_TRAIN_PREFETCH_FILES = 3
 CZECHERNET_CHECKPOINT_FILE = "data/checkpoint.weights.h5"
 
 def create_model():
-    input  = layers.Input(shape=(8, 8, 37))
+    input  = layers.Input(shape=(8, 8, len(chessers.data.plane_names(CZECHERNET_ENCODER))))
     # First set of conv layers
     x = layers.Conv2D(CZECHERNET_FILTERS//4, (3, 3), activation='relu', padding='same')(input)
     x = layers.Conv2D(CZECHERNET_FILTERS//4, (3, 3), activation='relu', padding='same')(x)
@@ -56,7 +57,7 @@ class CzecherNet:
         early_stopping = callbacks.EarlyStopping(monitor='loss', patience=10, restore_best_weights=True, verbose=1)
         checkpoint_cb = callbacks.ModelCheckpoint('best.keras', monitor='loss', save_freq='epoch', verbose=1)
 
-        train, test = dataset.get_data(batch_size, prefetch_data_files)
+        train, test = dataset.get_data(batch_size, prefetch_data_files, CZECHERNET_ENCODER)
         
         self.model.fit(
             train,
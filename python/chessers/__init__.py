@@ -0,0 +1,37 @@
+"""Chess game archives and neural network training data for chungus chess."""
+
+import sys
+
+from ._chessers import (
+    CancelledError,
+    CancelToken,
+    ChessersError,
+    FenError,
+    FormatError,
+    IllegalMoveError,
+    IoError,
+    ParseError,
+    Progress,
+    configure_logging,
+    data,
+    games,
+)
+
+# Extension submodules are plain attributes, register them so `import chessers.games` works
+sys.modules[__name__ + ".games"] = games
+sys.modules[__name__ + ".data"] = data
+
+__all__ = [
+    "CancelledError",
+    "CancelToken",
+    "ChessersError",
+    "FenError",
+    "FormatError",
+    "IllegalMoveError",
+    "IoError",
+    "ParseError",
+    "Progress",
+    "configure_logging",
+    "data",
+    "games",
+]
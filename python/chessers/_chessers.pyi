@@ -0,0 +1,80 @@
+from typing import Callable
+
+from . import data as data
+from . import games as games
+
+class ChessersError(Exception):
+    """Base class of all chessers errors."""
+
+class FormatError(ChessersError):
+    """Malformed game archive or train data."""
+
+class ParseError(ChessersError):
+    """Malformed PGN game."""
+
+    game: int
+    """Index of the game in its file, starting at 0."""
+
+class IllegalMoveError(ChessersError):
+    """Move which is not legal in its position."""
+
+    ply: int
+    """The ply of the move, starting at 1."""
+    uci: str
+
+class FenError(ChessersError):
+    """Malformed FEN or illegal position."""
+
+    fen: str
+
+class IoError(ChessersError):
+    """Failed file operation."""
+
+    filename: str | None
+    errno: int | None
+
+class CancelledError(ChessersError):
+    """Operation stopped through its CancelToken."""
+
+class CancelToken:
+    """Stops the operations it is passed to, from any thread."""
+
+    def __init__(self) -> None: ...
+    def cancel(self) -> None: ...
+    @property
+    def cancelled(self) -> bool: ...
+
+class Progress:
+    """Snapshot of the work done by a long running operation."""
+
+    @property
+    def bytes_processed(self) -> int: ...
+    @property
+    def bytes_total(self) -> int | None: ...
+    @property
+    def games_accepted(self) -> int: ...
+    @property
+    def games_rejected(self) -> int: ...
+    @property
+    def positions(self) -> int:
+        """Positions encoded into training data."""
+    @property
+    def elapsed(self) -> float:
+        """Seconds since the operation started."""
+    @property
+    def eta(self) -> float | None:
+        """Estimated seconds until completion."""
+    @property
+    def done(self) -> bool:
+        """Whether this is the final report of a successful operation."""
+
+ProgressCallback = Callable[[Progress], object]
+
+def configure_logging(
+    level: str | None = None,
+    stderr: bool | None = None,
+    python: bool | None = None,
+) -> None:
+    """Changes the `RUST_LOG` style filter directives, whether events are printed to stderr
+    and whether they are forwarded to the `chessers.*` loggers. `None` keeps the current
+    setting."""
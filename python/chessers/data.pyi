@@ -0,0 +1,260 @@
+import os
+from typing import Any, Iterator, Sequence
+
+import numpy as np
+import numpy.typing as npt
+
+from ._chessers import CancelToken, ProgressCallback
+from .games import Game
+
+def encoders() -> list[str]:
+    """Names of the registered feature encoders.
+
+    Options are appended to an encoder name with `+`: `v1+state` adds castling rights,
+    en passant, move counter and repetition planes, `v1+history=8` the pieces of the last
+    8 positions and `v1+relative` encodes positions and outcomes from the perspective of
+    the side to move."""
+
+def plane_names(encoder: str = "v1") -> list[str]:
+    """Names of the planes produced by a feature encoder."""
+
+def scalar_names(encoder: str = "v1") -> list[str]:
+    """Names of the scalar features of positions encoded by a feature encoder, `own` and
+    `opponent` instead of `white` and `black` for relative ones."""
+
+def history(encoder: str = "v1") -> int:
+    """Number of earlier positions a feature encoder stacks onto each position."""
+
+def encode_fen(
+    fen: str, encoder: str = "v1", history: Sequence[str] | None = None
+) -> npt.NDArray[np.float32]:
+    """Encodes a position of shape `(8, 8, planes)` the way positions of games are encoded
+    for training. `history` are the FENs of the positions before it, oldest first.
+
+    Raises `FenError` on malformed FENs or illegal positions."""
+
+def encode_fens(
+    fens: Sequence[str],
+    encoder: str = "v1",
+    threads: int | None = None,
+    histories: Sequence[Sequence[str]] | None = None,
+) -> npt.NDArray[np.float32]:
+    """Encodes a batch of positions of shape `(len(fens), 8, 8, planes)` on `threads`
+    threads, all cores by default, each like `encode_fen`. `histories` holds the FENs of
+    the positions before each of `fens`; encoders with history raise without them."""
+
+def decode_planes(planes: npt.NDArray[np.float32], encoder: str = "v1") -> str:
+    """FEN of an encoded position of shape `(8, 8, planes)`, as seen by the side to move
+    for relative encoders. Raises `FormatError` if encoding the FEN again does not give
+    the same planes, apart from those of earlier positions."""
+
+def dump_planes(planes: npt.NDArray[np.float32], encoder: str = "v1") -> str:
+    """Every plane of an encoded position as an ASCII board with its name, rank 8 at the
+    top and `.` for zeros."""
+
+def move_vocabulary(turn: str = "white", flip: bool = False) -> list[str | None]:
+    """UCI moves of the 8x8x73 policy vocabulary for `turn` to move, `None` where an index
+    leaves the board. `flip` is set for black to move under relative encoders."""
+
+def move_index(uci: str, flip: bool = False) -> int:
+    """Policy vocabulary index of a UCI move, castling given by the king's destination."""
+
+def index_move(index: int, turn: str = "white", flip: bool = False) -> str | None:
+    """UCI move of a policy vocabulary index, `None` if it leaves the board."""
+
+def rank_moves(
+    position: str | Game, probabilities: npt.NDArray[np.float32], encoder: str = "v1"
+) -> list[tuple[str, float]]:
+    """Legal moves of a FEN or of the final position of a game, ranked by the predicted
+    probabilities over the move vocabulary of `encoder`. Probabilities of illegal moves
+    are dropped and the rest renormalized."""
+
+class Sampling:
+    """Which positions of each game are encoded.
+
+    The positions reached by the first `skip_plies` moves are skipped, each other one is
+    kept with `probability` and at most `max_per_game` of them are chosen, with
+    `ply_weighted` later positions more likely. Choices are seeded by `seed` and the moves
+    of each game, so they do not depend on the order games are encoded in."""
+
+    def __init__(
+        self,
+        skip_plies: int = 0,
+        probability: float = 1.0,
+        max_per_game: int | None = None,
+        ply_weighted: bool = False,
+        seed: int = 0,
+    ) -> None: ...
+
+class ValueTargets:
+    """How outcome labels are built, by default the one-hot game result.
+
+    A position `n` plies before the end of its game is labelled `a * R + (1 - a) * P`, with
+    `a = (1 - elo_weight) * discount**n`, `R` its result and `P` the WDL of the Elo
+    expected score. Positions with an engine eval are labelled
+    `(1 - elo_weight) * E + elo_weight * P` with `E` the WDL of the eval, not discounted.
+    WDLs of expected scores give draws `draw_rate`. With `moves_left` the plies left in the
+    game are recorded too."""
+
+    def __init__(
+        self,
+        discount: float | None = None,
+        elo_weight: float = 0.0,
+        draw_rate: float = 0.1,
+        moves_left: bool = False,
+    ) -> None: ...
+
+class Split:
+    """How games are split into train, validation and test sets, by default all of them
+    into train.
+
+    Each game goes to a set by a stable hash of its moves, so copies of a game land in the
+    same set. `validation` and `test` are the shares of their sets. With `by="players"` the
+    names of the players are hashed instead and games between players of different sets
+    are left out, so no player occurs in two sets."""
+
+    def __init__(
+        self,
+        validation: float = 0.0,
+        test: float = 0.0,
+        by: str = "moves",
+        seed: int = 0,
+    ) -> None: ...
+    def assign(self, game: Game) -> str | None:
+        """Set of a game, `"train"`, `"val"` or `"test"`, or `None` if it is left out."""
+
+class TrainData:
+    def get_ins(self) -> npt.NDArray[Any]:
+        """Encoded positions of shape `(positions, 8, 8, planes)`, or
+        `(positions, planes, 8, 8)` in the `nchw` layout, with elements of `dtype()`."""
+    def get_outs(self) -> npt.NDArray[np.float32]:
+        """Outcome labels of shape `(positions, 3)`.
+
+        Rows are `[white win, draw, black win]`, or `[win, draw, loss]` of the side to
+        move for relative encoders."""
+    def get_policy(
+        self,
+    ) -> tuple[npt.NDArray[np.int32], npt.NDArray[np.bool_]] | None:
+        """Policy targets, if converted with `policy=True`: the 8x8x73 vocabulary index of
+        the move played next of shape `(positions,)`, -1 after the last move of a game, and
+        the legal move masks of shape `(positions, 4672)`."""
+    def get_weights(self) -> npt.NDArray[np.float32] | None:
+        """Weight of each position of shape `(positions,)`, for deduplicated data the
+        number of occurrences, 1 for data converted with `provenance=True`."""
+    def get_moves_left(self) -> npt.NDArray[np.float32] | None:
+        """Plies left until the end of the game of each position, if converted with
+        `ValueTargets(moves_left=True)`."""
+    def get_scalars(self) -> npt.NDArray[np.float32] | None:
+        """Scalar features of shape `(positions, features)` if converted with
+        `scalars=True`: Elos, time control, ply, clocks and material, named by
+        `scalar_names()`."""
+    def scalar_names(self) -> list[str]: ...
+    def get_provenance(
+        self,
+    ) -> tuple[npt.NDArray[np.uint64], npt.NDArray[np.uint32], npt.NDArray[np.uint64]] | None:
+        """Where each position comes from, if converted with `provenance=True`: the ordinal
+        of its game in the `.bin` archive counted from 0, the number of moves played before
+        it and its Zobrist hash. Augmented variants keep those of their position."""
+    def encoder(self) -> str:
+        """Name of the feature encoder of `get_ins`."""
+    def plane_names(self) -> list[str]: ...
+    def history(self) -> int:
+        """Number of earlier positions encoded into each position."""
+    def dtype(self) -> str:
+        """Element type of `get_ins`: `float32`, `float16`, `uint8` or `bool`."""
+    def layout(self) -> str:
+        """Axis order of `get_ins`: `nhwc`, or `nchw` with the planes first."""
+    def to_bytes(self) -> bytes: ...
+    @staticmethod
+    def from_bytes(data: bytes) -> TrainData: ...
+    def save(self, path: str) -> None: ...
+    @staticmethod
+    def load(path: str) -> TrainData: ...
+    @staticmethod
+    def convert_games_and_save(
+        path: str | os.PathLike[str],
+        max_games: int,
+        name: str,
+        encoder: str = "v1",
+        policy: bool = False,
+        dtype: str = "float32",
+        layout: str = "nhwc",
+        sampling: Sampling | None = None,
+        color_flip: bool = False,
+        mirror: bool = False,
+        targets: ValueTargets | None = None,
+        scalars: bool = False,
+        provenance: bool = False,
+        progress: ProgressCallback | None = None,
+        interval: float = 1.0,
+        cancel: CancelToken | None = None,
+    ) -> None: ...
+    @staticmethod
+    def convert_games_split_and_save(
+        path: str | os.PathLike[str],
+        max_games: int,
+        name: str,
+        split: Split,
+        encoder: str = "v1",
+        policy: bool = False,
+        dtype: str = "float32",
+        layout: str = "nhwc",
+        sampling: Sampling | None = None,
+        color_flip: bool = False,
+        mirror: bool = False,
+        targets: ValueTargets | None = None,
+        scalars: bool = False,
+        provenance: bool = False,
+        progress: ProgressCallback | None = None,
+        interval: float = 1.0,
+        cancel: CancelToken | None = None,
+    ) -> tuple[int, int, int]:
+        """Like `convert_games_and_save`, but splits the games by `split` in the same pass
+        and writes the shards of each set to `data/train/{name}`, `data/val/{name}` and
+        `data/test/{name}`. Returns the number of shards of each set."""
+    @staticmethod
+    def convert_games_dedup_and_save(
+        path: str | os.PathLike[str],
+        name: str,
+        encoder: str = "v1",
+        dtype: str = "float32",
+        layout: str = "nhwc",
+        sampling: Sampling | None = None,
+        targets: ValueTargets | None = None,
+        shard_positions: int = 100_000,
+        memory_positions: int = 1_000_000,
+        progress: ProgressCallback | None = None,
+        interval: float = 1.0,
+        cancel: CancelToken | None = None,
+    ) -> int:
+        """Encodes every distinct position of an archive once, keyed by its Zobrist hash,
+        into shards of `shard_positions` under `data/train/{name}`. Outcome labels are the
+        mean of all occurrences and `get_weights` their number. Beyond `memory_positions`
+        distinct positions they are spilled to sorted run files. Returns the number of
+        shards."""
+
+class TrainDataLoader:
+    def __init__(
+        self,
+        files: Sequence[str | os.PathLike[str]],
+        prefetch: int,
+        policy: bool = False,
+        weights: bool = False,
+        moves_left: bool = False,
+        scalars: bool = False,
+        provenance: bool = False,
+        color_flip: bool = False,
+        mirror: bool = False,
+        seed: int | None = None,
+    ) -> None:
+        """Loads shards in the background. Batches are `(ins, outs)`, followed by
+        `moves, legal` with `policy`, by the sample weights with `weights`, by the plies
+        left with `moves_left`, by the scalar features with `scalars` and by
+        `games, plies, hashes` with `provenance`, every shard needs to have what is asked
+        for. Inputs come in the dtype and layout each shard was
+        converted with.
+
+        With `color_flip` or `mirror` each position is swapped for its variant under these
+        symmetries with probability 1/2, seeded by `seed` or by default the current time."""
+    def __iter__(self) -> Iterator[tuple[npt.NDArray[Any], ...]]: ...
+    def __next__(self) -> tuple[npt.NDArray[Any], ...]: ...
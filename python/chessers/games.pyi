@@ -0,0 +1,107 @@
+import os
+from typing import Iterator, Sequence
+
+import numpy as np
+import numpy.typing as npt
+
+from ._chessers import CancelToken, ProgressCallback
+from .data import Sampling, TrainData, ValueTargets
+
+def pgn_to_bin(
+    pgn_path: str,
+    bin_path: str,
+    min_elo: int,
+    max_elo_diff: int,
+    progress: ProgressCallback | None = None,
+    interval: float = 1.0,
+    cancel: CancelToken | None = None,
+) -> None: ...
+def bin_to_pgn(
+    bin_path: str,
+    pgn_path: str,
+    min_elo: int,
+    max_elo_diff: int,
+    progress: ProgressCallback | None = None,
+    interval: float = 1.0,
+    cancel: CancelToken | None = None,
+) -> None: ...
+def filter_bin(
+    src_path: str | os.PathLike[str],
+    dst_path: str | os.PathLike[str],
+    min_elo: int = 0,
+    max_elo_diff: int = 5000,
+    min_plies: int = 0,
+    timectl_sec: int | None = None,
+    progress: ProgressCallback | None = None,
+    interval: float = 1.0,
+    cancel: CancelToken | None = None,
+) -> tuple[int, int]:
+    """Returns the number of games read and written."""
+
+class Game:
+    def white(self) -> str: ...
+    def black(self) -> str: ...
+    def white_elo(self) -> int: ...
+    def black_elo(self) -> int: ...
+    def outcome(self) -> str:
+        """`1-0`, `0-1` or `1/2-1/2`."""
+    def timectl_sec(self) -> int: ...
+    def timectl_inc(self) -> int: ...
+    def moves(self) -> list[str]:
+        """The moves in UCI notation."""
+    def to_pgn(
+        self,
+        line_width: int = 80,
+        event: str | None = None,
+        site: str | None = None,
+        date: str | None = None,
+        round: str | None = None,
+        termination: str | None = None,
+    ) -> str: ...
+    def to_annotated_pgn(
+        self,
+        wdl: npt.NDArray[np.float32],
+        good: float = 0.15,
+        mistake: float = 0.1,
+        blunder: float = 0.2,
+        line_width: int = 80,
+        nag_glyphs: bool = True,
+    ) -> str:
+        """`wdl` holds one `[white win, draw, black win]` row per ply."""
+    def encode(
+        self,
+        encoder: str = "v1",
+        evals: Sequence[str | None] | None = None,
+        clocks: Sequence[float | None] | None = None,
+        policy: bool = False,
+        dtype: str = "float32",
+        layout: str = "nhwc",
+        targets: ValueTargets | None = None,
+        scalars: bool = False,
+    ) -> TrainData:
+        """Encodes every position of the game. `evals` holds the engine eval after each
+        ply as in `[%eval ...]`, e.g. `"0.35"` or `"#-2"`, or `None`; the WDL of an eval
+        replaces the result in the label of its position. `clocks` holds the seconds left
+        on the mover's clock after each ply, for the scalar features."""
+
+class GameLoader:
+    def __init__(self, file_path: str) -> None: ...
+    def __iter__(self) -> Iterator[Game]: ...
+    def __next__(self) -> Game: ...
+    def read_games(self, max_games: int) -> list[Game]: ...
+    def convert_games(
+        self,
+        max_games: int,
+        encoder: str = "v1",
+        policy: bool = False,
+        dtype: str = "float32",
+        layout: str = "nhwc",
+        sampling: Sampling | None = None,
+        color_flip: bool = False,
+        mirror: bool = False,
+        targets: ValueTargets | None = None,
+        scalars: bool = False,
+        provenance: bool = False,
+    ) -> TrainData | None:
+        """Encodes the next `max_games` games, `None` at the end of the archive. The
+        provenance of positions counts games from the start of the archive."""
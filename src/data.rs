@@ -1,273 +1,606 @@
-use eyre::Result;
-use pyo3::exceptions::PyValueError;
-use pyo3::prelude::*;
-use pyo3::types::{PyBytes, PyBytesMethods};
-use std::path::PathBuf;
-use std::io::{Read, Write};
-
-use super::data;
-use crate::games::game::{Game, Outcome};
+//! Encoding of games into neural network training data.
+//!
+//! Every position after a move is encoded by a [`FeatureEncoder`] and labelled with the
+//! final result of its game by [`encode_outcome`], or a softer [value target](targets),
+//! seen from the side to move if the encoder is [relative](FeatureEncoder::relative), and
+//! optionally with the move played next as a [`Policy`] target. Batches of positions are held in [`TrainData`], which also
+//! defines the on-disk format of training shards.
+
+use eyre::{ensure, eyre, Result, WrapErr};
+use std::path::{Path, PathBuf};
+
+use crate::error::{FenError, FormatError, IoError};
+use crate::games::game::{Annotation, Game, Outcome};
 use crate::games::serialization::Decoder;
-use numpy::ndarray::{array, Array1, Array2, Array3, Array4, Axis};
-use numpy::{PyArray2, PyArray4, PyArrayMethods};
+use crate::games::write_atomically;
+use crate::progress::ProgressTracker;
+use ndarray::{
+    array, Array1, Array2, Array3, Array4, ArrayView1, ArrayView2, ArrayView3, ArrayViewMut2, Axis,
+};
+use policy::{Policy, POLICY_SIZE, POLICY_VOCABULARY};
 use serde::{Deserialize, Serialize};
-use shakmaty::{uci::UciMove, Chess, Color, File, Position, Rank, Role, Square};
+use encoder::{FeatureEncoder, GamePosition};
+use shakmaty::fen::Fen;
+use augment::Augmentation;
+use sampling::Sampling;
+use targets::ValueTargets;
+use tensor::TensorFormat;
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
 use std::sync::{mpsc, Arc, Mutex};
-use std::time::{Duration, Instant};
-use tracing::{info, debug};
-
-pub const FEATURES: usize = 37;
-const F_W_PAWN: usize = 0;
-const F_W_KNIGHT: usize = 1;
-const F_W_BISHOP: usize = 2;
-const F_W_ROOK: usize = 3;
-const F_W_QUEEN: usize = 4;
-const F_W_KING: usize = 5;
-const F_B_PAWN: usize = 6;
-const F_B_KNIGHT: usize = 7;
-const F_B_BISHOP: usize = 8;
-const F_B_ROOK: usize = 9;
-const F_B_QUEEN: usize = 10;
-const F_B_KING: usize = 11;
-const F_TURN: usize = 12;
-const F_ATTACKED_BY_W_PAWN: usize = 13;
-const F_ATTACKED_BY_W_KNIGHT: usize = 14;
-const F_ATTACKED_BY_W_BISHOP: usize = 15;
-const F_ATTACKED_BY_W_ROOK: usize = 16;
-const F_ATTACKED_BY_W_QUEEN: usize = 17;
-const F_ATTACKED_BY_W_KING: usize = 18;
-const F_ATTACKED_BY_B_PAWN: usize = 19;
-const F_ATTACKED_BY_B_KNIGHT: usize = 20;
-const F_ATTACKED_BY_B_BISHOP: usize = 21;
-const F_ATTACKED_BY_B_ROOK: usize = 22;
-const F_ATTACKED_BY_B_QUEEN: usize = 23;
-const F_ATTACKED_BY_B_KING: usize = 24;
-const F_ACCESSIBLE_BY_W_PAWN: usize = 25;
-const F_ACCESSIBLE_BY_W_KNIGHT: usize = 26;
-const F_ACCESSIBLE_BY_W_BISHOP: usize = 27;
-const F_ACCESSIBLE_BY_W_ROOK: usize = 28;
-const F_ACCESSIBLE_BY_W_QUEEN: usize = 29;
-const F_ACCESSIBLE_BY_W_KING: usize = 30;
-const F_ACCESSIBLE_BY_B_PAWN: usize = 31;
-const F_ACCESSIBLE_BY_B_KNIGHT: usize = 32;
-const F_ACCESSIBLE_BY_B_BISHOP: usize = 33;
-const F_ACCESSIBLE_BY_B_ROOK: usize = 34;
-const F_ACCESSIBLE_BY_B_QUEEN: usize = 35;
-const F_ACCESSIBLE_BY_B_KING: usize = 36;
+use std::thread::JoinHandle;
+use tracing::{info, info_span};
 
+pub mod augment;
+pub mod decode;
+pub mod dedup;
+pub mod encoder;
+pub mod policy;
+pub mod sampling;
+pub mod scalars;
+pub mod split;
+pub mod targets;
+pub mod tensor;
+
+/// A single position as `[file][7 - rank][plane]`.
 pub type NNInput = Array3<f32>;
 pub type NNInputBatch = Array4<f32>;
+/// Outcome label as `[white win, draw, black win]`, or as `[win, draw, loss]` of the side
+/// to move for relative encoders.
 pub type NNOutput = Array1<f32>;
 pub type NNOutputBatch = Array2<f32>;
 
-pub fn encode_position(chess: &impl Position) -> NNInput {
-    let mut encoded = Array3::zeros((8, 8, FEATURES));
-    let board = chess.board();
-    let turn_value = match chess.turn() {
-        Color::Black => -1.0,
-        Color::White => 1.0,
-    };
-
-    for move_ in chess.legal_moves() {
-        let piece = board.piece_at(move_.from().unwrap()).unwrap();
-        let filter = match piece.color {
-            Color::White => match piece.role {
-                Role::Pawn => F_ACCESSIBLE_BY_W_PAWN,
-                Role::Knight => F_ACCESSIBLE_BY_W_KNIGHT,
-                Role::Bishop => F_ACCESSIBLE_BY_W_BISHOP,
-                Role::Rook => F_ACCESSIBLE_BY_W_ROOK,
-                Role::Queen => F_ACCESSIBLE_BY_W_QUEEN,
-                Role::King => F_ACCESSIBLE_BY_W_KING,
-            },
-            Color::Black => match piece.role {
-                Role::Pawn => F_ACCESSIBLE_BY_B_PAWN,
-                Role::Knight => F_ACCESSIBLE_BY_B_KNIGHT,
-                Role::Bishop => F_ACCESSIBLE_BY_B_BISHOP,
-                Role::Rook => F_ACCESSIBLE_BY_B_ROOK,
-                Role::Queen => F_ACCESSIBLE_BY_B_QUEEN,
-                Role::King => F_ACCESSIBLE_BY_B_KING,
-            },
-        };
-        let (x, y) = (
-            move_.to().file().into(),
-            7 - Into::<usize>::into(move_.to().rank()),
-        );
-        encoded[[x, y, filter]] = 1.0;
-    }
-
-    for x in 0..8 {
-        for y in 0..8 {
-            encoded[[x, y, F_TURN]] = turn_value;
-            let square = Square::from_coords(File::new(x as u32), Rank::new(7 - (y as u32)));
-            if let Some(piece) = board.piece_at(square) {
-                match piece.color {
-                    Color::White => match piece.role {
-                        Role::Pawn => encoded[[x, y, F_W_PAWN]] = 1.0,
-                        Role::Knight => encoded[[x, y, F_W_KNIGHT]] = 1.0,
-                        Role::Bishop => encoded[[x, y, F_W_BISHOP]] = 1.0,
-                        Role::Rook => encoded[[x, y, F_W_ROOK]] = 1.0,
-                        Role::Queen => encoded[[x, y, F_W_QUEEN]] = 1.0,
-                        Role::King => encoded[[x, y, F_W_KING]] = 1.0,
-                    },
-                    Color::Black => match piece.role {
-                        Role::Pawn => encoded[[x, y, F_B_PAWN]] = 1.0,
-                        Role::Knight => encoded[[x, y, F_B_KNIGHT]] = 1.0,
-                        Role::Bishop => encoded[[x, y, F_B_BISHOP]] = 1.0,
-                        Role::Rook => encoded[[x, y, F_B_ROOK]] = 1.0,
-                        Role::Queen => encoded[[x, y, F_B_QUEEN]] = 1.0,
-                        Role::King => encoded[[x, y, F_B_KING]] = 1.0,
-                    },
-                }
-                let mut attacks_bb = board.attacks_from(square);
-                let attack_filter = match piece.color {
-                    Color::White => match piece.role {
-                        Role::Pawn => F_ATTACKED_BY_W_PAWN,
-                        Role::Knight => F_ATTACKED_BY_W_KNIGHT,
-                        Role::Bishop => F_ATTACKED_BY_W_BISHOP,
-                        Role::Rook => F_ATTACKED_BY_W_ROOK,
-                        Role::Queen => F_ATTACKED_BY_W_QUEEN,
-                        Role::King => F_ATTACKED_BY_W_KING,
-                    },
-                    Color::Black => match piece.role {
-                        Role::Pawn => F_ATTACKED_BY_B_PAWN,
-                        Role::Knight => F_ATTACKED_BY_B_KNIGHT,
-                        Role::Bishop => F_ATTACKED_BY_B_BISHOP,
-                        Role::Rook => F_ATTACKED_BY_B_ROOK,
-                        Role::Queen => F_ATTACKED_BY_B_QUEEN,
-                        Role::King => F_ATTACKED_BY_B_KING,
-                    },
-                };
-                while let Some(sq) = attacks_bb.pop_front() {
-                    let (x, y) = (sq.file().into(), 7 - Into::<usize>::into(sq.rank()));
-                    encoded[[x, y, attack_filter]] = 1.0;
-                }
-            }
+/// One-hot encodes a game result as `[win, draw, loss]` of `perspective`, for white this
+/// is `[white win, draw, black win]`.
+pub fn encode_outcome(outcome: Outcome, perspective: Color) -> NNOutput {
+    match (outcome, perspective) {
+        (Outcome::Draw, _) => array![0.0, 1.0, 0.0],
+        (Outcome::WhiteWin, Color::White) | (Outcome::BlackWin, Color::Black) => {
+            array![1.0, 0.0, 0.0]
+        }
+        (Outcome::WhiteWin, Color::Black) | (Outcome::BlackWin, Color::White) => {
+            array![0.0, 0.0, 1.0]
         }
     }
-    encoded
 }
 
-pub fn encode_outcome(outcome: Outcome) -> NNOutput {
-    match outcome {
-        Outcome::BlackWin => array![0.0, 0.0, 1.0],
-        Outcome::WhiteWin => array![1.0, 0.0, 0.0],
-        Outcome::Draw => array![0.0, 1.0, 0.0],
-    }
-}
+const TRAIN_DATA_MAGIC: [u8; 16] = *b"chesserstraindat";
+const TRAIN_DATA_VERSION: u32 = 1;
+/// Magic of shards written before the header recorded the encoder, all of them `v1`.
+const LEGACY_TRAIN_DATA_MAGIC: [u8; 16] = *b"mychesstraindata";
 
-#[pyclass]
+/// Encoded positions with their outcome labels, row `i` of `ins` belongs to row `i` of `outs`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct TrainData {
-    ins: Py<PyArray4<f32>>,
-    outs: Py<PyArray2<f32>>,
+    pub ins: NNInputBatch,
+    pub outs: NNOutputBatch,
+    /// Name of the [`FeatureEncoder`] of `ins`.
+    pub encoder: String,
+    /// Names of the planes of `ins`.
+    pub plane_names: Vec<String>,
+    /// Number of earlier positions encoded into each position, see
+    /// [`FeatureEncoder::history`].
+    pub history: usize,
+    /// Policy targets, if they were requested with [`EncodeOptions::policy`].
+    pub policy: Option<Policy>,
+    /// Element type and axis order in which `ins` are handed to models.
+    pub format: TensorFormat,
+    /// Weight of each position in the loss, e.g. how often a deduplicated position
+    /// occurred.
+    pub weights: Option<Array1<f32>>,
+    /// Plies left until the end of the game of each position, if they were requested with
+    /// [`ValueTargets::moves_left`].
+    pub moves_left: Option<Array1<f32>>,
+    /// Scalar features of each position, if they were requested with
+    /// [`EncodeOptions::scalars`].
+    pub scalars: Option<Array2<f32>>,
+    /// Names of the columns of `scalars`, empty without them.
+    pub scalar_names: Vec<String>,
+    /// Where each position comes from, if requested with [`EncodeOptions::provenance`].
+    pub provenance: Option<Provenance>,
+}
+
+/// What is encoded besides the positions and their outcomes.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    /// Also encode the move played next and the legal moves of each position.
+    pub policy: bool,
+    /// Element type and axis order of the inputs, recorded with the data.
+    pub format: TensorFormat,
+    /// Which positions of each game are encoded.
+    pub sampling: Sampling,
+    /// Symmetric variants of the positions appended to the encoded ones.
+    pub augmentation: Augmentation,
+    /// How the outcome labels are built.
+    pub targets: ValueTargets,
+    /// Also encode the [scalar features](scalars) of each position.
+    pub scalars: bool,
+    /// Also record the [`Provenance`] of each position, and a weight of 1 for each position
+    /// unless it has one.
+    pub provenance: bool,
+}
+
+/// The encoded positions of one game, see [`encode_game_positions`].
+#[derive(Debug, Clone)]
+pub struct EncodedGame {
+    pub ins: Vec<NNInput>,
+    pub outs: Vec<NNOutput>,
+    pub policy: Option<Policy>,
+    /// Zobrist hash of each position, as given by shakmaty.
+    pub hashes: Vec<u64>,
+    pub moves_left: Option<Array1<f32>>,
+    pub scalars: Option<Array2<f32>>,
+    /// Number of moves played before each position.
+    pub plies: Vec<usize>,
+}
+
+/// Where the positions of a batch come from, row `i` belongs to position `i`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// Ordinal of the game of each position in its `.bin` archive, counted from 0.
+    pub games: Array1<u64>,
+    /// Number of moves played in the game before each position.
+    pub plies: Array1<u32>,
+    /// Zobrist hash of each position as given by shakmaty. Augmented positions keep the
+    /// hash of the position they are a variant of.
+    pub hashes: Array1<u64>,
+}
+
+impl Provenance {
+    pub fn empty() -> Self {
+        Self {
+            games: Array1::zeros(0),
+            plies: Array1::zeros(0),
+            hashes: Array1::zeros(0),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 struct TrainDataFileHeader {
     magic: [u8; 16],
+    version: u32,
+    encoder: String,
+    plane_names: Vec<String>,
+    history: usize,
+    /// How each plane is stored, `None` for the dense floats of legacy shards.
+    packing: Option<Vec<PlaneStorage>>,
+    format: TensorFormat,
     ins_shape: [usize; 4],
     outs_shape: [usize; 2],
+    /// Optional arrays stored after the outcomes, in the order [`Section`] declares them.
+    /// New kinds of data are added as sections rather than by a new header version.
+    sections: Vec<Section>,
 }
 
-impl TrainData {
-    pub fn from_games(games: Vec<Vec<u8>>) -> (NNInputBatch, NNOutputBatch) {
-        let num_games = games.len();
-        let games = Arc::new(Mutex::new(games));
-        let mut inputs = Array4::zeros((0, 8, 8, data::FEATURES));
-        let mut outputs = Array2::zeros((0, 3));
-        std::thread::scope(|scope| {
-            let (tx, rx) = mpsc::channel();
-            for _ in 0..std::thread::available_parallelism()
-                .map(|n| n.get())
-                .unwrap_or(1)
-            {
-                let games = Arc::clone(&games);
-                let tx = tx.clone();
-                scope.spawn(move || 'outer: loop {
-                    let work = games.lock().unwrap().pop();
-                    match work {
-                        Some(game) => {
-                            let encoded = encode_game_positions(game);
-                            tx.send(encoded).unwrap();
-                        }
-                        None => {
-                            break 'outer;
-                        }
-                    }
+/// An optional array of a shard, stored after the outcomes if its header lists it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum Section {
+    /// Policy targets with moves indexed by the given vocabulary.
+    Policy(String),
+    /// A weight per position.
+    Weights,
+    /// The plies left per position.
+    MovesLeft,
+    /// The scalar features of the given names per position.
+    Scalars(Vec<String>),
+    /// The game ordinal, ply and hash of each position.
+    Provenance,
+}
+
+/// Storage of one plane of every position in a shard.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum PlaneStorage {
+    /// Only zeros and ones, one `u64` with bit `file + 8 * rank` per square.
+    Bits,
+    /// The same value on every square, one `f32`.
+    Scalar,
+    /// 64 `f32` in `[file][7 - rank]` order.
+    Dense,
+}
+
+impl PlaneStorage {
+    /// The most compact storage of one plane of many positions, `[position][x][y]`.
+    fn of(planes: ArrayView3<'_, f32>) -> Self {
+        if planes.iter().all(|&v| v == 0.0 || v == 1.0) {
+            PlaneStorage::Bits
+        } else if planes
+            .outer_iter()
+            .all(|board| board.iter().all(|&v| v == board[[0, 0]]))
+        {
+            PlaneStorage::Scalar
+        } else {
+            PlaneStorage::Dense
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            PlaneStorage::Bits => std::mem::size_of::<u64>(),
+            PlaneStorage::Scalar => std::mem::size_of::<f32>(),
+            PlaneStorage::Dense => 64 * std::mem::size_of::<f32>(),
+        }
+    }
+
+    /// Appends `plane` stored this way to `out`.
+    fn write(self, plane: ArrayView2<'_, f32>, out: &mut Vec<u8>) {
+        match self {
+            PlaneStorage::Bits => {
+                let bits = plane.indexed_iter().fold(0u64, |bits, ((x, y), &v)| {
+                    bits | (u64::from(v != 0.0) << (x + 8 * (7 - y)))
                 });
+                out.extend_from_slice(&bits.to_le_bytes());
             }
-            for _ in 0..num_games {
-                let (batch, outcome) = rx.recv().unwrap();
-                for pos in batch {
-                    inputs.push(Axis(0), pos.view()).unwrap();
-                    outputs.push(Axis(0), outcome.view()).unwrap();
+            PlaneStorage::Scalar => out.extend_from_slice(&plane[[0, 0]].to_le_bytes()),
+            PlaneStorage::Dense => {
+                for &f in plane.iter() {
+                    out.extend_from_slice(&f.to_le_bytes());
                 }
             }
-        });
+        }
+    }
+
+    /// Reads a plane stored this way from the start of `data`, which has to hold it, and
+    /// returns the rest.
+    fn read<'a>(self, data: &'a [u8], mut plane: ArrayViewMut2<'_, f32>) -> &'a [u8] {
+        let (bytes, rest) = data.split_at(self.bytes());
+        match self {
+            PlaneStorage::Bits => {
+                let bits = u64::from_le_bytes(bytes.try_into().unwrap());
+                for ((x, y), v) in plane.indexed_iter_mut() {
+                    *v = ((bits >> (x + 8 * (7 - y))) & 1) as f32;
+                }
+            }
+            PlaneStorage::Scalar => plane.fill(f32::from_le_bytes(bytes.try_into().unwrap())),
+            PlaneStorage::Dense => {
+                for (v, f) in plane.iter_mut().zip(TrainData::bytes_to_floats(bytes)) {
+                    *v = f;
+                }
+            }
+        }
+        rest
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyTrainDataFileHeader {
+    magic: [u8; 16],
+    ins_shape: [usize; 4],
+    outs_shape: [usize; 2],
+}
+
+impl TrainDataFileHeader {
+    fn decode(data: &[u8]) -> Result<Self> {
+        let invalid = |e: bincode::Error| FormatError::new(format!("Invalid train data header: {}", e));
+        let magic = bincode::deserialize::<[u8; 16]>(data).map_err(invalid)?;
+        if magic == LEGACY_TRAIN_DATA_MAGIC {
+            let legacy = bincode::deserialize::<LegacyTrainDataFileHeader>(data).map_err(invalid)?;
+            return Ok(Self {
+                magic: TRAIN_DATA_MAGIC,
+                version: TRAIN_DATA_VERSION,
+                encoder: encoder::V1Encoder.name().to_string(),
+                plane_names: encoder::V1Encoder.plane_names(),
+                history: 0,
+                packing: None,
+                format: TensorFormat::default(),
+                ins_shape: legacy.ins_shape,
+                outs_shape: legacy.outs_shape,
+                sections: Vec::new(),
+            });
+        }
+        ensure!(magic == TRAIN_DATA_MAGIC, FormatError::new("File format corrupted"));
+        let (_, version) = bincode::deserialize::<([u8; 16], u32)>(data).map_err(invalid)?;
+        ensure!(
+            version == TRAIN_DATA_VERSION,
+            FormatError::new(format!("Unsupported train data version {}", version))
+        );
+        Ok(bincode::deserialize::<Self>(data).map_err(invalid)?)
+    }
+}
+
+impl TrainData {
+    /// Encodes the positions of bincode serialized games, as returned by
+    /// [`Decoder::read_game_raw`], on all available cores. `first_game` is the ordinal of
+    /// the first of `games` in their archive, recorded in the [`Provenance`].
+    pub fn from_games(
+        games: Vec<Vec<u8>>,
+        first_game: u64,
+        encoder: &dyn FeatureEncoder,
+        options: &EncodeOptions,
+    ) -> Result<Self> {
+        let games = (first_game..).zip(games).collect();
+        Self::from_numbered_games(games, encoder, options)
+    }
+
+    /// Like [`TrainData::from_games`], with the ordinal of each game in its archive.
+    pub fn from_numbered_games(
+        games: Vec<(u64, Vec<u8>)>,
+        encoder: &dyn FeatureEncoder,
+        options: &EncodeOptions,
+    ) -> Result<Self> {
+        let num_games = games.len();
+        let _span = info_span!("from_games", games = num_games).entered();
+        let (ordinals, games): (Vec<_>, Vec<_>) = games.into_iter().unzip();
+        let mut data = Self::empty(encoder, options);
+        encode_games(games, encoder, options, |index, encoded| {
+            data.push_game(ordinals[index], encoded);
+            Ok(())
+        })?;
         info!(
             "Processed {} games into {} positions",
             num_games,
-            inputs.shape()[0]
+            data.len()
         );
 
-        let x_bytes = inputs.len() * std::mem::size_of::<f32>();
-        let y_bytes = outputs.len() * std::mem::size_of::<f32>();
+        let x_bytes = data.ins.len() * std::mem::size_of::<f32>();
+        let y_bytes = data.outs.len() * std::mem::size_of::<f32>();
+        let policy_bytes = data
+            .policy
+            .as_ref()
+            .map_or(0, |p| p.moves.len() * std::mem::size_of::<i32>() + p.legal.len());
 
         info!(
             "Total memory of encoded positions: {:.2} Mb",
-            (x_bytes + y_bytes) as f64 / (1024.0 * 1024.0)
+            (x_bytes + y_bytes + policy_bytes) as f64 / (1024.0 * 1024.0)
         );
+        data.finish(options)
+    }
 
-        (inputs, outputs)
+    /// Encodes the positions of a single game, with the evals and clocks of its plies if
+    /// known, see [`encode_annotated_game_positions`].
+    pub fn from_game(
+        game: &Game,
+        annotations: &[Annotation],
+        encoder: &dyn FeatureEncoder,
+        options: &EncodeOptions,
+    ) -> Result<Self> {
+        let mut data = Self::empty(encoder, options);
+        let encoded = encode_annotated_game_positions(game, annotations, encoder, options)?;
+        data.push_game(0, encoded);
+        data.finish(options)
     }
 
-    pub fn from_games_py(py: Python<'_>, games: Vec<Vec<u8>>) -> Self {
-        let (ins, outs) = Self::from_games(games);
-        let ins = PyArray4::from_owned_array_bound(py, ins).unbind();
-        let outs = PyArray2::from_owned_array_bound(py, outs).unbind();
+    /// No positions yet, with the arrays `options` asks for.
+    fn empty(encoder: &dyn FeatureEncoder, options: &EncodeOptions) -> Self {
+        Self {
+            ins: Array4::zeros((0, 8, 8, encoder.planes())),
+            outs: Array2::zeros((0, 3)),
+            encoder: encoder.name().to_string(),
+            plane_names: encoder.plane_names(),
+            history: encoder.history(),
+            policy: options.policy.then(Policy::empty),
+            format: options.format,
+            weights: None,
+            moves_left: options.targets.moves_left.then(|| Array1::zeros(0)),
+            scalars: options
+                .scalars
+                .then(|| Array2::zeros((0, scalars::SCALAR_NAMES.len()))),
+            scalar_names: if options.scalars {
+                scalars::scalar_names(encoder.relative())
+            } else {
+                Vec::new()
+            },
+            provenance: options.provenance.then(Provenance::empty),
+        }
+    }
 
-        TrainData { ins, outs }
+    /// Appends the positions of the game with ordinal `game`.
+    fn push_game(&mut self, game: u64, encoded: EncodedGame) {
+        for (pos, outcome) in encoded.ins.iter().zip(&encoded.outs) {
+            self.ins.push(Axis(0), pos.view()).unwrap();
+            self.outs.push(Axis(0), outcome.view()).unwrap();
+        }
+        if let (Some(policy), Some(encoded)) = (self.policy.as_mut(), encoded.policy) {
+            policy.moves.append(Axis(0), encoded.moves.view()).unwrap();
+            policy.legal.append(Axis(0), encoded.legal.view()).unwrap();
+        }
+        if let (Some(moves_left), Some(encoded)) = (self.moves_left.as_mut(), encoded.moves_left) {
+            moves_left.append(Axis(0), encoded.view()).unwrap();
+        }
+        if let (Some(scalars), Some(encoded)) = (self.scalars.as_mut(), encoded.scalars) {
+            scalars.append(Axis(0), encoded.view()).unwrap();
+        }
+        if let Some(provenance) = self.provenance.as_mut() {
+            let positions = encoded.plies.len();
+            let plies = encoded.plies.iter().map(|&ply| ply as u32);
+            provenance.games.append(Axis(0), Array1::from_elem(positions, game).view()).unwrap();
+            provenance.plies.append(Axis(0), plies.collect::<Array1<_>>().view()).unwrap();
+            provenance.hashes.append(Axis(0), ArrayView1::from(&encoded.hashes)).unwrap();
+        }
     }
 
-    fn compress(data: &[u8]) -> Vec<u8> {
-        let cursor = std::io::Cursor::new(Vec::new());
-        let mut encoder = lz4_flex::frame::FrameEncoder::new(cursor);
-        encoder.write_all(data).unwrap();
-        encoder.finish().unwrap().into_inner()
+    /// Checks that the inputs fit their format, gives positions with provenance their
+    /// weight and appends the augmented positions.
+    fn finish(mut self, options: &EncodeOptions) -> Result<Self> {
+        options.format.check(&self.ins, &self.plane_names)?;
+        if self.provenance.is_some() && self.weights.is_none() {
+            self.weights = Some(Array1::ones(self.len()));
+        }
+        if options.augmentation.is_none() {
+            return Ok(self);
+        }
+        augment::expand(self, &options.augmentation)
     }
 
-    fn decompress(data: &[u8]) -> Vec<u8> {
-        let mut decoder = lz4_flex::frame::FrameDecoder::new(data);
-        let mut buf = Vec::new();
-        decoder.read_to_end(&mut buf).unwrap();
-        buf
+    /// Number of positions.
+    pub fn len(&self) -> usize {
+        self.ins.shape()[0]
     }
 
-    pub fn encode_bin(ins: NNInputBatch, outs: NNOutputBatch) -> Vec<u8> {
-        assert!(ins.shape().len() == 4);
-        assert!(outs.shape().len() == 2);
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
+    /// The positions at `rows`, in their order.
+    pub fn select(&self, rows: &[usize]) -> Self {
+        Self {
+            ins: self.ins.select(Axis(0), rows),
+            outs: self.outs.select(Axis(0), rows),
+            encoder: self.encoder.clone(),
+            plane_names: self.plane_names.clone(),
+            history: self.history,
+            policy: self.policy.as_ref().map(|p| Policy {
+                moves: p.moves.select(Axis(0), rows),
+                legal: p.legal.select(Axis(0), rows),
+            }),
+            format: self.format,
+            weights: self.weights.as_ref().map(|w| w.select(Axis(0), rows)),
+            moves_left: self.moves_left.as_ref().map(|m| m.select(Axis(0), rows)),
+            scalars: self.scalars.as_ref().map(|s| s.select(Axis(0), rows)),
+            scalar_names: self.scalar_names.clone(),
+            provenance: self.provenance.as_ref().map(|p| Provenance {
+                games: p.games.select(Axis(0), rows),
+                plies: p.plies.select(Axis(0), rows),
+                hashes: p.hashes.select(Axis(0), rows),
+            }),
+        }
+    }
+
+    /// Appends the positions of `other`, which has to be encoded the same way and hold the
+    /// same arrays.
+    pub fn append(&mut self, other: &Self) -> Result<()> {
+        ensure!(
+            self.encoder == other.encoder
+                && self.plane_names == other.plane_names
+                && self.history == other.history
+                && self.format == other.format
+                && self.scalar_names == other.scalar_names,
+            "Cannot append positions encoded by {} to positions encoded by {}",
+            other.encoder,
+            self.encoder
+        );
+        let missing = |name: &str| eyre!("Only one of the train data has {}", name);
+        self.ins.append(Axis(0), other.ins.view())?;
+        self.outs.append(Axis(0), other.outs.view())?;
+        match (self.policy.as_mut(), &other.policy) {
+            (Some(policy), Some(other)) => {
+                policy.moves.append(Axis(0), other.moves.view())?;
+                policy.legal.append(Axis(0), other.legal.view())?;
+            }
+            (None, None) => {}
+            _ => return Err(missing("policy targets")),
+        }
+        let floats = [
+            ("weights", self.weights.as_mut(), &other.weights),
+            ("plies left", self.moves_left.as_mut(), &other.moves_left),
+        ];
+        for (name, floats, other) in floats {
+            match (floats, other) {
+                (Some(floats), Some(other)) => floats.append(Axis(0), other.view())?,
+                (None, None) => {}
+                _ => return Err(missing(name)),
+            }
+        }
+        match (self.scalars.as_mut(), &other.scalars) {
+            (Some(scalars), Some(other)) => scalars.append(Axis(0), other.view())?,
+            (None, None) => {}
+            _ => return Err(missing("scalar features")),
+        }
+        match (self.provenance.as_mut(), &other.provenance) {
+            (Some(provenance), Some(other)) => {
+                provenance.games.append(Axis(0), other.games.view())?;
+                provenance.plies.append(Axis(0), other.plies.view())?;
+                provenance.hashes.append(Axis(0), other.hashes.view())?;
+            }
+            (None, None) => {}
+            _ => return Err(missing("provenance")),
+        }
+        Ok(())
+    }
+
+    /// The inputs in their [`TensorFormat`].
+    pub fn formatted_ins(&self) -> Result<tensor::InputTensor> {
+        self.format.apply(&self.ins, &self.plane_names)
+    }
+
+    /// Serializes into the shard file format: the length of a bincode header, the header,
+    /// the planes of each position and then the little endian floats of `outs`. Each plane
+    /// is stored as the header's `packing` says, planes of zeros and ones as a `u64`
+    /// bitboard, constant planes as one float and any others as 64 floats. Policy
+    /// targets follow as little endian `i32` move indices and the legal move masks packed
+    /// into bits, least significant bit first, then sample weights, plies left and the
+    /// scalar features of each position as little endian floats. Provenance comes last, the
+    /// game ordinals as `u64`, the plies as `u32` and the hashes as `u64`.
+    pub fn encode_bin(&self) -> Vec<u8> {
+        let packing: Vec<_> = self
+            .ins
+            .axis_iter(Axis(3))
+            .map(PlaneStorage::of)
+            .collect();
         let header = TrainDataFileHeader {
-            magic: *b"mychesstraindata",
-            ins_shape: ins.shape().try_into().unwrap(),
-            outs_shape: outs.shape().try_into().unwrap(),
+            magic: TRAIN_DATA_MAGIC,
+            version: TRAIN_DATA_VERSION,
+            encoder: self.encoder.clone(),
+            plane_names: self.plane_names.clone(),
+            history: self.history,
+            packing: Some(packing.clone()),
+            format: self.format,
+            ins_shape: self.ins.dim().into(),
+            outs_shape: self.outs.dim().into(),
+            sections: [
+                self.policy
+                    .as_ref()
+                    .map(|_| Section::Policy(POLICY_VOCABULARY.to_string())),
+                self.weights.as_ref().map(|_| Section::Weights),
+                self.moves_left.as_ref().map(|_| Section::MovesLeft),
+                self.scalars
+                    .as_ref()
+                    .map(|_| Section::Scalars(self.scalar_names.clone())),
+                self.provenance.as_ref().map(|_| Section::Provenance),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
         };
         let mut header = bincode::serialize(&header).unwrap();
 
-        let ins = ins.as_slice().unwrap();
-        let outs = outs.as_slice().unwrap();
-
+        let position_bytes = packing.iter().map(|s| s.bytes()).sum::<usize>();
         let mut data = Vec::with_capacity(
-            ins.len() * std::mem::size_of::<f32>() + outs.len() * std::mem::size_of::<f32>(),
+            self.len() * position_bytes + self.outs.len() * std::mem::size_of::<f32>(),
         );
-        for &f in ins {
-            data.extend_from_slice(&f.to_le_bytes());
+        for position in self.ins.outer_iter() {
+            for (plane, storage) in position.axis_iter(Axis(2)).zip(&packing) {
+                storage.write(plane, &mut data);
+            }
         }
 
-        for &f in outs {
+        for &f in self.outs.iter() {
             data.extend_from_slice(&f.to_le_bytes());
         }
 
-        //let mut data = Self::compress(&data);
+        if let Some(policy) = &self.policy {
+            for &m in policy.moves.iter() {
+                data.extend_from_slice(&m.to_le_bytes());
+            }
+            let legal = policy.legal.iter().copied().collect::<Vec<_>>();
+            data.extend(legal.chunks(8).map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &bit)| byte | (u8::from(bit) << i))
+            }));
+        }
+
+        for floats in [&self.weights, &self.moves_left].into_iter().flatten() {
+            for &f in floats.iter() {
+                data.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        if let Some(scalars) = &self.scalars {
+            for &f in scalars.iter() {
+                data.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        if let Some(provenance) = &self.provenance {
+            for &game in provenance.games.iter() {
+                data.extend_from_slice(&game.to_le_bytes());
+            }
+            for &ply in provenance.plies.iter() {
+                data.extend_from_slice(&ply.to_le_bytes());
+            }
+            for &hash in provenance.hashes.iter() {
+                data.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
 
         let mut encoded= header.len().to_le_bytes().to_vec();
         encoded.append(&mut header);
@@ -276,14 +609,21 @@ impl TrainData {
         encoded
     }
 
-    pub fn encode_bin_py(&self, py: Python<'_>) -> Vec<u8> {
-        let ins = self.ins.bind(py).readonly().as_array().to_owned();
-        let outs = self.outs.bind(py).readonly().as_array().to_owned();
-        Self::encode_bin(ins, outs)
+    /// Unpacks positions stored plane by plane as given by `packing`, `data` has to be
+    /// of the size they take.
+    fn unpack_planes(data: &[u8], shape: [usize; 4], packing: &[PlaneStorage]) -> NNInputBatch {
+        let mut ins = NNInputBatch::zeros(shape);
+        let mut data = data;
+        for mut position in ins.outer_iter_mut() {
+            for (plane, storage) in position.axis_iter_mut(Axis(2)).zip(packing) {
+                data = storage.read(data, plane);
+            }
+        }
+        ins
     }
 
     fn bytes_to_floats(data: &[u8]) -> Vec<f32> {
-        assert!(data.len() % std::mem::size_of::<f32>() == 0);
+        assert!(data.len().is_multiple_of(std::mem::size_of::<f32>()));
         data.chunks_exact(std::mem::size_of::<f32>())
             .map(TryInto::<[u8; 4]>::try_into)
             .map(Result::unwrap)
@@ -291,217 +631,787 @@ impl TrainData {
             .collect()
     }
 
-    pub fn decode_bin(data: &[u8]) -> (NNInputBatch, NNOutputBatch) {
+    /// Deserializes the shard file format written by [`TrainData::encode_bin`].
+    pub fn decode_bin(data: &[u8]) -> Result<Self> {
         let header_offset = std::mem::size_of::<usize>();
+        ensure!(
+            data.len() >= header_offset,
+            FormatError::new("Train data is truncated")
+        );
         let header_size = usize::from_le_bytes(data[..header_offset].try_into().unwrap());
-        let data_offset = header_offset + header_size;
-        let header =
-            bincode::deserialize::<TrainDataFileHeader>(&data[header_offset..data_offset]).unwrap();
+        let data_offset = header_offset
+            .checked_add(header_size)
+            .filter(|&offset| offset <= data.len())
+            .ok_or_else(|| FormatError::new("Train data header is truncated"))?;
+        let header = TrainDataFileHeader::decode(&data[header_offset..data_offset])?;
         let data = &data[data_offset..];
-        //let data = Self::decompress(data);
 
-        assert!(header.magic == *b"mychesstraindata");
-        assert_eq!(header.ins_shape[1], 8);
-        assert_eq!(header.ins_shape[2], 8);
-        assert_eq!(header.ins_shape[3], data::FEATURES);
-        assert_eq!(header.outs_shape[1], 3);
+        ensure!(
+            header.ins_shape[1..] == [8, 8, header.plane_names.len()],
+            FormatError::new(format!("Unexpected input shape {:?}", header.ins_shape))
+        );
+        ensure!(
+            header.outs_shape == [header.ins_shape[0], 3],
+            FormatError::new(format!("Unexpected output shape {:?}", header.outs_shape))
+        );
 
-        let ins_bytes = header.ins_shape.iter().product::<usize>() * std::mem::size_of::<f32>();
-        let outs_bytes = header.outs_shape.iter().product::<usize>() * std::mem::size_of::<f32>();
+        let (mut policy, mut weights, mut moves_left) = (false, false, false);
+        let (mut scalar_names, mut provenance) = (None, false);
+        for section in &header.sections {
+            let duplicate = match section {
+                Section::Policy(vocabulary) => {
+                    ensure!(
+                        vocabulary == POLICY_VOCABULARY,
+                        FormatError::new(format!("Unknown move vocabulary {:?}", vocabulary))
+                    );
+                    std::mem::replace(&mut policy, true)
+                }
+                Section::Weights => std::mem::replace(&mut weights, true),
+                Section::MovesLeft => std::mem::replace(&mut moves_left, true),
+                Section::Scalars(names) => scalar_names.replace(names.clone()).is_some(),
+                Section::Provenance => std::mem::replace(&mut provenance, true),
+            };
+            ensure!(
+                !duplicate,
+                FormatError::new(format!("Train data header lists {:?} twice", section))
+            );
+        }
 
-        assert_eq!(data.len(), ins_bytes + outs_bytes);
+        let positions = header.ins_shape[0];
+        if let Some(packing) = &header.packing {
+            ensure!(
+                packing.len() == header.plane_names.len(),
+                FormatError::new(format!(
+                    "Expected the storage of {} planes, got {}",
+                    header.plane_names.len(),
+                    packing.len()
+                ))
+            );
+        }
+        // Sizes come from the header, a corrupted one must not overflow them
+        let overflow = || FormatError::new("Train data header gives sizes beyond memory");
+        let product = |factors: &[usize]| {
+            factors
+                .iter()
+                .try_fold(1usize, |product, &factor| product.checked_mul(factor))
+                .ok_or_else(overflow)
+        };
+        let position_bytes = match &header.packing {
+            Some(packing) => packing.iter().map(|s| s.bytes()).sum::<usize>(),
+            None => product(&[64, header.plane_names.len(), std::mem::size_of::<f32>()])?,
+        };
+        let ins_bytes = product(&[positions, position_bytes])?;
+        let outs_bytes = product(&[positions, 3, std::mem::size_of::<f32>()])?;
+        let moves_bytes = product(&[positions, std::mem::size_of::<i32>()])?;
+        let legal_bytes = product(&[positions, POLICY_SIZE])?.div_ceil(8);
+        let policy_bytes = if policy {
+            moves_bytes.checked_add(legal_bytes).ok_or_else(overflow)?
+        } else {
+            0
+        };
+        let floats_bytes = |present: bool| {
+            if present {
+                product(&[positions, std::mem::size_of::<f32>()])
+            } else {
+                Ok(0)
+            }
+        };
+        let weights_bytes = floats_bytes(weights)?;
+        let moves_left_bytes = floats_bytes(moves_left)?;
+        let scalar_count = scalar_names.as_ref().map_or(0, |names| names.len());
+        let scalars_bytes = product(&[scalar_count, positions, std::mem::size_of::<f32>()])?;
+        let provenance_bytes = if provenance {
+            let row_bytes = 2 * std::mem::size_of::<u64>() + std::mem::size_of::<u32>();
+            product(&[positions, row_bytes])?
+        } else {
+            0
+        };
+        let expected = [
+            ins_bytes,
+            outs_bytes,
+            policy_bytes,
+            weights_bytes,
+            moves_left_bytes,
+            scalars_bytes,
+            provenance_bytes,
+        ]
+        .into_iter()
+        .try_fold(0usize, |total, bytes| total.checked_add(bytes))
+        .ok_or_else(overflow)?;
 
-        let ins = Self::bytes_to_floats(&data[..ins_bytes]);
-        let ins = Array4::from_shape_vec(header.ins_shape, ins).unwrap();
+        ensure!(
+            data.len() == expected,
+            FormatError::new(format!(
+                "Expected {} bytes of train data, got {}",
+                expected,
+                data.len()
+            ))
+        );
 
-        let outs = Self::bytes_to_floats(&data[ins_bytes..]);
-        let outs = Array2::from_shape_vec(header.outs_shape, outs).unwrap();
-        (ins, outs)
-    }
+        let ins = match &header.packing {
+            Some(packing) => Self::unpack_planes(&data[..ins_bytes], header.ins_shape, packing),
+            // Legacy shards store the floats of the whole array in its order
+            None => {
+                let ins = Self::bytes_to_floats(&data[..ins_bytes]);
+                Array4::from_shape_vec(header.ins_shape, ins)?
+            }
+        };
 
-    pub fn decode_bin_py(py: Python<'_>, data: &[u8]) -> Self {
-        let (ins, outs) = Self::decode_bin(data);
-        let ins = PyArray4::from_owned_array_bound(py, ins).unbind();
-        let outs = PyArray2::from_owned_array_bound(py, outs).unbind();
+        let outs = Self::bytes_to_floats(&data[ins_bytes..ins_bytes + outs_bytes]);
+        let outs = Array2::from_shape_vec(header.outs_shape, outs)?;
 
-        TrainData { ins, outs }
+        let policy = policy.then(|| {
+            let data = &data[ins_bytes + outs_bytes..];
+            let moves = data[..moves_bytes]
+                .chunks_exact(std::mem::size_of::<i32>())
+                .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+                .collect::<Array1<_>>();
+            let legal = data[moves_bytes..moves_bytes + legal_bytes]
+                .iter()
+                .flat_map(|&byte| (0..8).map(move |i| byte & (1 << i) != 0))
+                .take(positions * POLICY_SIZE)
+                .collect::<Vec<_>>();
+            let legal = Array2::from_shape_vec((positions, POLICY_SIZE), legal).unwrap();
+            Policy { moves, legal }
+        });
+        let weights_offset = ins_bytes + outs_bytes + policy_bytes;
+        let weights = weights.then(|| {
+            let data = &data[weights_offset..weights_offset + weights_bytes];
+            Array1::from(Self::bytes_to_floats(data))
+        });
+        let moves_left_offset = weights_offset + weights_bytes;
+        let moves_left = moves_left.then(|| {
+            let data = &data[moves_left_offset..moves_left_offset + moves_left_bytes];
+            Array1::from(Self::bytes_to_floats(data))
+        });
+        let scalars_offset = moves_left_offset + moves_left_bytes;
+        let scalars = match &scalar_names {
+            Some(_) => {
+                let data = &data[scalars_offset..scalars_offset + scalars_bytes];
+                let floats = Self::bytes_to_floats(data);
+                Some(Array2::from_shape_vec((positions, scalar_count), floats)?)
+            }
+            None => None,
+        };
+        let provenance = provenance.then(|| {
+            let data = &data[scalars_offset + scalars_bytes..];
+            let (games, data) = data.split_at(positions * std::mem::size_of::<u64>());
+            let (plies, hashes) = data.split_at(positions * std::mem::size_of::<u32>());
+            Provenance {
+                games: games
+                    .chunks_exact(std::mem::size_of::<u64>())
+                    .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+                plies: plies
+                    .chunks_exact(std::mem::size_of::<u32>())
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+                hashes: hashes
+                    .chunks_exact(std::mem::size_of::<u64>())
+                    .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            }
+        });
+        Ok(Self {
+            ins,
+            outs,
+            encoder: header.encoder,
+            plane_names: header.plane_names,
+            history: header.history,
+            policy,
+            format: header.format,
+            weights,
+            moves_left,
+            scalars,
+            scalar_names: scalar_names.unwrap_or_default(),
+            provenance,
+        })
     }
-}
 
-#[pymethods]
-impl TrainData {
-    fn get_ins(slf: PyRef<'_, Self>) -> Bound<'_, PyArray4<f32>> {
-        let ins = slf.ins.clone_ref(slf.py());
-        ins.into_bound(slf.py())
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, |part| {
+            std::fs::write(part, self.encode_bin()).map_err(|e| IoError::new(path, e).into())
+        })
     }
 
-    fn get_outs(slf: PyRef<'_, Self>) -> Bound<'_, PyArray2<f32>> {
-        let outs = slf.outs.clone_ref(slf.py());
-        outs.into_bound(slf.py())
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).map_err(|e| IoError::new(path, e))?;
+        Self::decode_bin(&data).wrap_err_with(|| format!("failed to decode {}", path.display()))
     }
+}
 
-    fn to_bytes(slf: PyRef<'_, Self>) -> PyResult<Bound<'_, PyBytes>> {
-        let data = slf.encode_bin_py(slf.py());
-        PyBytes::new_bound_with(slf.py(), data.len(), |buf| {
-            buf.copy_from_slice(&data);
-            Ok(())
-        })
-    }
+/// Encodes the games of a `.bin` archive into [`TrainData`] shards of at most `max_games`
+/// games each, saved as `{out_dir}/000.bin`, `{out_dir}/001.bin`, ..
+///
+/// Shards are written whole, when the operation fails or is cancelled the shards saved
+/// until then stay valid. Returns the number of shards written.
+pub fn convert_games_and_save(
+    path: &Path,
+    max_games: usize,
+    out_dir: &Path,
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<usize> {
+    let _span = info_span!("convert_games_and_save", file = %path.display()).entered();
+    let out_dirs = [out_dir.to_path_buf()];
+    let shards = convert_games_into_shards(
+        path,
+        max_games,
+        &out_dirs,
+        encoder,
+        options,
+        tracker,
+        |_| Ok(Some(0)),
+    )?;
+    Ok(shards[0])
+}
+
+/// Encodes the games of a `.bin` archive into sets of shards in one pass, like
+/// [`convert_games_and_save`]. `assign` picks the set of each bincode serialized game, an
+/// index into `out_dirs`, or rejects it with `None`. The shards of set `i` are saved as
+/// `{out_dirs[i]}/000.bin`, .. Returns the number of shards written per set.
+pub(crate) fn convert_games_into_shards(
+    path: &Path,
+    max_games: usize,
+    out_dirs: &[PathBuf],
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+    tracker: &mut ProgressTracker<'_>,
+    mut assign: impl FnMut(&[u8]) -> Result<Option<usize>>,
+) -> Result<Vec<usize>> {
+    ensure!(max_games > 0, "Shards need to hold at least one game");
+    let mut decoder = Decoder::open(path).wrap_err("failed to open source bin file")?;
+    tracker.set_bytes_total(std::fs::metadata(path).ok().map(|m| m.len()));
+    let mut handles: Vec<Vec<JoinHandle<Result<()>>>> =
+        out_dirs.iter().map(|_| Vec::new()).collect();
+    let mut pending: Vec<Vec<(u64, Vec<u8>)>> = out_dirs.iter().map(|_| Vec::new()).collect();
+    let mut games_read = 0;
+    let result = (|| -> Result<()> {
+        loop {
+            let game = decoder.read_game_raw()?;
+            let done = game.is_none();
+            if let Some(game) = game {
+                match assign(&game)? {
+                    Some(set) => pending[set].push((games_read, game)),
+                    None => {
+                        let bytes = decoder.bytes_read();
+                        tracker.update(|p| {
+                            p.bytes_processed = bytes;
+                            p.games_rejected += 1;
+                        })?;
+                    }
+                }
+                games_read += 1;
+            }
+            for (set, games) in pending.iter_mut().enumerate() {
+                if games.len() < max_games && (!done || games.is_empty()) {
+                    continue;
+                }
+                let games = std::mem::take(games);
+                let num_games = games.len();
+                let data = TrainData::from_numbered_games(games, encoder, options)?;
+                let positions = data.len();
+                let path = out_dirs[set].join(format!("{:03}.bin", handles[set].len()));
+                handles[set].push(std::thread::spawn(move || data.save(&path)));
 
-    #[staticmethod]
-    fn from_bytes(py: Python<'_>, data: &Bound<'_, PyBytes>) -> Self {
-        Self::decode_bin_py(py, data.as_bytes())
+                let bytes = decoder.bytes_read();
+                tracker.update(|p| {
+                    p.bytes_processed = bytes;
+                    p.games_accepted += num_games;
+                    p.positions += positions;
+                })?;
+            }
+            if done {
+                return Ok(());
+            }
+        }
+    })();
+    info!("Waiting for files to finish saving");
+    let shards = handles.iter().map(Vec::len).collect();
+    for h in handles.into_iter().flatten() {
+        h.join().unwrap()?;
     }
+    result?;
+    tracker.finish()?;
+    Ok(shards)
+}
 
-    fn save(slf: PyRef<'_, Self>, path: &str) -> PyResult<()> {
-        let path = PathBuf::from(path);
-        std::fs::write(path, slf.encode_bin_py(slf.py()))
-            .map_err(|e| PyValueError::new_err(format!("{:#?}", e)))?;
+/// Encodes bincode serialized games on all available cores, handing each encoded game to
+/// `f` with its index in `games` as it is done, in no particular order.
+fn encode_games(
+    games: Vec<Vec<u8>>,
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+    mut f: impl FnMut(usize, EncodedGame) -> Result<()>,
+) -> Result<()> {
+    let num_games = games.len();
+    let games = Arc::new(Mutex::new(games.into_iter().enumerate().collect::<Vec<_>>()));
+    std::thread::scope(|scope| -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+        {
+            let games = Arc::clone(&games);
+            let tx = tx.clone();
+            scope.spawn(move || loop {
+                let Some((index, game)) = games.lock().unwrap().pop() else {
+                    break;
+                };
+                let encoded = bincode::deserialize::<Game>(&game)
+                    .map_err(Into::into)
+                    .and_then(|game| encode_game_positions(&game, encoder, options))
+                    .map(|encoded| (index, encoded));
+                // The receiver is gone when an earlier game failed
+                if tx.send(encoded).is_err() {
+                    break;
+                }
+            });
+        }
+        for _ in 0..num_games {
+            let (index, encoded) = rx.recv().unwrap()?;
+            f(index, encoded)?;
+        }
         Ok(())
+    })
+}
+
+/// Encodes the positions reached in `game` which [`EncodeOptions::sampling`] selects, each
+/// labelled with the outcome of the game as [`EncodeOptions::targets`] say.
+///
+/// Each position is encoded with the positions before it as history. Outcomes are seen
+/// from the side to move of each position if the encoder is relative, from white otherwise,
+/// and policy targets are flipped along with the board.
+pub fn encode_game_positions(
+    game: &Game,
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+) -> Result<EncodedGame> {
+    encode_annotated_game_positions(game, &[], encoder, options)
+}
+
+/// Like [`encode_game_positions`], with `annotations[i]` those of move `i + 1`. The WDL of
+/// an [eval](Annotation::eval) takes the place of the result of the position after the
+/// move, [clocks](Annotation::clock) go into the [scalar features](scalars).
+/// `annotations` is empty or has an entry per move.
+pub fn encode_annotated_game_positions(
+    game: &Game,
+    annotations: &[Annotation],
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+) -> Result<EncodedGame> {
+    ensure!(
+        annotations.is_empty() || annotations.len() == game.moves.len(),
+        "Got {} annotations for a game of {} plies",
+        annotations.len(),
+        game.moves.len()
+    );
+    options.targets.check()?;
+    let mut boards = Vec::with_capacity(game.moves.len() + 1);
+    boards.push(Chess::new());
+    let mut moves = Vec::with_capacity(game.moves.len());
+    for (ply, move_) in game.moves.iter().enumerate() {
+        let mut board = boards[ply].clone();
+        let move_ = move_.to_legal_move(&board, ply + 1)?;
+        board.play_unchecked(&move_);
+        moves.push(move_);
+        boards.push(board);
     }
 
-    #[staticmethod]
-    fn load(py: Python<'_>, path: &str) -> PyResult<Self> {
-        let path = PathBuf::from(path);
-        let data = std::fs::read(path).map_err(|e| PyValueError::new_err(format!("{:#?}", e)))?;
-        Ok(Self::decode_bin_py(py, &data))
-    }
-
-    #[staticmethod]
-    fn convert_games_and_save<'py>(_py: Python<'_>, path: PathBuf, max_games: usize, name: &str) -> PyResult<()> {
-        let games: Vec<Vec<u8>> = Decoder::open(&path).unwrap().raw_iter().collect();
-        let name = name.to_string();
-        let mut handles = Vec::with_capacity(games.len());
-        for (i, batch) in games.chunks(max_games).enumerate() {
-            let data = data::TrainData::from_games(batch.to_vec());
-            let path = format!("data/train/{name}/{i:03}.bin");
-            handles.push(std::thread::spawn(move || {
-                let data = data::TrainData::encode_bin(data.0, data.1);
-                std::fs::write(path, data).unwrap();
-            }));
+    let selected = options.sampling.select(game, moves.len())?;
+    let mut ins = Vec::with_capacity(selected.len());
+    let mut outs = Vec::with_capacity(selected.len());
+    let mut hashes = Vec::with_capacity(selected.len());
+    let mut policy = options.policy.then(|| Policy {
+        moves: Array1::from_elem(selected.len(), -1),
+        legal: Array2::from_elem((selected.len(), POLICY_SIZE), false),
+    });
+    let mut scalars = options
+        .scalars
+        .then(|| Array2::zeros((selected.len(), scalars::SCALAR_NAMES.len())));
+    for (row, &ply) in selected.iter().enumerate() {
+        let (board, history) = boards[..ply + 2].split_last().unwrap();
+        ins.push(encoder.encode(&GamePosition::with_history(board, history)));
+        let flip = encoder.relative() && board.turn() == Color::Black;
+        let mut target = options
+            .targets
+            .target(game, ply, annotations.get(ply).and_then(|a| a.eval));
+        if flip {
+            target.reverse();
         }
-        info!("Waiting for files to finish saving");
-        for h in handles {
-            h.join().unwrap();
+        outs.push(Array1::from(target.to_vec()));
+        hashes.push(board.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0);
+        if let Some(policy) = policy.as_mut() {
+            if let Some(next) = moves.get(ply + 1) {
+                policy.moves[row] = policy::move_index(next, flip) as i32;
+            }
+            policy::legal_moves_into(board, flip, policy.legal.row_mut(row));
+        }
+        if let Some(scalars) = scalars.as_mut() {
+            let features =
+                scalars::encode_scalars(game, annotations, ply, board, encoder.relative());
+            scalars.row_mut(row).assign(&ndarray::aview1(&features));
         }
-        Ok(())
     }
+    let moves_left = options.targets.moves_left.then(|| {
+        selected
+            .iter()
+            .map(|&ply| (moves.len() - ply - 1) as f32)
+            .collect()
+    });
+
+    Ok(EncodedGame {
+        ins,
+        outs,
+        policy,
+        hashes,
+        moves_left,
+        scalars,
+        plies: selected.iter().map(|&ply| ply + 1).collect(),
+    })
 }
 
-#[derive(Clone, Copy)]
-struct ReadSend {
-    read: usize,
-    sent: usize,
+/// Parses a FEN of a standard chess position.
+pub fn parse_fen(fen: &str) -> Result<Chess> {
+    let error = |message: String| FenError {
+        fen: fen.to_string(),
+        message,
+    };
+    let setup = fen.parse::<Fen>().map_err(|e| error(e.to_string()))?;
+    let pos = setup
+        .into_position(CastlingMode::Standard)
+        .map_err(|e| error(e.to_string()))?;
+    Ok(pos)
 }
 
-#[pyclass]
-pub struct TrainDataLoader {
-    receiver: mpsc::Receiver<(NNInputBatch, NNOutputBatch)>,
-    // This is a kinda hacky way to inform the worker thread
-    // when we want more data. CBA with a condvar right now
-    read_sent_count: Arc<Mutex<ReadSend>>,
+/// Encodes a position given as FEN exactly like [`encode_game_positions`] encodes the
+/// positions of games. `history` are the FENs of the positions before it, oldest first,
+/// for encoders looking at earlier positions.
+pub fn encode_fen(
+    fen: &str,
+    history: &[impl AsRef<str>],
+    encoder: &dyn FeatureEncoder,
+) -> Result<NNInput> {
+    let (pos, history) = parse_fen_with_history(fen, history)?;
+    Ok(encoder.encode(&GamePosition::with_history(&pos, &history)))
 }
 
-#[pymethods]
-impl TrainDataLoader {
-    #[new]
-    fn new(files: Vec<PathBuf>, prefetch: usize) -> Self {
-        let (tx, receiver) = mpsc::channel();
-        let read_sent_count = Arc::new(Mutex::new(ReadSend { read: 0, sent: 0 }));
-        {
-            let files = Arc::new(Mutex::new(files));
-            for _ in 0..prefetch.max(1).min(std::thread::available_parallelism().unwrap().get()) {
-                let files = Arc::clone(&files);
-                let read_sent_count = Arc::clone(&read_sent_count);
-                let tx = tx.clone();
-                std::thread::spawn(move || {
-                    loop {
-                        if files.lock().unwrap().is_empty() {
-                            break
-                        }
-                        let ReadSend { read, sent } = {
-                            let mg = read_sent_count.lock().unwrap();
-                            *mg
-                        };
-                        if read > sent || (sent - read) < prefetch {
-                            let file = {
-                                let mut mg = files.lock().unwrap();
-                                match mg.pop() {
-                                    Some(f) => f,
-                                    None => break,
-                                }
-                            };
-                            let data = std::fs::read(&file).unwrap();
-                            let start = Instant::now();
-                            let batch = TrainData::decode_bin(&data);
-                            let delta = start.elapsed().as_millis() as f64 / 1000.0;
-                            info!("Loaded {} in {:.2} seconds", file.file_name().unwrap().to_str().unwrap(), delta);
-                            tx.send(batch).unwrap();
-                            {
-                                let mut mg = read_sent_count.lock().unwrap();
-                                mg.sent += 1;
-                            }
-                        } else {
-                            std::thread::sleep(Duration::from_millis(50));
-                        }
+fn parse_fen_with_history(
+    fen: &str,
+    history: &[impl AsRef<str>],
+) -> Result<(Chess, Vec<Chess>)> {
+    let pos = parse_fen(fen)?;
+    let history = history
+        .iter()
+        .map(|fen| parse_fen(fen.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((pos, history))
+}
+
+/// Encodes positions given as FENs into a batch on `threads` threads, all available cores
+/// if 0, each exactly like [`encode_fen`]. `histories` is empty or holds the FENs of the
+/// positions before each of `fens`, it is needed by encoders looking at earlier positions.
+/// Fails on the first invalid FEN.
+pub fn encode_fens<S: AsRef<str> + Sync>(
+    fens: &[S],
+    histories: &[Vec<S>],
+    encoder: &dyn FeatureEncoder,
+    threads: usize,
+) -> Result<NNInputBatch> {
+    ensure!(
+        histories.is_empty() || histories.len() == fens.len(),
+        "Got {} histories for {} positions",
+        histories.len(),
+        fens.len()
+    );
+    ensure!(
+        encoder.history() == 0 || !histories.is_empty() || fens.is_empty(),
+        "Encoder {} stacks {} earlier positions, positions need their histories",
+        encoder.name(),
+        encoder.history()
+    );
+    let threads = match threads {
+        0 => std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+        n => n,
+    };
+    let mut batch = Array4::zeros((fens.len(), 8, 8, encoder.planes()));
+    let chunk = fens.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = fens
+            .chunks(chunk)
+            .enumerate()
+            .zip(batch.axis_chunks_iter_mut(Axis(0), chunk))
+            .map(|((i, fens), mut out)| {
+                scope.spawn(move || -> Result<()> {
+                    for (j, (fen, out)) in fens.iter().zip(out.outer_iter_mut()).enumerate() {
+                        let history = histories.get(i * chunk + j).map_or(&[][..], Vec::as_slice);
+                        let (pos, history) = parse_fen_with_history(fen.as_ref(), history)?;
+                        encoder.encode_into(&GamePosition::with_history(&pos, &history), out);
                     }
-                });
-            }
-        }
-        Self { receiver, read_sent_count }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
+    Ok(batch)
+}
+
+#[test]
+fn test_train_data_roundtrip() {
+    use std::io::Cursor;
+
+    let test_bin = Box::new(Cursor::new(include_bytes!("games/testfiles/test.bin")));
+    let games: Vec<Vec<u8>> = Decoder::start(test_bin).unwrap().raw_iter().take(3).collect();
+    let options = EncodeOptions::default();
+    let data = TrainData::from_games(games.clone(), 0, &encoder::V1Encoder, &options).unwrap();
+    assert_eq!(&data.ins.shape()[1..], &[8, 8, 37]);
+    assert_eq!(data.outs.dim(), (data.len(), 3));
+    assert_eq!(data.encoder, "v1");
+    assert_eq!(data.history, 0);
+
+    let encoded = data.encode_bin();
+    assert_eq!(TrainData::decode_bin(&encoded).unwrap(), data);
+    assert!(TrainData::decode_bin(&encoded[..encoded.len() - 1]).is_err());
+    // 36 binary planes as bitboards and the turn as a single float
+    let header_len = usize::from_le_bytes(encoded[..8].try_into().unwrap());
+    let outs_bytes = data.outs.len() * 4;
+    assert_eq!(
+        encoded.len() - 8 - header_len - outs_bytes,
+        data.len() * (36 * 8 + 4)
+    );
+    // Truncated and corrupted headers are errors, not panics
+    assert!(TrainData::decode_bin(&encoded[..8 + header_len - 1]).is_err());
+    assert!(TrainData::decode_bin(&usize::MAX.to_le_bytes()).is_err());
+    let mut header = TrainDataFileHeader::decode(&encoded[8..8 + header_len]).unwrap();
+    for positions in [usize::MAX / 8, usize::MAX / 300 + 1] {
+        header.ins_shape[0] = positions;
+        header.outs_shape[0] = positions;
+        let mut corrupted = bincode::serialize(&header).unwrap();
+        corrupted.splice(0..0, corrupted.len().to_le_bytes());
+        corrupted.extend_from_slice(&encoded[8 + header_len..]);
+        let err = TrainData::decode_bin(&corrupted).unwrap_err();
+        assert!(err.downcast_ref::<FormatError>().is_some());
     }
 
-    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
-        slf
+    // Shards from before the encoder was recorded store dense floats and decode as v1
+    let dense: Vec<u8> = data
+        .ins
+        .iter()
+        .chain(data.outs.iter())
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+
+    let mut legacy = bincode::serialize(&LegacyTrainDataFileHeader {
+        magic: LEGACY_TRAIN_DATA_MAGIC,
+        ins_shape: data.ins.dim().into(),
+        outs_shape: data.outs.dim().into(),
+    })
+    .unwrap();
+    legacy.splice(0..0, legacy.len().to_le_bytes());
+    legacy.extend_from_slice(&dense);
+    assert_eq!(TrainData::decode_bin(&legacy).unwrap(), data);
+
+    let options = EncodeOptions {
+        policy: true,
+        ..Default::default()
+    };
+    let data = TrainData::from_games(games.clone(), 0, &encoder::V1Encoder, &options).unwrap();
+    let policy = data.policy.as_ref().unwrap();
+    assert_eq!(policy.legal.dim(), (data.len(), POLICY_SIZE));
+    // Every game ends with one position without a next move
+    assert_eq!(policy.moves.iter().filter(|&&m| m < 0).count(), 3);
+    for (&m, legal) in policy.moves.iter().zip(policy.legal.rows()) {
+        assert!(m < 0 || legal[m as usize]);
     }
+    let encoded = data.encode_bin();
+    assert_eq!(TrainData::decode_bin(&encoded).unwrap(), data);
+    assert!(TrainData::decode_bin(&encoded[..encoded.len() - 1]).is_err());
 
-    fn __next__(slf: PyRefMut<'_, Self>) -> Option<(Bound<'_, PyArray4<f32>>, Bound<'_, PyArray2<f32>>)> {
-        debug!("Reading next batch");
-        let now = Instant::now();
-        {
-            let mut mg = slf.read_sent_count.lock().unwrap();
-            debug!("Read {} batches total. Sent {} batches total", mg.read, mg.sent);
-            mg.read += 1;
-        }
-        match slf.receiver.recv() {
-            Ok(batch) => {
-                debug!("Read batch in {:.2}s", now.elapsed().as_secs() as f64 / 1000.0);
-                let now = Instant::now();
-                let ret = Some((
-                    PyArray4::from_owned_array_bound(slf.py(), batch.0),
-                    PyArray2::from_owned_array_bound(slf.py(), batch.1),
-                ));
-                debug!("Conversion to python took {:.3}", now.elapsed().as_secs() as f64 / 1000.0);
-                ret
-            },
-            Err(_) => None,
-        }
+    let weighted = TrainData {
+        weights: Some(Array1::linspace(1.0, 2.0, data.len())),
+        moves_left: Some(Array1::linspace(40.0, 0.0, data.len())),
+        scalars: Some(Array2::from_elem((data.len(), 2), 0.25)),
+        scalar_names: vec!["white_elo".to_string(), "black_elo".to_string()],
+        ..data
+    };
+    assert_eq!(TrainData::decode_bin(&weighted.encode_bin()).unwrap(), weighted);
+
+    let options = EncodeOptions {
+        provenance: true,
+        ..Default::default()
+    };
+    let data = TrainData::from_games(games.clone(), 10, &encoder::V1Encoder, &options).unwrap();
+    let provenance = data.provenance.as_ref().unwrap();
+    assert_eq!(data.weights, Some(Array1::ones(data.len())));
+    // Games arrive in any order, but their positions stay together and in order
+    for game in 10..13 {
+        let plies: Vec<u32> = provenance
+            .plies
+            .iter()
+            .zip(&provenance.games)
+            .filter(|(_, &g)| g == game)
+            .map(|(&ply, _)| ply)
+            .collect();
+        assert_eq!(plies, (1..=plies.len() as u32).collect::<Vec<_>>());
     }
+    let first: Game = bincode::deserialize(&games[0]).unwrap();
+    let encoded = encode_game_positions(&first, &encoder::V1Encoder, &options).unwrap();
+    let rows: Vec<usize> = (0..data.len()).filter(|&i| provenance.games[i] == 10).collect();
+    assert_eq!(provenance.hashes.select(Axis(0), &rows).to_vec(), encoded.hashes);
+    assert_eq!(TrainData::decode_bin(&data.encode_bin()).unwrap(), data);
+
+    let mut appended = data.select(&rows);
+    appended.append(&data.select(&rows)).unwrap();
+    assert_eq!(appended.len(), 2 * rows.len());
+    assert!(appended.append(&weighted).is_err());
+
+    // The turn plane of v1 is -1 for black
+    let games = games[..1].to_vec();
+    let format = TensorFormat {
+        dtype: tensor::Dtype::Uint8,
+        layout: tensor::Layout::Nchw,
+    };
+    let options = EncodeOptions {
+        format,
+        ..Default::default()
+    };
+    assert!(TrainData::from_games(games.clone(), 0, &encoder::V1Encoder, &options).is_err());
+    let relative = encoder::encoder("v1+relative").unwrap();
+    let data = TrainData::from_games(games, 0, &*relative, &options).unwrap();
+    let decoded = TrainData::decode_bin(&data.encode_bin()).unwrap();
+    assert_eq!(decoded.format, format);
+    assert_eq!(decoded.formatted_ins().unwrap().dtype(), tensor::Dtype::Uint8);
 }
 
+#[test]
+fn test_relative_outcomes() {
+    use std::io::Cursor;
 
+    let test_bin = Box::new(Cursor::new(include_bytes!("games/testfiles/test.bin")));
+    let game = Decoder::start(test_bin)
+        .unwrap()
+        .raw_iter()
+        .map(|raw| bincode::deserialize::<Game>(&raw).unwrap())
+        .find(|game| game.outcome != Outcome::Draw)
+        .unwrap();
+    let relative = encoder::encoder("v1+relative").unwrap();
+    let options = EncodeOptions::default();
+    let absolute = encode_game_positions(&game, &encoder::V1Encoder, &options).unwrap();
+    let relative = encode_game_positions(&game, &*relative, &options).unwrap();
+    // Black is to move after white's first move, its view is white's reversed
+    assert_eq!(relative.outs[0], absolute.outs[0].slice(ndarray::s![..;-1]));
+    assert_eq!(relative.outs[1], absolute.outs[1]);
 
-pub fn encode_game_positions(game: Vec<u8>) -> (Vec<NNInput>, NNOutput) {
-    let game = bincode::deserialize::<Game>(&game).unwrap();
-    let mut board = Chess::new();
-    let mut positions = Vec::with_capacity(game.moves.len());
+    // Soft targets and scalar features are swapped the same way, the last position keeps
+    // its result
+    let options = EncodeOptions {
+        targets: ValueTargets {
+            discount: Some(0.9),
+            moves_left: true,
+            ..Default::default()
+        },
+        scalars: true,
+        ..Default::default()
+    };
+    let plies = game.moves.len();
+    let mut annotations = vec![Annotation::default(); plies];
+    annotations[1].eval = Some(crate::games::game::Eval::Mate(-2));
+    let relative = encoder::encoder("v1+relative").unwrap();
+    let absolute =
+        encode_annotated_game_positions(&game, &annotations, &encoder::V1Encoder, &options)
+            .unwrap();
+    let relative =
+        encode_annotated_game_positions(&game, &annotations, &*relative, &options).unwrap();
+    assert_eq!(relative.outs[0], absolute.outs[0].slice(ndarray::s![..;-1]));
+    let (absolute_scalars, relative_scalars) = (absolute.scalars.unwrap(), relative.scalars.unwrap());
+    assert_eq!(relative_scalars[[0, 0]], absolute_scalars[[0, 1]]);
+    assert_eq!(relative_scalars[[1, 0]], absolute_scalars[[1, 0]]);
+    let eval_target = options.targets.target(&game, 1, annotations[1].eval);
+    assert_eq!(absolute.outs[1].to_vec(), eval_target);
+    assert_ne!(eval_target, options.targets.target(&game, 1, None));
+    let last = encode_outcome(game.outcome.clone(), Color::White);
+    assert!((absolute.outs[plies - 1].clone() - last).iter().all(|d| d.abs() < 1e-6));
+    let moves_left = absolute.moves_left.unwrap();
+    assert_eq!((moves_left[0], moves_left[plies - 1]), ((plies - 1) as f32, 0.0));
+    assert!(encode_annotated_game_positions(
+        &game,
+        &annotations[1..],
+        &encoder::V1Encoder,
+        &options
+    )
+    .is_err());
+}
 
-    for move_ in &game.moves {
-        let uci = UciMove::Normal {
-            from: move_.move_from(),
-            to: move_.move_to(),
-            promotion: move_.promotion(),
-        };
-        let move_ = uci.to_move(&board).unwrap();
-        board = board.play(&move_).unwrap();
-        positions.push(encode_position(&board));
-    }
+#[test]
+fn test_sampled_positions() {
+    use std::io::Cursor;
 
-    (positions, encode_outcome(game.outcome))
+    let test_bin = Box::new(Cursor::new(include_bytes!("games/testfiles/test.bin")));
+    let raw = Decoder::start(test_bin).unwrap().raw_iter().next().unwrap();
+    let game = bincode::deserialize::<Game>(&raw).unwrap();
+    let all = EncodeOptions {
+        policy: true,
+        ..Default::default()
+    };
+    let sampled = EncodeOptions {
+        sampling: Sampling {
+            skip_plies: 2,
+            max_per_game: Some(5),
+            ..Default::default()
+        },
+        ..all.clone()
+    };
+    let all = encode_game_positions(&game, &encoder::V1Encoder, &all).unwrap();
+    let sampled = encode_game_positions(&game, &encoder::V1Encoder, &sampled).unwrap();
+    assert_eq!(sampled.ins.len(), 5);
+    let (all_policy, policy) = (all.policy.unwrap(), sampled.policy.unwrap());
+    for (row, ins) in sampled.ins.iter().enumerate() {
+        let ply = all.ins.iter().position(|i| i == ins).unwrap();
+        assert!(ply >= 2);
+        assert_eq!(policy.moves[row], all_policy.moves[ply]);
+        assert_eq!(policy.legal.row(row), all_policy.legal.row(ply));
+    }
 }
 
-pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_class::<TrainData>()?;
-    m.add_class::<TrainDataLoader>()?;
-    Ok(())
+#[test]
+fn test_encode_fens() {
+    let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    let encoder = encoder::encoder("v1+history=1+relative").unwrap();
+    let histories = vec![vec![], vec![start], vec![]];
+    let batch = encode_fens(&[start, after_e4, start], &histories, &*encoder, 2).unwrap();
+    assert_eq!(batch.dim(), (3, 8, 8, 37 + 12));
+    assert_eq!(batch.index_axis(Axis(0), 0), batch.index_axis(Axis(0), 2));
+    assert_eq!(
+        batch.index_axis(Axis(0), 1),
+        encode_fen(after_e4, &[start], &*encoder).unwrap()
+    );
+    // Encoders with history do not silently encode positions without one
+    assert!(encode_fens(&[start, after_e4], &[], &*encoder, 0).is_err());
+    let plain = encode_fens(&[after_e4], &[], &encoder::V1Encoder, 0).unwrap();
+    assert_eq!(
+        plain.index_axis(Axis(0), 0),
+        encode_fen(after_e4, &[] as &[&str], &encoder::V1Encoder).unwrap()
+    );
+    // Positions of games encode the same, given the same history
+    let test_bin = Box::new(std::io::Cursor::new(include_bytes!("games/testfiles/test.bin")));
+    let game = Decoder::start(test_bin).unwrap().read_game().unwrap().unwrap();
+    let mut fens = vec![start.to_string()];
+    let mut pos = Chess::new();
+    for (ply, m) in game.moves[..2].iter().enumerate() {
+        pos.play_unchecked(&m.to_legal_move(&pos, ply + 1).unwrap());
+        fens.push(Fen::from_position(pos.clone(), shakmaty::EnPassantMode::Legal).to_string());
+    }
+    let encoded = encode_game_positions(&game, &*encoder, &EncodeOptions::default()).unwrap();
+    assert_eq!(encoded.ins[1], encode_fen(&fens[2], &fens[..2], &*encoder).unwrap());
+
+    let invalid = [start, "8/8/8/8/8/8/8/8 w - - 0 1"];
+    let err = encode_fens(&invalid, &[], &encoder::V1Encoder, 0).unwrap_err();
+    assert!(err.downcast_ref::<FenError>().is_some());
 }
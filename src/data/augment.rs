@@ -0,0 +1,347 @@
+//! Label preserving symmetries of encoded positions, applied to the planes directly.
+//!
+//! - A color flip mirrors the board vertically and swaps the colors: planes of white and
+//!   black trade places, `turn` is negated, outcome labels are reversed and policy moves
+//!   flipped. Relative encoders already see every position from the side to move, for
+//!   them a color flip changes nothing.
+//! - A mirror swaps the a and h files. It is only label preserving while castling is
+//!   impossible, so it is applied to positions whose castling planes are all zero or, for
+//!   encoders without them, whose kings have both left their starting squares.
+//!
+//! Planes are matched by their names, see [`FeatureEncoder::plane_names`](super::FeatureEncoder::plane_names).
+
+use super::policy;
+use super::sampling::SplitMix64;
+use super::TrainData;
+use eyre::{ensure, Result};
+use ndarray::{s, Array1, Array3, ArrayView3, ArrayViewMut3, Axis};
+
+/// Which symmetries are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Augmentation {
+    pub color_flip: bool,
+    pub mirror: bool,
+}
+
+impl Augmentation {
+    pub fn is_none(&self) -> bool {
+        !self.color_flip && !self.mirror
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Symmetry {
+    ColorFlip,
+    Mirror,
+}
+
+/// A symmetry for the planes of one encoder.
+struct Transform {
+    symmetry: Symmetry,
+    /// Source plane of each plane and whether it is negated.
+    sources: Vec<(usize, bool)>,
+    /// Source of each scalar feature.
+    scalar_sources: Vec<usize>,
+    /// Castling right planes.
+    castling: Vec<usize>,
+    /// King planes of the side starting on rank 1 and of the one starting on rank 8.
+    kings: Option<(usize, usize)>,
+}
+
+fn swap_colors(name: &str) -> String {
+    name.split('_')
+        .map(|word| match word {
+            "white" => "black",
+            "black" => "white",
+            word => word,
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+impl Transform {
+    fn new(symmetry: Symmetry, plane_names: &[String], scalar_names: &[String]) -> Result<Self> {
+        let index = |name: &str| plane_names.iter().position(|n| n == name);
+        let sources = match symmetry {
+            Symmetry::ColorFlip => {
+                ensure!(
+                    !plane_names.iter().any(|n| n.split('_').any(|w| w == "own")),
+                    "Relative encoders see every position from the side to move, color flips \
+                     do not change them"
+                );
+                plane_names
+                    .iter()
+                    .map(|name| {
+                        let swapped = swap_colors(name);
+                        match index(&swapped) {
+                            Some(source) => Ok((source, name == "turn")),
+                            None => Err(eyre::eyre!(
+                                "Plane {} has no counterpart {} for color flips",
+                                name,
+                                swapped
+                            )),
+                        }
+                    })
+                    .collect::<Result<_>>()?
+            }
+            Symmetry::Mirror => (0..plane_names.len()).map(|i| (i, false)).collect(),
+        };
+        let scalar_sources = scalar_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| match symmetry {
+                Symmetry::ColorFlip => scalar_names
+                    .iter()
+                    .position(|n| *n == swap_colors(name))
+                    .unwrap_or(i),
+                Symmetry::Mirror => i,
+            })
+            .collect();
+        let castling = plane_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| name.ends_with("_castling"))
+            .map(|(i, _)| i)
+            .collect();
+        let kings = index("white_king")
+            .zip(index("black_king"))
+            .or_else(|| index("own_king").zip(index("opponent_king")));
+        Ok(Self {
+            symmetry,
+            sources,
+            scalar_sources,
+            castling,
+            kings,
+        })
+    }
+
+    /// Whether the symmetry preserves the label of `position`.
+    fn applies(&self, position: ArrayView3<'_, f32>) -> bool {
+        if self.symmetry == Symmetry::ColorFlip {
+            return true;
+        }
+        if !self.castling.is_empty() {
+            return self
+                .castling
+                .iter()
+                .all(|&plane| position.index_axis(Axis(2), plane).iter().all(|&v| v == 0.0));
+        }
+        // Without castling rights a king on its starting square might still castle
+        match self.kings {
+            Some((first, eighth)) => {
+                position[[4, 7, first]] == 0.0 && position[[4, 0, eighth]] == 0.0
+            }
+            None => false,
+        }
+    }
+
+    fn planes(&self, position: ArrayView3<'_, f32>, mut out: ArrayViewMut3<'_, f32>) {
+        for (i, &(source, negate)) in self.sources.iter().enumerate() {
+            let plane = position.index_axis(Axis(2), source);
+            let plane = match self.symmetry {
+                Symmetry::ColorFlip => plane.slice_move(s![.., ..;-1]),
+                Symmetry::Mirror => plane.slice_move(s![..;-1, ..]),
+            };
+            out.index_axis_mut(Axis(2), i)
+                .zip_mut_with(&plane, |o, &v| *o = if negate { -v } else { v });
+        }
+    }
+
+    fn outcome(&self, outcome: &mut [f32]) {
+        if self.symmetry == Symmetry::ColorFlip {
+            outcome.reverse();
+        }
+    }
+
+    fn move_index(&self, index: usize) -> usize {
+        match self.symmetry {
+            Symmetry::ColorFlip => policy::flip_index(index),
+            Symmetry::Mirror => policy::mirror_index(index),
+        }
+    }
+}
+
+fn transforms(augmentation: &Augmentation, data: &TrainData) -> Result<Vec<Transform>> {
+    let (planes, scalars) = (&data.plane_names, &data.scalar_names);
+    let mut transforms = Vec::new();
+    if augmentation.color_flip {
+        transforms.push(Transform::new(Symmetry::ColorFlip, planes, scalars)?);
+    }
+    if augmentation.mirror {
+        transforms.push(Transform::new(Symmetry::Mirror, planes, scalars)?);
+    }
+    Ok(transforms)
+}
+
+/// Applies `transform` to row `row` of `data` in place.
+fn apply(transform: &Transform, data: &mut TrainData, row: usize) {
+    let position = data.ins.index_axis(Axis(0), row);
+    let mut planes = Array3::zeros(position.raw_dim());
+    transform.planes(position, planes.view_mut());
+    data.ins.index_axis_mut(Axis(0), row).assign(&planes);
+    let mut outcome = data.outs.row(row).to_vec();
+    transform.outcome(&mut outcome);
+    data.outs.row_mut(row).assign(&Array1::from(outcome));
+    if let Some(scalars) = data.scalars.as_mut() {
+        let features = scalars.row(row).to_vec();
+        for (value, &source) in scalars.row_mut(row).iter_mut().zip(&transform.scalar_sources) {
+            *value = features[source];
+        }
+    }
+    if let Some(policy) = data.policy.as_mut() {
+        let m = policy.moves[row];
+        if m >= 0 {
+            policy.moves[row] = transform.move_index(m as usize) as i32;
+        }
+        let legal = policy.legal.row(row).to_owned();
+        let mut mapped = policy.legal.row_mut(row);
+        mapped.fill(false);
+        for (index, _) in legal.iter().enumerate().filter(|(_, &legal)| legal) {
+            mapped[transform.move_index(index)] = true;
+        }
+    }
+}
+
+/// Applies each symmetry of `augmentation` to every position it preserves with
+/// probability 1/2, seeded by `seed`. Meant for loading, where every epoch should see
+/// other variants.
+pub fn randomize(data: &mut TrainData, augmentation: &Augmentation, seed: u64) -> Result<()> {
+    let transforms = transforms(augmentation, data)?;
+    let mut rng = SplitMix64::mixed(seed, []);
+    for row in 0..data.len() {
+        for transform in &transforms {
+            if transform.applies(data.ins.index_axis(Axis(0), row)) && rng.next_u64() & 1 == 1 {
+                apply(transform, data, row);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `data` followed by the variants of its positions under each combination of the
+/// symmetries of `augmentation`, in the order color flip, mirror, both.
+pub fn expand(data: TrainData, augmentation: &Augmentation) -> Result<TrainData> {
+    let transforms = transforms(augmentation, &data)?;
+    let mut combinations: Vec<Vec<&Transform>> = vec![vec![]];
+    for transform in &transforms {
+        let with: Vec<_> = combinations
+            .iter()
+            .map(|c| c.iter().copied().chain([transform]).collect())
+            .collect();
+        combinations.extend(with);
+    }
+
+    let mut expanded = data.clone();
+    for combination in &combinations[1..] {
+        // The symmetries do not change whether each other applies
+        let rows: Vec<usize> = (0..data.len())
+            .filter(|&row| {
+                combination
+                    .iter()
+                    .all(|t| t.applies(data.ins.index_axis(Axis(0), row)))
+            })
+            .collect();
+        let mut variant = data.select(&rows);
+        for row in 0..variant.len() {
+            for transform in combination {
+                apply(transform, &mut variant, row);
+            }
+        }
+        expanded.append(&variant)?;
+    }
+    Ok(expanded)
+}
+
+#[test]
+fn test_augmentation() {
+    use super::encoder::{encoder, GamePosition};
+    use super::{encode_outcome, parse_fen};
+    use crate::games::game::Outcome;
+    use ndarray::stack;
+    use shakmaty::{Color, Position};
+
+    let single = |fen: &str, name: &str| {
+        let encoder = encoder(name).unwrap();
+        let pos = parse_fen(fen).unwrap();
+        let mut legal = Array1::from_elem(policy::POLICY_SIZE, false);
+        policy::legal_moves_into(&pos, false, legal.view_mut());
+        let move_ = &pos.legal_moves()[0];
+        TrainData {
+            ins: stack![Axis(0), encoder.encode(&GamePosition::new(&pos))],
+            outs: stack![Axis(0), encode_outcome(Outcome::WhiteWin, Color::White)],
+            encoder: encoder.name().to_string(),
+            plane_names: encoder.plane_names(),
+            history: 0,
+            policy: Some(policy::Policy {
+                moves: Array1::from_elem(1, policy::move_index(move_, false) as i32),
+                legal: legal.insert_axis(Axis(0)),
+            }),
+            format: Default::default(),
+            weights: None,
+            moves_left: None,
+            scalars: None,
+            scalar_names: Vec::new(),
+            provenance: None,
+        }
+    };
+
+    // A color flip gives the encoding of the flipped position, with black winning
+    let fen = "r3k2r/ppp2ppp/8/3pP3/8/8/PPP2PPP/R3K2R w Kq d6 0 9";
+    let flipped_fen = "r3k2r/ppp2ppp/8/8/3Pp3/8/PPP2PPP/R3K2R b Qk d3 0 9";
+    let color_flip = Augmentation {
+        color_flip: true,
+        mirror: false,
+    };
+    for name in ["v1", "v1+state"] {
+        let expanded = expand(single(fen, name), &color_flip).unwrap();
+        assert_eq!(expanded.len(), 2);
+        let flipped = single(flipped_fen, name);
+        assert_eq!(expanded.ins.index_axis(Axis(0), 1), flipped.ins.index_axis(Axis(0), 0));
+        assert_eq!(expanded.outs.row(1).to_vec(), [0.0, 0.0, 1.0]);
+        let (policy, expected) = (expanded.policy.unwrap(), flipped.policy.unwrap());
+        assert_eq!(policy.legal.row(1), expected.legal.row(0));
+    }
+    assert!(expand(single(fen, "v1+relative"), &color_flip).is_err());
+    // Scalar features of the colors trade places too
+    let mut data = single(fen, "v1");
+    data.scalar_names = ["white_elo", "black_elo", "ply"].map(String::from).to_vec();
+    data.scalars = Some(ndarray::array![[0.5, 0.6, 0.1]]);
+    let expanded = expand(data, &color_flip).unwrap();
+    assert_eq!(expanded.scalars.unwrap().row(1).to_vec(), [0.6, 0.5, 0.1]);
+
+    // Castling rights keep the position from being mirrored, kings off their starting
+    // squares allow it
+    let both = Augmentation {
+        color_flip: true,
+        mirror: true,
+    };
+    assert_eq!(expand(single(fen, "v1+state"), &both).unwrap().len(), 2);
+    let fen = "8/5k2/8/3pP3/8/8/1K6/8 w - d6 0 40";
+    let mirrored_fen = "8/2k5/8/3Pp3/8/8/6K1/8 w - e6 0 40";
+    for name in ["v1", "v1+state", "v1+relative"] {
+        let mirror = Augmentation {
+            color_flip: false,
+            mirror: true,
+        };
+        let expanded = expand(single(fen, name), &mirror).unwrap();
+        let mirrored = single(mirrored_fen, name);
+        assert_eq!(expanded.ins.index_axis(Axis(0), 1), mirrored.ins.index_axis(Axis(0), 0));
+        let (policy, expected) = (expanded.policy.unwrap(), mirrored.policy.unwrap());
+        assert_eq!(policy.legal.row(1), expected.legal.row(0));
+    }
+    assert_eq!(expand(single(fen, "v1"), &both).unwrap().len(), 4);
+
+    let mut data = single(fen, "v1");
+    let original = data.clone();
+    let variants: Vec<_> = (0..16)
+        .map(|seed| {
+            let mut data = original.clone();
+            randomize(&mut data, &both, seed).unwrap();
+            data.ins
+        })
+        .collect();
+    assert!(variants.contains(&original.ins));
+    assert!(variants.iter().any(|ins| ins != original.ins));
+    randomize(&mut data, &both, 3).unwrap();
+    assert_eq!(data.len(), 1);
+}
@@ -0,0 +1,321 @@
+//! Decoding of encoded positions back to boards, for looking into training data.
+//!
+//! Positions are read from the piece planes, `turn` and, if the encoder has them, the
+//! [state planes](super::encoder::StatePlanes). Relative encodings decode as seen by the
+//! side to move, which then is white. The decoded position is encoded again and every
+//! plane which does not depend on earlier positions has to match.
+
+use super::encoder::{FeatureEncoder, GamePosition};
+use crate::error::FormatError;
+use eyre::{ensure, Result};
+use ndarray::{ArrayView2, ArrayView3, Axis};
+use shakmaty::{
+    Bitboard, Board, CastlingMode, Chess, Color, File, Piece, PositionError, Rank, Role, Setup,
+    Square,
+};
+use std::fmt::Write;
+use std::num::NonZeroU32;
+
+const ROLES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+/// Castling plane name suffixes with the square of their rook, from white's side.
+const CASTLING: [(&str, &str, Square); 4] = [
+    ("white", "kingside_castling", Square::H1),
+    ("white", "queenside_castling", Square::A1),
+    ("black", "kingside_castling", Square::H8),
+    ("black", "queenside_castling", Square::A8),
+];
+
+/// Square of `[file][7 - rank]` indices.
+fn square(x: usize, y: usize) -> Square {
+    Square::from_coords(File::new(x as u32), Rank::new(7 - y as u32))
+}
+
+fn color_names(color: Color) -> [&'static str; 2] {
+    match color {
+        Color::White => ["white", "own"],
+        Color::Black => ["black", "opponent"],
+    }
+}
+
+/// Squares of a plane which are set.
+fn squares(plane: ArrayView2<'_, f32>) -> Bitboard {
+    let mut bb = Bitboard::EMPTY;
+    for ((x, y), &value) in plane.indexed_iter() {
+        if value != 0.0 {
+            bb.add(square(x, y));
+        }
+    }
+    bb
+}
+
+/// Planes which depend on earlier positions: repetitions and history planes, whose names
+/// end in how many plies ago they are.
+fn needs_history(name: &str) -> bool {
+    name.starts_with("repeated_")
+        || name
+            .rsplit_once('_')
+            .is_some_and(|(_, ply)| ply.parse::<usize>().is_ok())
+}
+
+/// Reconstructs the position of `planes`, of shape `[8][8][planes]` as produced by
+/// `encoder`, and checks that encoding it gives the same planes.
+///
+/// Castling rights and the en passant square are taken from state planes. Without them
+/// they are only visible through the moves of the side to move, the most castling rights
+/// which fit the planes are assumed.
+pub fn decode_planes(planes: ArrayView3<'_, f32>, encoder: &dyn FeatureEncoder) -> Result<Chess> {
+    let names = encoder.plane_names();
+    ensure!(
+        planes.shape() == [8, 8, names.len()],
+        FormatError::new(format!(
+            "Expected planes of shape [8, 8, {}] for {}, got {:?}",
+            names.len(),
+            encoder.name(),
+            planes.shape()
+        ))
+    );
+    let plane = |name: &str| {
+        names
+            .iter()
+            .position(|n| n == name)
+            .map(|i| planes.index_axis(Axis(2), i))
+    };
+    let colored = |color: Color, suffix: &str| {
+        color_names(color)
+            .iter()
+            .find_map(|c| plane(&format!("{}_{}", c, suffix)))
+    };
+
+    let mut board = Board::empty();
+    for color in Color::ALL {
+        for (role, role_name) in Role::ALL.into_iter().zip(ROLES) {
+            let Some(pieces) = colored(color, role_name) else {
+                return Err(FormatError::new(format!(
+                    "{} has no plane for {} {}s",
+                    encoder.name(),
+                    color,
+                    role_name
+                ))
+                .into());
+            };
+            for sq in squares(pieces) {
+                ensure!(
+                    board.piece_at(sq).is_none(),
+                    FormatError::new(format!("Two pieces on {}", sq))
+                );
+                board.set_piece_at(sq, Piece { color, role });
+            }
+        }
+    }
+    let turn = match plane("turn") {
+        Some(turn) if turn.sum() < 0.0 => Color::Black,
+        _ => Color::White,
+    };
+
+    let castling_candidates: Vec<Bitboard> = {
+        let given: Vec<_> = CASTLING
+            .iter()
+            .map(|&(color, suffix, rook)| {
+                let color = if color == "white" {
+                    Color::White
+                } else {
+                    Color::Black
+                };
+                (colored(color, suffix), rook)
+            })
+            .collect();
+        if given.iter().all(|(plane, _)| plane.is_some()) {
+            let rights = given
+                .iter()
+                .filter(|(plane, _)| plane.is_some_and(|p| p.sum() > 0.0))
+                .map(|&(_, rook)| rook);
+            vec![Bitboard::from_iter(rights)]
+        } else {
+            // Every subset of the rooks on their starting squares next to their king, the
+            // largest first
+            let possible: Vec<Square> = CASTLING
+                .iter()
+                .map(|&(_, _, rook)| rook)
+                .filter(|&rook| {
+                    let color = if rook.rank() == Rank::First {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    let king = Square::from_coords(File::E, rook.rank());
+                    board.piece_at(rook)
+                        == Some(Piece {
+                            color,
+                            role: Role::Rook,
+                        })
+                        && board.piece_at(king)
+                            == Some(Piece {
+                                color,
+                                role: Role::King,
+                            })
+                })
+                .collect();
+            (0..1u32 << possible.len())
+                .rev()
+                .map(|subset| {
+                    Bitboard::from_iter(
+                        possible
+                            .iter()
+                            .enumerate()
+                            .filter(|(i, _)| subset & (1 << i) != 0)
+                            .map(|(_, &sq)| sq),
+                    )
+                })
+                .collect()
+        }
+    };
+    let ep_candidates: Vec<Option<Square>> = match plane("en_passant") {
+        Some(ep) => vec![squares(ep).first()],
+        None => {
+            let rank = match turn {
+                Color::White => Rank::Sixth,
+                Color::Black => Rank::Third,
+            };
+            let mut candidates = vec![None];
+            candidates.extend(
+                File::ALL
+                    .into_iter()
+                    .map(|file| Some(Square::from_coords(file, rank))),
+            );
+            candidates
+        }
+    };
+    let halfmoves = plane("halfmove_clock").map_or(0, |p| (p[[0, 0]] * 100.0).round() as u32);
+    let fullmoves = plane("fullmove_number").map_or(1, |p| (p[[0, 0]] * 200.0).round() as u32);
+
+    let mut mismatch = None;
+    for &castling_rights in &castling_candidates {
+        for &ep_square in &ep_candidates {
+            let setup = Setup {
+                board: board.clone(),
+                turn,
+                castling_rights,
+                ep_square,
+                halfmoves,
+                fullmoves: NonZeroU32::new(fullmoves).unwrap_or(NonZeroU32::MIN),
+                ..Setup::empty()
+            };
+            let pos = setup
+                .position::<Chess>(CastlingMode::Standard)
+                .or_else(PositionError::ignore_too_much_material);
+            let pos = match pos {
+                Ok(pos) => pos,
+                Err(e) if mismatch.is_none() => {
+                    mismatch = Some(e.to_string());
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let encoded = encoder.encode(&GamePosition::new(&pos));
+            let differing: Vec<&str> = names
+                .iter()
+                .enumerate()
+                .filter(|(i, name)| {
+                    !needs_history(name)
+                        && encoded.index_axis(Axis(2), *i) != planes.index_axis(Axis(2), *i)
+                })
+                .map(|(_, name)| name.as_str())
+                .collect();
+            if differing.is_empty() {
+                return Ok(pos);
+            }
+            if mismatch.is_none() {
+                mismatch = Some(format!("planes {} differ", differing.join(", ")));
+            }
+        }
+    }
+    Err(FormatError::new(format!(
+        "Planes are not an encoding of a position: {}",
+        mismatch.unwrap_or_default()
+    ))
+    .into())
+}
+
+/// A plane as board, rank 8 at the top: `.` where it is 0 and the value elsewhere.
+pub fn plane_ascii(plane: ArrayView2<'_, f32>) -> String {
+    let mut out = String::new();
+    for y in 0..8 {
+        let _ = write!(out, "{} ", 8 - y);
+        for x in 0..8 {
+            let value = plane[[x, y]];
+            if value == 0.0 {
+                out.push_str("    .");
+            } else if value.fract() == 0.0 {
+                let _ = write!(out, "{:>5}", value);
+            } else {
+                let _ = write!(out, "{:>5.2}", value);
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("      a    b    c    d    e    f    g    h\n");
+    out
+}
+
+/// Every plane of an encoded position as board, headed by its name.
+pub fn dump_planes(planes: ArrayView3<'_, f32>, plane_names: &[String]) -> String {
+    planes
+        .axis_iter(Axis(2))
+        .enumerate()
+        .map(|(i, plane)| {
+            let name = plane_names.get(i).map_or("?", |name| name.as_str());
+            format!("{} {}:\n{}", i, name, plane_ascii(plane))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_decode_planes() {
+    use super::encoder::encoder;
+    use shakmaty::fen::Fen;
+    use shakmaty::EnPassantMode;
+
+    let fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/ppp2ppp/8/3pP3/8/8/PPP2PPP/R3K2R w Kq d6 0 9",
+        "4k3/8/8/8/8/8/8/R3K2R b Q - 3 40",
+    ];
+    for name in ["v1", "v1+state", "v1+state+history=2"] {
+        let encoder = encoder(name).unwrap();
+        for fen in fens {
+            let pos = super::parse_fen(fen).unwrap();
+            let planes = encoder.encode(&GamePosition::new(&pos));
+            let decoded = decode_planes(planes.view(), &*encoder).unwrap();
+            let decoded = Fen::from_position(decoded, EnPassantMode::Legal).to_string();
+            if name == "v1" {
+                // Without state planes castling rights of the side not to move and the
+                // clocks are unknown
+                let decoded: Vec<_> = decoded.split(' ').take(2).collect();
+                let expected: Vec<_> = fen.split(' ').take(2).collect();
+                assert_eq!(decoded, expected);
+            } else {
+                assert_eq!(decoded, fen);
+            }
+        }
+    }
+
+    let encoder = encoder("v1").unwrap();
+    let pos = super::parse_fen(fens[0]).unwrap();
+    let mut planes = encoder.encode(&GamePosition::new(&pos));
+    // The e pawn moved to e4 without its attacks
+    planes[[4, 6, 0]] = 0.0;
+    planes[[4, 4, 0]] = 1.0;
+    let err = decode_planes(planes.view(), &*encoder).unwrap_err();
+    assert!(
+        err.to_string().contains("attacked_by_white_pawn"),
+        "{}",
+        err
+    );
+
+    let ascii = plane_ascii(planes.index_axis(Axis(2), 0));
+    assert_eq!(
+        ascii.lines().nth(4).unwrap(),
+        "4     .    .    .    .    1    .    .    ."
+    );
+}
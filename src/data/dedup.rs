@@ -0,0 +1,472 @@
+//! Deduplication of positions across games.
+//!
+//! Opening positions recur in a large share of all games. Instead of one sample per
+//! occurrence with a hard label, [`convert_games_dedup_and_save`] writes every distinct
+//! position once, labelled with the mean outcome label of its occurrences and weighted by
+//! their number. Positions are told apart by their Zobrist hash. Planes which depend on
+//! more than the position, such as clocks or history, are taken from the occurrence with
+//! the smallest encoding, so the choice does not depend on the order games are read in.
+//!
+//! Memory stays bounded: whenever [`DedupOptions::memory_positions`] distinct positions
+//! are held, they are sorted by hash and spilled to a run file. The runs are merged in
+//! the end, like in an external merge sort.
+
+use super::{encode_games, EncodeOptions, FeatureEncoder, NNInputBatch, PlaneStorage, TrainData};
+use crate::error::{FormatError, IoError};
+use crate::games::serialization::Decoder;
+use crate::progress::ProgressTracker;
+use eyre::{ensure, Result, WrapErr};
+use ndarray::{Array1, Array2, ArrayView3, ArrayViewMut3, Axis};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span};
+
+/// Games read and encoded at once.
+const GAMES_PER_CHUNK: usize = 1000;
+
+/// Limits of [`convert_games_dedup_and_save`].
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    /// Distinct positions held in memory before they are spilled to a run file, each
+    /// takes a few hundred bytes.
+    pub memory_positions: usize,
+    /// Positions per written shard.
+    pub shard_positions: usize,
+}
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        Self {
+            memory_positions: 1_000_000,
+            shard_positions: 100_000,
+        }
+    }
+}
+
+/// A distinct position with the outcomes of all its occurrences so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    hash: u64,
+    /// Sum of the outcome labels.
+    outcomes: [f32; 3],
+    count: u32,
+    /// The planes, see [`pack`].
+    planes: Vec<u8>,
+}
+
+impl Entry {
+    fn merge(&mut self, other: Entry) {
+        for (sum, outcome) in self.outcomes.iter_mut().zip(other.outcomes) {
+            *sum += outcome;
+        }
+        self.count += other.count;
+        if other.planes < self.planes {
+            self.planes = other.planes;
+        }
+    }
+}
+
+/// Packs the planes of one position, each one as a tag byte of its [`PlaneStorage`]
+/// followed by its data.
+fn pack(position: ArrayView3<'_, f32>) -> Vec<u8> {
+    let mut packed = Vec::new();
+    for plane in position.axis_iter(Axis(2)) {
+        let storage = PlaneStorage::of(plane.insert_axis(Axis(0)));
+        packed.push(match storage {
+            PlaneStorage::Bits => 0,
+            PlaneStorage::Scalar => 1,
+            PlaneStorage::Dense => 2,
+        });
+        storage.write(plane, &mut packed);
+    }
+    packed
+}
+
+fn unpack(mut packed: &[u8], mut position: ArrayViewMut3<'_, f32>) -> Result<()> {
+    for plane in position.axis_iter_mut(Axis(2)) {
+        let storage = match packed.first() {
+            Some(0) => PlaneStorage::Bits,
+            Some(1) => PlaneStorage::Scalar,
+            Some(2) => PlaneStorage::Dense,
+            _ => return Err(FormatError::new("Corrupted deduplication run").into()),
+        };
+        ensure!(
+            packed.len() > storage.bytes(),
+            FormatError::new("Truncated deduplication run")
+        );
+        packed = storage.read(&packed[1..], plane);
+    }
+    Ok(())
+}
+
+/// Sorts `entries` by hash and writes them to a new run file in `dir`.
+fn spill(entries: &mut HashMap<u64, Entry>, dir: &Path, runs: &mut Vec<PathBuf>) -> Result<()> {
+    let mut sorted: Vec<Entry> = entries.drain().map(|(_, entry)| entry).collect();
+    sorted.sort_unstable_by_key(|entry| entry.hash);
+    let path = dir.join(format!("{:04}.run", runs.len()));
+    info!("Spilling {} positions to {}", sorted.len(), path.display());
+    let file = File::create(&path).map_err(|e| IoError::new(&path, e))?;
+    let mut writer = lz4_flex::frame::FrameEncoder::new(BufWriter::new(file));
+    bincode::serialize_into(&mut writer, &(sorted.len() as u64))?;
+    for entry in &sorted {
+        bincode::serialize_into(&mut writer, entry)?;
+    }
+    writer
+        .finish()
+        .map_err(std::io::Error::from)
+        .and_then(|mut w| w.flush())
+        .map_err(|e| IoError::new(&path, e))?;
+    runs.push(path);
+    Ok(())
+}
+
+/// Entries of a run file in hash order.
+struct Run {
+    reader: lz4_flex::frame::FrameDecoder<BufReader<File>>,
+    remaining: u64,
+}
+
+impl Run {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| IoError::new(path, e))?;
+        let mut reader = lz4_flex::frame::FrameDecoder::new(BufReader::new(file));
+        let remaining = bincode::deserialize_from(&mut reader)?;
+        Ok(Self { reader, remaining })
+    }
+
+    fn next(&mut self) -> Result<Option<Entry>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        Ok(Some(bincode::deserialize_from(&mut self.reader)?))
+    }
+}
+
+/// Merges sorted runs into one sequence of entries in hash order, equal hashes are not
+/// merged yet.
+struct Merge {
+    runs: Vec<Run>,
+    heads: BinaryHeap<Reverse<(u64, usize)>>,
+    pending: Vec<Option<Entry>>,
+}
+
+impl Merge {
+    fn new(mut runs: Vec<Run>) -> Result<Self> {
+        let mut heads = BinaryHeap::new();
+        let mut pending = Vec::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            let entry = run.next()?;
+            if let Some(entry) = &entry {
+                heads.push(Reverse((entry.hash, i)));
+            }
+            pending.push(entry);
+        }
+        Ok(Self {
+            runs,
+            heads,
+            pending,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Entry>> {
+        let Some(Reverse((_, i))) = self.heads.pop() else {
+            return Ok(None);
+        };
+        let entry = self.pending[i].take();
+        if let Some(next) = self.runs[i].next()? {
+            self.heads.push(Reverse((next.hash, i)));
+            self.pending[i] = Some(next);
+        }
+        Ok(entry)
+    }
+}
+
+/// Turns distinct positions into a shard, labelled with their mean outcome and weighted
+/// by their count.
+fn shard(
+    entries: &[Entry],
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+) -> Result<TrainData> {
+    let mut ins = NNInputBatch::zeros((entries.len(), 8, 8, encoder.planes()));
+    let mut outs = Array2::zeros((entries.len(), 3));
+    for ((entry, position), mut outcome) in entries
+        .iter()
+        .zip(ins.outer_iter_mut())
+        .zip(outs.outer_iter_mut())
+    {
+        unpack(&entry.planes, position)?;
+        for (label, sum) in outcome.iter_mut().zip(entry.outcomes) {
+            *label = sum / entry.count as f32;
+        }
+    }
+    let weights: Array1<f32> = entries.iter().map(|entry| entry.count as f32).collect();
+    let plane_names = encoder.plane_names();
+    options.format.check(&ins, &plane_names)?;
+    Ok(TrainData {
+        ins,
+        outs,
+        encoder: encoder.name().to_string(),
+        plane_names,
+        history: encoder.history(),
+        policy: None,
+        format: options.format,
+        weights: Some(weights),
+        moves_left: None,
+        scalars: None,
+        scalar_names: Vec::new(),
+        provenance: None,
+    })
+}
+
+/// Encodes the positions of a `.bin` archive like [`super::convert_games_and_save`], but
+/// writes every distinct position once into shards of [`DedupOptions::shard_positions`]
+/// positions, saved as `{out_dir}/000.bin`, `{out_dir}/001.bin`, .. in hash order.
+///
+/// Outcome labels are the mean of those of all occurrences and each position carries its
+/// number of occurrences as weight. Run files are kept in `{out_dir}/runs.tmp` while
+/// converting. Returns the number of shards written.
+pub fn convert_games_dedup_and_save(
+    path: &Path,
+    out_dir: &Path,
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+    dedup: &DedupOptions,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<usize> {
+    ensure!(
+        !options.policy,
+        "Policy targets can not be combined with deduplication"
+    );
+    ensure!(
+        options.augmentation.is_none(),
+        "Augmentation can not be combined with deduplication, augment when loading instead"
+    );
+    ensure!(
+        !options.targets.moves_left,
+        "Moves left targets can not be combined with deduplication"
+    );
+    ensure!(
+        !options.scalars,
+        "Scalar features differ between the games of a position, they can not be combined \
+         with deduplication"
+    );
+    ensure!(
+        !options.provenance,
+        "Deduplicated positions come from many games, provenance can not be combined with \
+         deduplication"
+    );
+    ensure!(
+        dedup.memory_positions > 0 && dedup.shard_positions > 0,
+        "Deduplication needs to hold at least one position in memory and per shard"
+    );
+    let _span = info_span!("convert_games_dedup_and_save", file = %path.display()).entered();
+    let mut decoder = Decoder::open(path).wrap_err("failed to open source bin file")?;
+    tracker.set_bytes_total(std::fs::metadata(path).ok().map(|m| m.len()));
+
+    let runs_dir = out_dir.join("runs.tmp");
+    std::fs::create_dir_all(&runs_dir).map_err(|e| IoError::new(&runs_dir, e))?;
+    let result = (|| -> Result<usize> {
+        let mut entries: HashMap<u64, Entry> = HashMap::new();
+        let mut runs = Vec::new();
+        loop {
+            let mut games = Vec::with_capacity(GAMES_PER_CHUNK);
+            while games.len() < GAMES_PER_CHUNK {
+                match decoder.read_game_raw()? {
+                    Some(game) => games.push(game),
+                    None => break,
+                }
+            }
+            if games.is_empty() {
+                break;
+            }
+            let num_games = games.len();
+            let mut positions = 0;
+            encode_games(games, encoder, options, |_, encoded| {
+                positions += encoded.ins.len();
+                for ((planes, outcome), hash) in
+                    encoded.ins.iter().zip(&encoded.outs).zip(encoded.hashes)
+                {
+                    let entry = Entry {
+                        hash,
+                        outcomes: [outcome[0], outcome[1], outcome[2]],
+                        count: 1,
+                        planes: pack(planes.view()),
+                    };
+                    match entries.get_mut(&hash) {
+                        Some(existing) => existing.merge(entry),
+                        None => {
+                            entries.insert(hash, entry);
+                            if entries.len() >= dedup.memory_positions {
+                                spill(&mut entries, &runs_dir, &mut runs)?;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            let bytes = decoder.bytes_read();
+            tracker.update(|p| {
+                p.bytes_processed = bytes;
+                p.games_accepted += num_games;
+                p.positions += positions;
+            })?;
+        }
+
+        let mut merge = if runs.is_empty() {
+            None
+        } else {
+            if !entries.is_empty() {
+                spill(&mut entries, &runs_dir, &mut runs)?;
+            }
+            let runs = runs
+                .iter()
+                .map(|run| Run::open(run))
+                .collect::<Result<_>>()?;
+            Some(Merge::new(runs)?)
+        };
+        let mut in_memory: Vec<Entry> = entries.into_values().collect();
+        in_memory.sort_unstable_by_key(|entry| Reverse(entry.hash));
+        let mut next = || match merge.as_mut() {
+            Some(merge) => merge.next(),
+            None => Ok(in_memory.pop()),
+        };
+
+        let mut shards = 0;
+        let mut distinct = 0;
+        let mut batch: Vec<Entry> = Vec::new();
+        let mut current: Option<Entry> = None;
+        loop {
+            let entry = next()?;
+            match (&mut current, entry) {
+                (Some(current), Some(entry)) if current.hash == entry.hash => {
+                    current.merge(entry);
+                    continue;
+                }
+                (_, entry) => {
+                    batch.extend(std::mem::replace(&mut current, entry));
+                }
+            }
+            if batch.len() == dedup.shard_positions || (current.is_none() && !batch.is_empty()) {
+                distinct += batch.len();
+                let path = out_dir.join(format!("{:03}.bin", shards));
+                shard(&batch, encoder, options)?.save(&path)?;
+                batch.clear();
+                shards += 1;
+            }
+            if current.is_none() {
+                break;
+            }
+        }
+        info!(
+            "Wrote {} distinct of {} positions",
+            distinct,
+            tracker.progress().positions
+        );
+        Ok(shards)
+    })();
+    // Run files are only useful to the conversion which wrote them
+    let _ = std::fs::remove_dir_all(&runs_dir);
+    let shards = result?;
+    tracker.finish()?;
+    Ok(shards)
+}
+
+#[test]
+fn test_dedup() {
+    /// Removes the directory of the test when dropped, also when an assertion fails.
+    struct TempDir(PathBuf);
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("chessers-dedup-{}", std::process::id()));
+    let _cleanup = TempDir(dir.clone());
+    std::fs::create_dir_all(&dir).unwrap();
+    // The first games of the test archive, they share their first moves
+    let archive = dir.join("test.bin");
+    let test_bin = Box::new(std::io::Cursor::new(include_bytes!(
+        "../games/testfiles/test.bin"
+    )));
+    let mut encoder =
+        crate::games::serialization::Encoder::start(File::create(&archive).unwrap()).unwrap();
+    for raw in Decoder::start(test_bin).unwrap().raw_iter().take(6) {
+        encoder
+            .write_game(&bincode::deserialize(&raw).unwrap())
+            .unwrap();
+    }
+    encoder.finish().unwrap();
+
+    let encoder = super::encoder::encoder("v1+state").unwrap();
+    let options = EncodeOptions::default();
+    let convert = |name: &str, dedup: &DedupOptions| {
+        let out_dir = dir.join(name);
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let mut tracker = ProgressTracker::new();
+        let shards = convert_games_dedup_and_save(
+            &archive,
+            &out_dir,
+            &*encoder,
+            &options,
+            dedup,
+            &mut tracker,
+        )
+        .unwrap();
+        assert!(!out_dir.join("runs.tmp").exists());
+        let data: Vec<TrainData> = (0..shards)
+            .map(|i| TrainData::load(&out_dir.join(format!("{:03}.bin", i))).unwrap())
+            .collect();
+        (data, tracker.progress().positions)
+    };
+
+    let (in_memory, positions) = convert("memory", &DedupOptions::default());
+    assert_eq!(in_memory.len(), 1);
+    let data = &in_memory[0];
+    let weights = data.weights.as_ref().unwrap();
+    // Every game passes through the position after its first move
+    assert!(data.len() < positions);
+    assert_eq!(weights.sum() as usize, positions);
+    for (outcome, &weight) in data.outs.outer_iter().zip(weights) {
+        assert!((outcome.sum() - 1.0).abs() < 1e-5);
+        assert!(weight >= 1.0);
+    }
+
+    // Spilling after every few positions gives the same positions, in more shards
+    let small = DedupOptions {
+        memory_positions: 50,
+        shard_positions: 40,
+    };
+    let (spilled, _) = convert("spilled", &small);
+    assert_eq!(spilled.len(), data.len().div_ceil(40));
+    let ins: Vec<_> = spilled.iter().flat_map(|d| d.ins.outer_iter()).collect();
+    let expected: Vec<_> = data.ins.outer_iter().collect();
+    assert_eq!(ins, expected);
+    let spilled_weights: Vec<f32> = spilled
+        .iter()
+        .flat_map(|d| d.weights.clone().unwrap())
+        .collect();
+    assert_eq!(spilled_weights, weights.to_vec());
+
+    let policy = EncodeOptions {
+        policy: true,
+        ..Default::default()
+    };
+    let mut tracker = ProgressTracker::new();
+    let err = convert_games_dedup_and_save(
+        &archive,
+        &dir.join("policy"),
+        &*encoder,
+        &policy,
+        &DedupOptions::default(),
+        &mut tracker,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("Policy targets"));
+}
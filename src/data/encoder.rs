@@ -0,0 +1,234 @@
+//! Feature encoders turning positions into the planes of the network input.
+//!
+//! Encoders are looked up by name with [`encoder`], the name is recorded in the header
+//! of every training data shard so data and models stay matched. Options are appended
+//! to the name of a registered encoder with `+`, e.g. `v1+state+relative`.
+
+use super::NNInput;
+use eyre::{bail, ensure, Result};
+use ndarray::{Array3, ArrayViewMut3};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, EnPassantMode, Position};
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+mod history;
+mod relative;
+mod state;
+mod v1;
+
+pub use history::HistoryPlanes;
+pub(crate) use relative::relative_plane_name;
+pub use relative::{mirror, Relative};
+pub use state::StatePlanes;
+pub use v1::V1Encoder;
+
+/// Name of the encoder used when none is given.
+pub const DEFAULT_ENCODER: &str = "v1";
+
+/// Options which can be appended to an encoder name, in the order they are applied.
+const OPTIONS: [&str; 3] = ["state", "history", "relative"];
+
+/// A position together with the positions before it in its game.
+#[derive(Debug, Clone, Copy)]
+pub struct GamePosition<'a> {
+    pub pos: &'a Chess,
+    /// Earlier positions of the game, oldest first. Empty if they are unknown.
+    pub history: &'a [Chess],
+}
+
+impl<'a> GamePosition<'a> {
+    /// A position without known history.
+    pub fn new(pos: &'a Chess) -> Self {
+        Self { pos, history: &[] }
+    }
+
+    pub fn with_history(pos: &'a Chess, history: &'a [Chess]) -> Self {
+        Self { pos, history }
+    }
+
+    /// The last `plies` positions before this one, fewer if the history is shorter.
+    pub fn last(&self, plies: usize) -> &'a [Chess] {
+        &self.history[self.history.len().saturating_sub(plies)..]
+    }
+
+    /// The positions since the last capture or pawn move, as counted by the halfmove
+    /// clock. Only these can be repetitions of the position.
+    pub fn reversible_history(&self) -> &'a [Chess] {
+        self.last(self.pos.halfmoves() as usize)
+    }
+
+    /// How often the position occurred before in the game.
+    pub fn repetitions(&self) -> usize {
+        let hash: Zobrist64 = self.pos.zobrist_hash(EnPassantMode::Legal);
+        self.reversible_history()
+            .iter()
+            .filter(|pos| pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal) == hash)
+            .count()
+    }
+}
+
+/// Encodes positions into feature planes.
+pub trait FeatureEncoder: Send + Sync {
+    /// Name of the encoder including its options, as accepted by [`encoder`].
+    fn name(&self) -> &str;
+
+    /// Names of the planes, in the order of the last axis of an encoded position.
+    fn plane_names(&self) -> Vec<String>;
+
+    fn planes(&self) -> usize {
+        self.plane_names().len()
+    }
+
+    /// Whether positions are encoded from the perspective of the side to move, their
+    /// outcome labels then are too.
+    fn relative(&self) -> bool {
+        false
+    }
+
+    /// Number of earlier positions whose planes are part of the encoding, apart from
+    /// those checked for repetitions.
+    fn history(&self) -> usize {
+        0
+    }
+
+    /// Writes the planes of `pos` into `out`, which is zeroed and of shape
+    /// `[8][8][planes]`, indexed `[file][7 - rank][plane]`.
+    fn encode_into(&self, pos: &GamePosition<'_>, out: ArrayViewMut3<'_, f32>);
+}
+
+impl dyn FeatureEncoder + '_ {
+    pub fn encode(&self, pos: &GamePosition<'_>) -> NNInput {
+        let mut encoded = Array3::zeros((8, 8, self.planes()));
+        self.encode_into(pos, encoded.view_mut());
+        encoded
+    }
+}
+
+fn registry() -> &'static RwLock<BTreeMap<String, Arc<dyn FeatureEncoder>>> {
+    static REGISTRY: OnceLock<RwLock<BTreeMap<String, Arc<dyn FeatureEncoder>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtin: [Arc<dyn FeatureEncoder>; 1] = [Arc::new(V1Encoder)];
+        let encoders = builtin
+            .into_iter()
+            .map(|e| (e.name().to_string(), e))
+            .collect();
+        RwLock::new(encoders)
+    })
+}
+
+/// Looks up a registered encoder by name, with the options appended to it.
+///
+/// Options are applied in a fixed order, so `encoder(name).name()` is the same for any
+/// order of the options in `name`:
+/// - `state`: adds [`StatePlanes`]
+/// - `history=N`: adds the pieces of the last `N` positions with [`HistoryPlanes`]
+/// - `relative`: encodes from the side to move's perspective with [`Relative`]
+pub fn encoder(name: &str) -> Result<Arc<dyn FeatureEncoder>> {
+    let mut parts = name.split('+');
+    let base = parts.next().unwrap_or_default();
+    let mut options: Vec<(usize, &str, Option<&str>)> = Vec::new();
+    for option in parts {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        };
+        match OPTIONS.iter().position(|&o| o == key) {
+            Some(order) => {
+                ensure!(
+                    options.iter().all(|&(_, o, _)| o != key),
+                    "Feature encoder option {:?} is given twice in {:?}",
+                    key,
+                    name
+                );
+                options.push((order, key, value));
+            }
+            None => bail!(
+                "Unknown feature encoder option {:?} in {:?}, expected one of {:?}",
+                option,
+                name,
+                OPTIONS
+            ),
+        }
+    }
+    options.sort();
+
+    let mut encoder = match registry().read().unwrap().get(base) {
+        Some(encoder) => Arc::clone(encoder),
+        None => bail!(
+            "Unknown feature encoder {:?}, expected one of {:?}",
+            base,
+            encoder_names()
+        ),
+    };
+    for (_, option, value) in options {
+        encoder = match (option, value) {
+            ("state", None) => Arc::new(StatePlanes::new(encoder)),
+            ("history", Some(plies)) => match plies.parse::<usize>() {
+                Ok(plies) if plies > 0 => Arc::new(HistoryPlanes::new(encoder, plies)),
+                _ => bail!(
+                    "Feature encoder option history takes a positive number of plies, got {:?}",
+                    plies
+                ),
+            },
+            ("relative", None) => Arc::new(Relative::new(encoder)),
+            ("history", None) => {
+                bail!("Feature encoder option history needs a value, e.g. history=8")
+            }
+            (option, Some(_)) => bail!("Feature encoder option {:?} takes no value", option),
+            _ => unreachable!("options are checked above"),
+        };
+    }
+    Ok(encoder)
+}
+
+/// Names of the registered encoders, sorted.
+pub fn encoder_names() -> Vec<String> {
+    registry().read().unwrap().keys().cloned().collect()
+}
+
+/// Adds an encoder to the registry, its name must not be taken yet.
+pub fn register_encoder(encoder: Arc<dyn FeatureEncoder>) -> Result<()> {
+    ensure!(
+        !encoder.name().contains('+'),
+        "Feature encoder names can't contain '+', it separates options"
+    );
+    let mut registry = registry().write().unwrap();
+    if registry.contains_key(encoder.name()) {
+        bail!(
+            "A feature encoder named {:?} is already registered",
+            encoder.name()
+        );
+    }
+    registry.insert(encoder.name().to_string(), encoder);
+    Ok(())
+}
+
+#[test]
+fn test_encoder_registry() {
+    let v1 = encoder(DEFAULT_ENCODER).unwrap();
+    assert_eq!(v1.planes(), 37);
+    assert!(encoder("v0").is_err());
+    assert!(encoder("v1+unknown").is_err());
+    assert!(encoder("v1+state+state").is_err());
+    assert!(register_encoder(Arc::new(V1Encoder)).is_err());
+    assert!(encoder_names().contains(&"v1".to_string()));
+
+    let state = encoder("v1+state").unwrap();
+    assert_eq!(state.name(), "v1+state");
+    assert_eq!(state.planes(), 37 + 9);
+    assert_eq!(state.plane_names()[..37], v1.plane_names()[..]);
+
+    let relative = encoder("v1+relative+state").unwrap();
+    assert_eq!(relative.name(), "v1+state+relative");
+    assert!(relative.relative() && !state.relative());
+    assert_eq!(relative.plane_names()[37], "own_kingside_castling");
+
+    let history = encoder("v1+relative+history=4").unwrap();
+    assert_eq!(history.name(), "v1+history=4+relative");
+    assert_eq!(history.history(), 4);
+    assert_eq!(history.planes(), 37 + 4 * 12);
+    assert!(encoder("v1+history").is_err());
+    assert!(encoder("v1+history=0").is_err());
+    assert!(encoder("v1+state=1").is_err());
+}
@@ -0,0 +1,99 @@
+use super::v1::V1_PLANES;
+use super::{FeatureEncoder, GamePosition};
+use ndarray::{s, ArrayViewMut3};
+use shakmaty::{Color, Piece, Position, Role};
+use std::sync::Arc;
+
+/// Appends the pieces of the `plies` positions before the encoded one to the planes of
+/// another encoder, 12 planes per position from the most recent one back. Planes of
+/// positions before the start of the game, or before the known history, stay zero.
+#[derive(Clone)]
+pub struct HistoryPlanes {
+    inner: Arc<dyn FeatureEncoder>,
+    plies: usize,
+    name: String,
+}
+
+impl HistoryPlanes {
+    pub fn new(inner: Arc<dyn FeatureEncoder>, plies: usize) -> Self {
+        let name = format!("{}+history={}", inner.name(), plies);
+        Self { inner, plies, name }
+    }
+}
+
+fn pieces() -> impl Iterator<Item = Piece> {
+    Color::ALL
+        .into_iter()
+        .flat_map(|color| Role::ALL.into_iter().map(move |role| Piece { color, role }))
+}
+
+impl FeatureEncoder for HistoryPlanes {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn plane_names(&self) -> Vec<String> {
+        let mut names = self.inner.plane_names();
+        for ply in 1..=self.plies {
+            // The first planes of v1 are the pieces in the same order
+            names.extend(
+                V1_PLANES[..12]
+                    .iter()
+                    .map(|piece| format!("{}_{}", piece, ply)),
+            );
+        }
+        names
+    }
+
+    fn planes(&self) -> usize {
+        self.inner.planes() + 12 * self.plies
+    }
+
+    fn relative(&self) -> bool {
+        self.inner.relative()
+    }
+
+    fn history(&self) -> usize {
+        self.plies.max(self.inner.history())
+    }
+
+    fn encode_into(&self, pos: &GamePosition<'_>, mut out: ArrayViewMut3<'_, f32>) {
+        let offset = self.inner.planes();
+        self.inner
+            .encode_into(pos, out.slice_mut(s![.., .., ..offset]));
+
+        for (ago, earlier) in pos.last(self.plies).iter().rev().enumerate() {
+            let board = earlier.board();
+            for (i, piece) in pieces().enumerate() {
+                let plane = offset + 12 * ago + i;
+                for sq in board.by_piece(piece) {
+                    let (x, y): (usize, usize) =
+                        (sq.file().into(), 7 - Into::<usize>::into(sq.rank()));
+                    out[[x, y, plane]] = 1.0;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_history_planes() {
+    use shakmaty::uci::UciMove;
+    use shakmaty::Chess;
+
+    let encoder = HistoryPlanes::new(Arc::new(super::V1Encoder), 2);
+    let encoder: &dyn FeatureEncoder = &encoder;
+    assert_eq!(encoder.planes(), 37 + 24);
+    assert_eq!(encoder.plane_names()[37], "white_pawn_1");
+    assert_eq!(encoder.plane_names()[37 + 23], "black_king_2");
+
+    let start = Chess::default();
+    let m = "e2e4".parse::<UciMove>().unwrap().to_move(&start).unwrap();
+    let after = start.clone().play(&m).unwrap();
+    let history = [start];
+    let encoded = encoder.encode(&GamePosition::with_history(&after, &history));
+    // One ply ago the e pawn was still on e2, two plies ago is before the game
+    assert_eq!(encoded[[4, 6, 37]], 1.0);
+    assert_eq!(encoded[[4, 4, 37]], 0.0);
+    assert_eq!(encoded.slice(s![.., .., 37 + 12..]).sum(), 0.0);
+}
@@ -0,0 +1,107 @@
+use super::{FeatureEncoder, GamePosition};
+use ndarray::ArrayViewMut3;
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
+use std::sync::Arc;
+
+/// Encodes positions from the perspective of the side to move. With black to move the
+/// position is mirrored vertically and its colors are swapped before it is handed to the
+/// inner encoder, so the mover's pieces always take the planes of white's and move up the
+/// board. Plane names say `own` and `opponent` instead of `white` and `black`.
+///
+/// Outcome labels of positions encoded this way are `[win, draw, loss]` of the side to
+/// move, see [`crate::data::encode_outcome`].
+#[derive(Clone)]
+pub struct Relative {
+    inner: Arc<dyn FeatureEncoder>,
+    name: String,
+}
+
+impl Relative {
+    pub fn new(inner: Arc<dyn FeatureEncoder>) -> Self {
+        let name = format!("{}+relative", inner.name());
+        Self { inner, name }
+    }
+}
+
+/// The same position with colors swapped, it is legal if `pos` is.
+pub fn mirror(pos: &Chess) -> Chess {
+    pos.clone()
+        .into_setup(EnPassantMode::Legal)
+        .into_mirrored()
+        .position(CastlingMode::Standard)
+        .expect("mirrored position is legal")
+}
+
+pub(crate) fn relative_plane_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| match word {
+            "white" => "own",
+            "black" => "opponent",
+            word => word,
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+impl FeatureEncoder for Relative {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn plane_names(&self) -> Vec<String> {
+        self.inner
+            .plane_names()
+            .iter()
+            .map(|name| relative_plane_name(name))
+            .collect()
+    }
+
+    fn planes(&self) -> usize {
+        self.inner.planes()
+    }
+
+    fn relative(&self) -> bool {
+        true
+    }
+
+    fn history(&self) -> usize {
+        self.inner.history()
+    }
+
+    fn encode_into(&self, pos: &GamePosition<'_>, out: ArrayViewMut3<'_, f32>) {
+        if pos.pos.turn() == Color::White {
+            return self.inner.encode_into(pos, out);
+        }
+        let mirrored = mirror(pos.pos);
+        // Only the positions the encoding can depend on are mirrored
+        let plies = (pos.pos.halfmoves() as usize).max(self.inner.history());
+        let history: Vec<Chess> = pos.last(plies).iter().map(mirror).collect();
+        self.inner
+            .encode_into(&GamePosition::with_history(&mirrored, &history), out);
+    }
+}
+
+#[test]
+fn test_relative() {
+    use ndarray::s;
+    use shakmaty::uci::UciMove;
+
+    let encoder = Relative::new(Arc::new(super::V1Encoder));
+    let encoder: &dyn FeatureEncoder = &encoder;
+    assert_eq!(encoder.plane_names()[0], "own_pawn");
+    assert_eq!(encoder.plane_names()[24], "attacked_by_opponent_king");
+
+    let start = Chess::default();
+    let m = "e2e4".parse::<UciMove>().unwrap().to_move(&start).unwrap();
+    let after = start.clone().play(&m).unwrap();
+    let white = encoder.encode(&GamePosition::new(&start));
+    let black = encoder.encode(&GamePosition::new(&after));
+    assert_eq!(
+        white,
+        (&super::V1Encoder as &dyn FeatureEncoder).encode(&GamePosition::new(&start))
+    );
+    // Black's pawns are on the second rank from its side, white's e pawn on e5 from there
+    assert_eq!(black.slice(s![.., 6, 0]).sum(), 8.0);
+    assert_eq!(black[[4, 3, 6]], 1.0);
+    assert_eq!(black[[0, 0, 12]], 1.0);
+}
@@ -0,0 +1,136 @@
+use super::{FeatureEncoder, GamePosition};
+use ndarray::{s, ArrayViewMut3};
+use shakmaty::{CastlingSide, Color, EnPassantMode, Position};
+use std::sync::Arc;
+
+const STATE_PLANES: [&str; 9] = [
+    "white_kingside_castling",
+    "white_queenside_castling",
+    "black_kingside_castling",
+    "black_queenside_castling",
+    "en_passant",
+    "halfmove_clock",
+    "fullmove_number",
+    "repeated_once",
+    "repeated_twice",
+];
+
+/// Fullmove number encoded as 1, later moves are clamped.
+const MAX_FULLMOVES: f32 = 200.0;
+
+/// Appends the game state which is not visible on the board to the planes of another
+/// encoder:
+/// - castling rights, a plane of ones per right still held
+/// - the square a pawn can capture onto en passant, if that capture is legal
+/// - the halfmove clock divided by 100, reaching 1 when the fifty-move rule applies
+/// - the fullmove number divided by 200, clamped to 1
+/// - whether the position occurred once, or twice or more, before in the game
+#[derive(Clone)]
+pub struct StatePlanes {
+    inner: Arc<dyn FeatureEncoder>,
+    name: String,
+}
+
+impl StatePlanes {
+    pub fn new(inner: Arc<dyn FeatureEncoder>) -> Self {
+        let name = format!("{}+state", inner.name());
+        Self { inner, name }
+    }
+}
+
+impl FeatureEncoder for StatePlanes {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn plane_names(&self) -> Vec<String> {
+        let mut names = self.inner.plane_names();
+        names.extend(STATE_PLANES.iter().map(|name| name.to_string()));
+        names
+    }
+
+    fn planes(&self) -> usize {
+        self.inner.planes() + STATE_PLANES.len()
+    }
+
+    fn relative(&self) -> bool {
+        self.inner.relative()
+    }
+
+    fn history(&self) -> usize {
+        self.inner.history()
+    }
+
+    fn encode_into(&self, pos: &GamePosition<'_>, mut out: ArrayViewMut3<'_, f32>) {
+        let offset = self.inner.planes();
+        self.inner
+            .encode_into(pos, out.slice_mut(s![.., .., ..offset]));
+        let mut state = out.slice_mut(s![.., .., offset..]);
+
+        let castles = pos.pos.castles();
+        let rights = [
+            (Color::White, CastlingSide::KingSide),
+            (Color::White, CastlingSide::QueenSide),
+            (Color::Black, CastlingSide::KingSide),
+            (Color::Black, CastlingSide::QueenSide),
+        ];
+        for (plane, (color, side)) in rights.into_iter().enumerate() {
+            if castles.has(color, side) {
+                state.slice_mut(s![.., .., plane]).fill(1.0);
+            }
+        }
+        if let Some(sq) = pos.pos.ep_square(EnPassantMode::Legal) {
+            let (x, y): (usize, usize) = (sq.file().into(), 7 - Into::<usize>::into(sq.rank()));
+            state[[x, y, 4]] = 1.0;
+        }
+        let halfmoves = (pos.pos.halfmoves() as f32 / 100.0).min(1.0);
+        state.slice_mut(s![.., .., 5]).fill(halfmoves);
+        let fullmoves = (pos.pos.fullmoves().get() as f32 / MAX_FULLMOVES).min(1.0);
+        state.slice_mut(s![.., .., 6]).fill(fullmoves);
+        match pos.repetitions() {
+            0 => {}
+            1 => state.slice_mut(s![.., .., 7]).fill(1.0),
+            _ => state.slice_mut(s![.., .., 8]).fill(1.0),
+        }
+    }
+}
+
+#[test]
+fn test_state_planes() {
+    use shakmaty::uci::UciMove;
+    use shakmaty::Chess;
+
+    let encoder = StatePlanes::new(Arc::new(super::V1Encoder));
+    let encoder: &dyn FeatureEncoder = &encoder;
+    let offset = encoder.planes() - STATE_PLANES.len();
+
+    // Knights out and back twice, then a double pawn push next to an enemy pawn
+    let moves = [
+        "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8", "e2e4", "d7d5", "e4e5",
+        "f7f5",
+    ];
+    let mut history = vec![Chess::default()];
+    for uci in moves {
+        let pos = history.last().unwrap();
+        let m = uci.parse::<UciMove>().unwrap().to_move(pos).unwrap();
+        let mut next = pos.clone();
+        next.play_unchecked(&m);
+        history.push(next);
+    }
+
+    let (start_again, earlier) = history[..9].split_last().unwrap();
+    let encoded = encoder.encode(&GamePosition::with_history(start_again, earlier));
+    assert_eq!(encoded[[0, 0, offset + 8]], 1.0);
+    assert_eq!(encoded[[0, 0, offset + 7]], 0.0);
+    assert_eq!(encoded[[3, 3, offset + 5]], 8.0 / 100.0);
+    assert!((0..4).all(|p| encoded[[0, 0, offset + p]] == 1.0));
+
+    let (last, earlier) = history.split_last().unwrap();
+    let encoded = encoder.encode(&GamePosition::with_history(last, earlier));
+    // e5 can take f5 en passant onto f6
+    assert_eq!(encoded[[5, 2, offset + 4]], 1.0);
+    assert_eq!(encoded.slice(s![.., .., offset + 4]).sum(), 1.0);
+    assert_eq!(encoded[[0, 0, offset + 5]], 0.0);
+    assert_eq!(encoded[[0, 0, offset + 6]], 7.0 / MAX_FULLMOVES);
+    assert_eq!(encoded.slice(s![.., .., offset + 7..]).sum(), 0.0);
+}
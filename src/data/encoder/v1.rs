@@ -0,0 +1,199 @@
+use super::{FeatureEncoder, GamePosition};
+use ndarray::ArrayViewMut3;
+use shakmaty::{Color, File, Position, Rank, Role, Square};
+
+const F_W_PAWN: usize = 0;
+const F_W_KNIGHT: usize = 1;
+const F_W_BISHOP: usize = 2;
+const F_W_ROOK: usize = 3;
+const F_W_QUEEN: usize = 4;
+const F_W_KING: usize = 5;
+const F_B_PAWN: usize = 6;
+const F_B_KNIGHT: usize = 7;
+const F_B_BISHOP: usize = 8;
+const F_B_ROOK: usize = 9;
+const F_B_QUEEN: usize = 10;
+const F_B_KING: usize = 11;
+const F_TURN: usize = 12;
+const F_ATTACKED_BY_W_PAWN: usize = 13;
+const F_ATTACKED_BY_W_KNIGHT: usize = 14;
+const F_ATTACKED_BY_W_BISHOP: usize = 15;
+const F_ATTACKED_BY_W_ROOK: usize = 16;
+const F_ATTACKED_BY_W_QUEEN: usize = 17;
+const F_ATTACKED_BY_W_KING: usize = 18;
+const F_ATTACKED_BY_B_PAWN: usize = 19;
+const F_ATTACKED_BY_B_KNIGHT: usize = 20;
+const F_ATTACKED_BY_B_BISHOP: usize = 21;
+const F_ATTACKED_BY_B_ROOK: usize = 22;
+const F_ATTACKED_BY_B_QUEEN: usize = 23;
+const F_ATTACKED_BY_B_KING: usize = 24;
+const F_ACCESSIBLE_BY_W_PAWN: usize = 25;
+const F_ACCESSIBLE_BY_W_KNIGHT: usize = 26;
+const F_ACCESSIBLE_BY_W_BISHOP: usize = 27;
+const F_ACCESSIBLE_BY_W_ROOK: usize = 28;
+const F_ACCESSIBLE_BY_W_QUEEN: usize = 29;
+const F_ACCESSIBLE_BY_W_KING: usize = 30;
+const F_ACCESSIBLE_BY_B_PAWN: usize = 31;
+const F_ACCESSIBLE_BY_B_KNIGHT: usize = 32;
+const F_ACCESSIBLE_BY_B_BISHOP: usize = 33;
+const F_ACCESSIBLE_BY_B_ROOK: usize = 34;
+const F_ACCESSIBLE_BY_B_QUEEN: usize = 35;
+const F_ACCESSIBLE_BY_B_KING: usize = 36;
+
+pub(super) const V1_PLANES: [&str; 37] = [
+    "white_pawn",
+    "white_knight",
+    "white_bishop",
+    "white_rook",
+    "white_queen",
+    "white_king",
+    "black_pawn",
+    "black_knight",
+    "black_bishop",
+    "black_rook",
+    "black_queen",
+    "black_king",
+    "turn",
+    "attacked_by_white_pawn",
+    "attacked_by_white_knight",
+    "attacked_by_white_bishop",
+    "attacked_by_white_rook",
+    "attacked_by_white_queen",
+    "attacked_by_white_king",
+    "attacked_by_black_pawn",
+    "attacked_by_black_knight",
+    "attacked_by_black_bishop",
+    "attacked_by_black_rook",
+    "attacked_by_black_queen",
+    "attacked_by_black_king",
+    "accessible_by_white_pawn",
+    "accessible_by_white_knight",
+    "accessible_by_white_bishop",
+    "accessible_by_white_rook",
+    "accessible_by_white_queen",
+    "accessible_by_white_king",
+    "accessible_by_black_pawn",
+    "accessible_by_black_knight",
+    "accessible_by_black_bishop",
+    "accessible_by_black_rook",
+    "accessible_by_black_queen",
+    "accessible_by_black_king",
+];
+
+/// The original 37 planes: pieces, side to move (1 for white, -1 for black), attacked
+/// squares and legal move destinations, each split by color and piece type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct V1Encoder;
+
+impl FeatureEncoder for V1Encoder {
+    fn name(&self) -> &str {
+        "v1"
+    }
+
+    fn plane_names(&self) -> Vec<String> {
+        V1_PLANES.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn planes(&self) -> usize {
+        V1_PLANES.len()
+    }
+
+    fn encode_into(&self, pos: &GamePosition<'_>, mut encoded: ArrayViewMut3<'_, f32>) {
+        let chess = pos.pos;
+        let board = chess.board();
+        let turn_value = match chess.turn() {
+            Color::Black => -1.0,
+            Color::White => 1.0,
+        };
+
+        for move_ in chess.legal_moves() {
+            let piece = board.piece_at(move_.from().unwrap()).unwrap();
+            let filter = match piece.color {
+                Color::White => match piece.role {
+                    Role::Pawn => F_ACCESSIBLE_BY_W_PAWN,
+                    Role::Knight => F_ACCESSIBLE_BY_W_KNIGHT,
+                    Role::Bishop => F_ACCESSIBLE_BY_W_BISHOP,
+                    Role::Rook => F_ACCESSIBLE_BY_W_ROOK,
+                    Role::Queen => F_ACCESSIBLE_BY_W_QUEEN,
+                    Role::King => F_ACCESSIBLE_BY_W_KING,
+                },
+                Color::Black => match piece.role {
+                    Role::Pawn => F_ACCESSIBLE_BY_B_PAWN,
+                    Role::Knight => F_ACCESSIBLE_BY_B_KNIGHT,
+                    Role::Bishop => F_ACCESSIBLE_BY_B_BISHOP,
+                    Role::Rook => F_ACCESSIBLE_BY_B_ROOK,
+                    Role::Queen => F_ACCESSIBLE_BY_B_QUEEN,
+                    Role::King => F_ACCESSIBLE_BY_B_KING,
+                },
+            };
+            let (x, y) = (
+                move_.to().file().into(),
+                7 - Into::<usize>::into(move_.to().rank()),
+            );
+            encoded[[x, y, filter]] = 1.0;
+        }
+
+        for x in 0..8 {
+            for y in 0..8 {
+                encoded[[x, y, F_TURN]] = turn_value;
+                let square = Square::from_coords(File::new(x as u32), Rank::new(7 - (y as u32)));
+                if let Some(piece) = board.piece_at(square) {
+                    match piece.color {
+                        Color::White => match piece.role {
+                            Role::Pawn => encoded[[x, y, F_W_PAWN]] = 1.0,
+                            Role::Knight => encoded[[x, y, F_W_KNIGHT]] = 1.0,
+                            Role::Bishop => encoded[[x, y, F_W_BISHOP]] = 1.0,
+                            Role::Rook => encoded[[x, y, F_W_ROOK]] = 1.0,
+                            Role::Queen => encoded[[x, y, F_W_QUEEN]] = 1.0,
+                            Role::King => encoded[[x, y, F_W_KING]] = 1.0,
+                        },
+                        Color::Black => match piece.role {
+                            Role::Pawn => encoded[[x, y, F_B_PAWN]] = 1.0,
+                            Role::Knight => encoded[[x, y, F_B_KNIGHT]] = 1.0,
+                            Role::Bishop => encoded[[x, y, F_B_BISHOP]] = 1.0,
+                            Role::Rook => encoded[[x, y, F_B_ROOK]] = 1.0,
+                            Role::Queen => encoded[[x, y, F_B_QUEEN]] = 1.0,
+                            Role::King => encoded[[x, y, F_B_KING]] = 1.0,
+                        },
+                    }
+                    let mut attacks_bb = board.attacks_from(square);
+                    let attack_filter = match piece.color {
+                        Color::White => match piece.role {
+                            Role::Pawn => F_ATTACKED_BY_W_PAWN,
+                            Role::Knight => F_ATTACKED_BY_W_KNIGHT,
+                            Role::Bishop => F_ATTACKED_BY_W_BISHOP,
+                            Role::Rook => F_ATTACKED_BY_W_ROOK,
+                            Role::Queen => F_ATTACKED_BY_W_QUEEN,
+                            Role::King => F_ATTACKED_BY_W_KING,
+                        },
+                        Color::Black => match piece.role {
+                            Role::Pawn => F_ATTACKED_BY_B_PAWN,
+                            Role::Knight => F_ATTACKED_BY_B_KNIGHT,
+                            Role::Bishop => F_ATTACKED_BY_B_BISHOP,
+                            Role::Rook => F_ATTACKED_BY_B_ROOK,
+                            Role::Queen => F_ATTACKED_BY_B_QUEEN,
+                            Role::King => F_ATTACKED_BY_B_KING,
+                        },
+                    };
+                    while let Some(sq) = attacks_bb.pop_front() {
+                        let (x, y) = (sq.file().into(), 7 - Into::<usize>::into(sq.rank()));
+                        encoded[[x, y, attack_filter]] = 1.0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_v1_encoder() {
+    use shakmaty::Chess;
+
+    let encoded = (&V1Encoder as &dyn FeatureEncoder).encode(&GamePosition::new(&Chess::default()));
+    // x is the file and y counts ranks from the top, white to move
+    assert_eq!(encoded[[4, 6, F_W_PAWN]], 1.0);
+    assert_eq!(encoded[[4, 1, F_B_PAWN]], 1.0);
+    assert_eq!(encoded[[0, 0, F_TURN]], 1.0);
+    assert_eq!(encoded[[4, 4, F_ACCESSIBLE_BY_W_PAWN]], 1.0);
+    assert_eq!(V1Encoder.plane_names()[F_TURN], "turn");
+}
@@ -0,0 +1,336 @@
+//! Policy targets: the move played in a position as an index into a fixed vocabulary.
+//!
+//! Moves are numbered as in AlphaZero's 8x8x73 scheme, `73 * from + plane` where `from`
+//! is the origin square `file + 8 * rank` and the plane tells how the piece moves:
+//! - 0..56: queen-like moves, 7 distances in each of the directions N, NE, E, SE, S,
+//!   SW, W and NW. Pawn moves and promotions to a queen are among them.
+//! - 56..64: knight moves
+//! - 64..73: promotions to a knight, bishop or rook, each with the file offsets -1, 0, 1
+//!
+//! Castling is the king moving two squares. For relative encoders moves are flipped along
+//! with the board, so white's moves from the second rank and black's from the seventh
+//! share indices. Underpromotions move towards the last rank of the side to move, which
+//! is why decoding an index needs to know it.
+
+use crate::games::game;
+use eyre::{ensure, Result};
+use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1};
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Color, File, Move, Position, Rank, Role, Square};
+
+/// Name of the move vocabulary, recorded in the header of training data.
+pub const POLICY_VOCABULARY: &str = "8x8x73";
+/// Number of moves in the vocabulary.
+pub const POLICY_SIZE: usize = 64 * 73;
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const KNIGHT_MOVES: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const UNDERPROMOTIONS: [Role; 3] = [Role::Knight, Role::Bishop, Role::Rook];
+
+/// Policy targets of a batch of positions, row `i` belongs to position `i`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// Vocabulary index of the move played next, -1 after the last move of a game.
+    pub moves: Array1<i32>,
+    /// Mask of the legal moves of each position, of shape `[positions][POLICY_SIZE]`.
+    pub legal: Array2<bool>,
+}
+
+impl Policy {
+    pub fn empty() -> Self {
+        Self {
+            moves: Array1::zeros(0),
+            legal: Array2::from_elem((0, POLICY_SIZE), false),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Vocabulary index of a UCI move, with its squares mirrored vertically if `flip` is set.
+/// Castling is given by the king's destination, e.g. `e1g1`. Returns `None` for moves no
+/// piece can make, such as `a1b3q`.
+pub fn uci_index(uci: &UciMove, flip: bool) -> Option<usize> {
+    let (from, to, promotion) = match *uci {
+        UciMove::Normal {
+            from,
+            to,
+            promotion,
+        } => (from, to, promotion),
+        _ => return None,
+    };
+    let (from, to) = if flip {
+        (from.flip_vertical(), to.flip_vertical())
+    } else {
+        (from, to)
+    };
+    let dx = i32::from(to.file()) - i32::from(from.file());
+    let dy = i32::from(to.rank()) - i32::from(from.rank());
+
+    let plane = match promotion {
+        Some(Role::Queen) | None => {
+            if let Some(knight) = KNIGHT_MOVES.iter().position(|&d| d == (dx, dy)) {
+                (promotion.is_none()).then_some(56 + knight)?
+            } else {
+                let distance = dx.abs().max(dy.abs());
+                let direction = DIRECTIONS
+                    .iter()
+                    .position(|&d| d == (dx.signum(), dy.signum()))?;
+                if dx != 0 && dy != 0 && dx.abs() != dy.abs() {
+                    return None;
+                }
+                7 * direction + (distance - 1) as usize
+            }
+        }
+        Some(role) => {
+            let piece = UNDERPROMOTIONS.iter().position(|&r| r == role)?;
+            if dx.abs() > 1 || dy.abs() != 1 {
+                return None;
+            }
+            64 + 3 * piece + (dx + 1) as usize
+        }
+    };
+    Some(73 * usize::from(from) + plane)
+}
+
+/// Vocabulary index of a move of standard chess, with its squares mirrored vertically if
+/// `flip` is set.
+pub fn move_index(move_: &Move, flip: bool) -> usize {
+    uci_index(&move_.to_uci(CastlingMode::Standard), flip)
+        .expect("moves of standard chess are in the vocabulary")
+}
+
+/// The UCI move of a vocabulary index when `turn` is to move, with squares mirrored back
+/// if `flip` is set. Returns `None` for indices leaving the board.
+///
+/// Moves onto the last rank are returned without promotion, unless they underpromote.
+/// Whether a queen promotion is meant depends on the piece moving, see [`rank_moves`].
+pub fn index_uci(index: usize, turn: Color, flip: bool) -> Option<UciMove> {
+    if index >= POLICY_SIZE {
+        return None;
+    }
+    let from = Square::new((index / 73) as u32);
+    let plane = index % 73;
+    // Underpromotions move towards the promotion rank of the side to move, in the
+    // orientation of the index
+    let forward = if flip || turn == Color::White { 1 } else { -1 };
+    let ((dx, dy), promotion) = match plane {
+        0..=55 => {
+            let (dx, dy) = DIRECTIONS[plane / 7];
+            let distance = (plane % 7 + 1) as i32;
+            ((dx * distance, dy * distance), None)
+        }
+        56..=63 => (KNIGHT_MOVES[plane - 56], None),
+        _ => {
+            let (piece, dx) = ((plane - 64) / 3, (plane - 64) % 3);
+            ((dx as i32 - 1, forward), Some(UNDERPROMOTIONS[piece]))
+        }
+    };
+    let file = i32::from(from.file()) + dx;
+    let rank = i32::from(from.rank()) + dy;
+    if !(0..8).contains(&file) || !(0..8).contains(&rank) {
+        return None;
+    }
+    let to = Square::from_coords(File::new(file as u32), Rank::new(rank as u32));
+    let (from, to) = if flip {
+        (from.flip_vertical(), to.flip_vertical())
+    } else {
+        (from, to)
+    };
+    Some(UciMove::Normal {
+        from,
+        to,
+        promotion,
+    })
+}
+
+/// Index of the same move with the board mirrored vertically, as for a color flipped
+/// position. Underpromotions keep their plane since it does not tell the direction.
+pub fn flip_index(index: usize) -> usize {
+    let (from, plane) = (Square::new((index / 73) as u32), index % 73);
+    let plane = match plane {
+        0..=55 => {
+            let (dx, dy) = DIRECTIONS[plane / 7];
+            let direction = DIRECTIONS.iter().position(|&d| d == (dx, -dy)).unwrap();
+            7 * direction + plane % 7
+        }
+        56..=63 => {
+            let (dx, dy) = KNIGHT_MOVES[plane - 56];
+            56 + KNIGHT_MOVES.iter().position(|&d| d == (dx, -dy)).unwrap()
+        }
+        _ => plane,
+    };
+    73 * usize::from(from.flip_vertical()) + plane
+}
+
+/// Index of the same move with the board mirrored horizontally, files a and h swapped.
+pub fn mirror_index(index: usize) -> usize {
+    let (from, plane) = (Square::new((index / 73) as u32), index % 73);
+    let plane = match plane {
+        0..=55 => {
+            let (dx, dy) = DIRECTIONS[plane / 7];
+            let direction = DIRECTIONS.iter().position(|&d| d == (-dx, dy)).unwrap();
+            7 * direction + plane % 7
+        }
+        56..=63 => {
+            let (dx, dy) = KNIGHT_MOVES[plane - 56];
+            56 + KNIGHT_MOVES.iter().position(|&d| d == (-dx, dy)).unwrap()
+        }
+        _ => 64 + 3 * ((plane - 64) / 3) + (2 - (plane - 64) % 3),
+    };
+    73 * usize::from(from.flip_horizontal()) + plane
+}
+
+/// The whole vocabulary for `turn` to move, see [`index_uci`].
+pub fn vocabulary(turn: Color, flip: bool) -> Vec<Option<UciMove>> {
+    (0..POLICY_SIZE)
+        .map(|index| index_uci(index, turn, flip))
+        .collect()
+}
+
+/// The legal moves of `pos` with their probabilities under a predicted distribution over
+/// the vocabulary, most likely first. Illegal moves are masked out and the probabilities
+/// of the legal ones renormalized to sum to 1, or spread evenly if they are all zero.
+pub fn rank_moves(
+    pos: &Chess,
+    probabilities: ArrayView1<'_, f32>,
+    flip: bool,
+) -> Result<Vec<(game::Move, f32)>> {
+    ensure!(
+        probabilities.len() == POLICY_SIZE,
+        "Expected {} move probabilities, got {}",
+        POLICY_SIZE,
+        probabilities.len()
+    );
+    let mut ranked: Vec<(game::Move, f32)> = pos
+        .legal_moves()
+        .iter()
+        .map(|m| {
+            (
+                game::Move::from(m),
+                probabilities[move_index(m, flip)].max(0.0),
+            )
+        })
+        .collect();
+    let total: f32 = ranked.iter().map(|(_, p)| p).sum();
+    let count = ranked.len() as f32;
+    for (_, p) in ranked.iter_mut() {
+        *p = if total > 0.0 { *p / total } else { 1.0 / count };
+    }
+    ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    Ok(ranked)
+}
+
+/// Sets the vocabulary indices of the legal moves of `pos` in `mask`.
+pub fn legal_moves_into(pos: &Chess, flip: bool, mut mask: ArrayViewMut1<'_, bool>) {
+    for move_ in pos.legal_moves() {
+        mask[move_index(&move_, flip)] = true;
+    }
+}
+
+#[test]
+fn test_move_index() {
+    let pos = Chess::default();
+    let index = |uci: &str, pos: &Chess, flip: bool| {
+        let move_ = uci.parse::<UciMove>().unwrap().to_move(pos).unwrap();
+        move_index(&move_, flip)
+    };
+    // e2e4 is two squares north from e2
+    assert_eq!(index("e2e4", &pos, false), 73 * 12 + 1);
+    assert_eq!(index("g1f3", &pos, false), 73 * 6 + 56 + 7);
+
+    // e7e5 mirrored is e2e4
+    let e4 = "e2e4".parse::<UciMove>().unwrap().to_move(&pos).unwrap();
+    let pos = pos.play(&e4).unwrap();
+    assert_eq!(index("e7e5", &pos, true), 73 * 12 + 1);
+
+    let mut mask = Array1::from_elem(POLICY_SIZE, false);
+    legal_moves_into(&pos, true, mask.view_mut());
+    assert_eq!(mask.iter().filter(|&&legal| legal).count(), 20);
+
+    let pos: Chess = "n3k3/1P6/8/8/8/8/8/4K2R w K - 0 1"
+        .parse::<shakmaty::fen::Fen>()
+        .unwrap()
+        .into_position(CastlingMode::Standard)
+        .unwrap();
+    assert_eq!(index("b7a8n", &pos, false), 73 * 49 + 64);
+    assert_eq!(index("b7b8q", &pos, false), 73 * 49);
+    assert_eq!(index("e1g1", &pos, false), 73 * 4 + 7 * 2 + 1);
+}
+
+#[test]
+fn test_vocabulary() {
+    for (turn, flip) in [
+        (Color::White, false),
+        (Color::Black, false),
+        (Color::Black, true),
+    ] {
+        let vocabulary = vocabulary(turn, flip);
+        for (index, uci) in vocabulary.iter().enumerate() {
+            if let Some(uci) = uci {
+                assert_eq!(uci_index(uci, flip), Some(index), "{}", uci);
+            }
+        }
+    }
+    // 1456 queen-like moves, 336 knight moves and 462 underpromotions stay on the board
+    assert_eq!(
+        vocabulary(Color::White, false).iter().flatten().count(),
+        2254
+    );
+    assert_eq!(uci_index(&"a1b3q".parse().unwrap(), false), None);
+    assert_eq!(
+        index_uci(73 * 49 + 64, Color::White, false),
+        Some("b7a8n".parse().unwrap())
+    );
+    assert_eq!(
+        index_uci(73 * 49 + 64, Color::Black, true),
+        Some("b2a1n".parse().unwrap())
+    );
+
+    let index = |uci: &str| uci_index(&uci.parse().unwrap(), false).unwrap();
+    assert_eq!(flip_index(index("g1f3")), index("g8f6"));
+    assert_eq!(flip_index(index("b2a1n")), index("b7a8n"));
+    assert_eq!(mirror_index(index("g1f3")), index("b1c3"));
+    assert_eq!(mirror_index(index("b7a8r")), index("g7h8r"));
+    for i in 0..POLICY_SIZE {
+        assert_eq!(flip_index(flip_index(i)), i);
+        assert_eq!(mirror_index(mirror_index(i)), i);
+    }
+
+    let pos = Chess::default();
+    let mut probabilities = Array1::zeros(POLICY_SIZE);
+    let e4 = "e2e4".parse::<UciMove>().unwrap();
+    probabilities[uci_index(&e4, false).unwrap()] = 0.3;
+    probabilities[uci_index(&"e1e2".parse().unwrap(), false).unwrap()] = 0.5;
+    probabilities[uci_index(&"d2d4".parse().unwrap(), false).unwrap()] = 0.1;
+    let ranked = rank_moves(&pos, probabilities.view(), false).unwrap();
+    assert_eq!(ranked.len(), 20);
+    assert_eq!(ranked[0].0.to_uci(), "e2e4");
+    assert_eq!(ranked[0].1, 0.75);
+    assert_eq!(ranked[2].1, 0.0);
+    assert!(rank_moves(&pos, probabilities.slice(ndarray::s![1..]), false).is_err());
+}
@@ -0,0 +1,161 @@
+//! Choice of the positions of a game which are encoded.
+//!
+//! Consecutive positions of a game are highly correlated and share their outcome label,
+//! and every game passes through the same openings. A [`Sampling`] thins them out: it skips
+//! the opening, keeps each position with some probability and caps the positions taken
+//! from one game. Its random choices are seeded by the moves of each game, so the same
+//! positions are chosen however games are spread over threads and shards.
+
+use crate::games::game::Game;
+use eyre::{ensure, Result};
+
+/// Which positions of each game are encoded, see [`Sampling::select`]. The default keeps
+/// every position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    /// Positions reached by the first `skip_plies` moves of a game are not encoded.
+    pub skip_plies: usize,
+    /// Probability with which each remaining position is kept.
+    pub probability: f64,
+    /// Most positions kept per game, chosen by `weighting` among the kept ones.
+    pub max_per_game: Option<usize>,
+    pub weighting: Weighting,
+    pub seed: u64,
+}
+
+/// How positions are chosen when a game has more than [`Sampling::max_per_game`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weighting {
+    /// Every position is equally likely.
+    #[default]
+    Uniform,
+    /// The chance of a position grows with its ply, favouring later positions over the
+    /// over-represented openings.
+    Ply,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            skip_plies: 0,
+            probability: 1.0,
+            max_per_game: None,
+            weighting: Weighting::Uniform,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64, small and stable across platforms and versions, unlike the generators of
+/// `rand`.
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// A generator seeded by `seed` and every one of `values`.
+    pub(crate) fn mixed(seed: u64, values: impl IntoIterator<Item = u64>) -> Self {
+        Self(
+            values
+                .into_iter()
+                .fold(seed, |seed, value| SplitMix64(seed ^ value).next_u64()),
+        )
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `(0, 1]`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Sampling {
+    /// Whether every position is kept.
+    pub fn keeps_all(&self) -> bool {
+        self.skip_plies == 0 && self.probability >= 1.0 && self.max_per_game.is_none()
+    }
+
+    /// Indices of the positions of `game` to encode, in order. Position `i` is the one
+    /// after move `i + 1`, `positions` is their number.
+    pub fn select(&self, game: &Game, positions: usize) -> Result<Vec<usize>> {
+        ensure!(
+            (0.0..=1.0).contains(&self.probability),
+            "Sampling probability {} is not between 0 and 1",
+            self.probability
+        );
+        if self.keeps_all() {
+            return Ok((0..positions).collect());
+        }
+        let mut rng = SplitMix64::mixed(self.seed, game.moves.iter().map(|m| u64::from(m.bitfield)));
+        let mut kept: Vec<usize> = (self.skip_plies..positions)
+            .filter(|_| self.probability >= 1.0 || rng.next_f64() <= self.probability)
+            .collect();
+        if let Some(max) = self.max_per_game.filter(|&max| kept.len() > max) {
+            // Weighted sampling without replacement: the positions with the largest
+            // `ln(u) / weight` for uniform `u`
+            let mut keys: Vec<(f64, usize)> = kept
+                .iter()
+                .map(|&i| {
+                    let weight = match self.weighting {
+                        Weighting::Uniform => 1.0,
+                        Weighting::Ply => (i + 1) as f64,
+                    };
+                    (rng.next_f64().ln() / weight, i)
+                })
+                .collect();
+            keys.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+            kept = keys.into_iter().take(max).map(|(_, i)| i).collect();
+            kept.sort_unstable();
+        }
+        Ok(kept)
+    }
+}
+
+#[test]
+fn test_sampling() {
+    let a = Game::test_with_moves(&[1, 2, 3]);
+    let b = Game::test_with_moves(&[3, 2, 1]);
+
+    assert_eq!(Sampling::default().select(&a, 5).unwrap(), [0, 1, 2, 3, 4]);
+    let skip = Sampling {
+        skip_plies: 3,
+        ..Default::default()
+    };
+    assert_eq!(skip.select(&a, 5).unwrap(), [3, 4]);
+
+    let capped = Sampling {
+        max_per_game: Some(10),
+        weighting: Weighting::Ply,
+        seed: 7,
+        ..Default::default()
+    };
+    let selected = capped.select(&a, 100).unwrap();
+    assert_eq!(selected.len(), 10);
+    assert_eq!(selected, capped.select(&a, 100).unwrap());
+    assert_ne!(selected, capped.select(&b, 100).unwrap());
+    // The later half of the positions carries three quarters of the weight
+    let late = (0..100)
+        .map(|seed| Sampling { seed, ..capped.clone() })
+        .flat_map(|s| s.select(&a, 100).unwrap())
+        .filter(|&i| i >= 50)
+        .count();
+    assert!(late > 600, "{}", late);
+
+    let half = Sampling {
+        probability: 0.5,
+        ..Default::default()
+    };
+    let kept = half.select(&a, 1000).unwrap().len();
+    assert!((400..600).contains(&kept), "{}", kept);
+    assert!(Sampling {
+        probability: 1.5,
+        ..Default::default()
+    }
+    .select(&a, 10)
+    .is_err());
+}
@@ -0,0 +1,166 @@
+//! Scalar features of positions besides the board: who plays under which conditions.
+//!
+//! Every feature is a single float per position, roughly between 0 and 1:
+//!
+//! - the Elos of both players divided by 3000, 0 if unknown
+//! - the base time of the time control divided by an hour and its increment divided by a
+//!   minute, both clamped to 1 which untimed games get
+//! - the ply number divided by 400, clamped to 1
+//! - the remaining clocks of both players as share of the base time, the full base time of
+//!   1 where a clock is unknown or the game untimed, and whether the game has clocks at
+//!   all; `.bin` archives hold no clocks, they come from [`Annotation::clock`]
+//! - the material of both players in pawns (queens 9, rooks 5, minor pieces 3) divided
+//!   by the 39 of the initial position
+//!
+//! Like planes, features of relative encoders say `own` and `opponent` instead of `white`
+//! and `black`.
+
+use super::encoder::relative_plane_name;
+use crate::games::game::{Annotation, Game};
+use shakmaty::{Chess, Color, Position, Role};
+
+/// Names of the features, for encoders which are not relative.
+pub const SCALAR_NAMES: [&str; 10] = [
+    "white_elo",
+    "black_elo",
+    "time_base",
+    "time_increment",
+    "ply",
+    "white_clock",
+    "black_clock",
+    "clocks_known",
+    "white_material",
+    "black_material",
+];
+
+const MAX_ELO: f32 = 3000.0;
+const MAX_PLIES: f32 = 400.0;
+
+/// Names of the features of an encoder, see [`FeatureEncoder::relative`](super::FeatureEncoder::relative).
+pub fn scalar_names(relative: bool) -> Vec<String> {
+    SCALAR_NAMES
+        .iter()
+        .map(|&name| {
+            if relative {
+                relative_plane_name(name)
+            } else {
+                name.to_string()
+            }
+        })
+        .collect()
+}
+
+fn material(pos: &Chess, color: Color) -> f32 {
+    let board = pos.board();
+    let pieces = board.by_color(color);
+    let value = |role: Role, pawns: u32| (board.by_role(role) & pieces).count() as u32 * pawns;
+    let total = value(Role::Pawn, 1)
+        + value(Role::Knight, 3)
+        + value(Role::Bishop, 3)
+        + value(Role::Rook, 5)
+        + value(Role::Queen, 9);
+    total as f32 / 39.0
+}
+
+/// Remaining time of `color` after move `ply + 1`, from the clock of its last move in
+/// `annotations`, the full base time before its first one. `None` where unknown and for
+/// untimed games.
+fn clock(game: &Game, annotations: &[Annotation], ply: usize, color: Color) -> Option<f32> {
+    let first = if color == Color::White { 0 } else { 1 };
+    let base = game.timectl_sec as f32;
+    let clock = if ply < first {
+        Some(base)
+    } else {
+        // The plies of `color` up to `ply`, latest first
+        let last = ply - (ply - first) % 2;
+        (first..=last)
+            .rev()
+            .step_by(2)
+            .find_map(|ply| annotations.get(ply).and_then(|a| a.clock))
+            .map(|clock| clock.as_secs_f32())
+    };
+    clock
+        .filter(|_| game.timectl_sec > 0 && game.timectl_sec < i32::MAX)
+        .map(|clock| (clock / base).min(1.0))
+}
+
+/// Features of `pos`, the position after move `ply + 1` of `game`, in the order of
+/// [`SCALAR_NAMES`]. `annotations` are those of the plies of `game` or empty. For relative
+/// encoders features of the side to move come first.
+pub fn encode_scalars(
+    game: &Game,
+    annotations: &[Annotation],
+    ply: usize,
+    pos: &Chess,
+    relative: bool,
+) -> [f32; 10] {
+    let elo = |elo: i32| (elo.max(0) as f32 / MAX_ELO).min(1.0);
+    // An unknown clock is taken as full, so that only flagged clocks come out as 0
+    let white_clock = clock(game, annotations, ply, Color::White).unwrap_or(1.0);
+    let black_clock = clock(game, annotations, ply, Color::Black).unwrap_or(1.0);
+    let clocks_known = annotations.iter().any(|a| a.clock.is_some());
+    let mut features = [
+        elo(game.white_elo),
+        elo(game.black_elo),
+        (game.timectl_sec.max(0) as f32 / 3600.0).min(1.0),
+        (game.timectl_inc.max(0) as f32 / 60.0).min(1.0),
+        ((ply + 1) as f32 / MAX_PLIES).min(1.0),
+        white_clock,
+        black_clock,
+        if clocks_known { 1.0 } else { 0.0 },
+        material(pos, Color::White),
+        material(pos, Color::Black),
+    ];
+    if relative && pos.turn() == Color::Black {
+        for (white, black) in [(0, 1), (5, 6), (8, 9)] {
+            features.swap(white, black);
+        }
+    }
+    features
+}
+
+#[test]
+fn test_scalars() {
+    use std::time::Duration;
+
+    let game = Game {
+        white_elo: 1500,
+        timectl_sec: 600,
+        timectl_inc: 5,
+        ..Game::test_with_moves(&[0; 4])
+    };
+    let pos = super::parse_fen("4k3/8/8/8/8/8/3PP3/R3K3 b - - 0 1").unwrap();
+    let features = encode_scalars(&game, &[], 0, &pos, false);
+    assert_eq!(features[..5], [0.5, 0.0, 600.0 / 3600.0, 5.0 / 60.0, 1.0 / 400.0]);
+    assert_eq!(features[5..], [1.0, 1.0, 0.0, 7.0 / 39.0, 0.0]);
+    assert_eq!(scalar_names(true)[8], "own_material");
+
+    let annotations: Vec<_> = [Some(590), None, Some(300), Some(450)]
+        .iter()
+        .map(|&secs| Annotation {
+            clock: secs.map(Duration::from_secs),
+            ..Default::default()
+        })
+        .collect();
+    let features = encode_scalars(&game, &annotations, 1, &pos, false);
+    // Black's first move has no clock, black's clock is unknown from then on
+    assert_eq!(features[5..8], [590.0 / 600.0, 1.0, 1.0]);
+    let features = encode_scalars(&game, &annotations, 3, &pos, false);
+    assert_eq!(features[5..7], [300.0 / 600.0, 450.0 / 600.0]);
+    let features = encode_scalars(&game, &annotations, 0, &pos, true);
+    assert_eq!(features[5..8], [1.0, 590.0 / 600.0, 1.0]);
+    assert_eq!(features[8..], [0.0, 7.0 / 39.0]);
+    // A flagged clock is told apart from an unknown one
+    let mut flagged = annotations.clone();
+    flagged[2].clock = Some(Duration::ZERO);
+    let features = encode_scalars(&game, &flagged, 2, &pos, false);
+    assert_eq!(features[5..7], [0.0, 1.0]);
+    let untimed = Game {
+        timectl_sec: i32::MAX,
+        timectl_inc: i32::MAX,
+        ..game
+    };
+    let features = encode_scalars(&untimed, &annotations, 3, &pos, false);
+    assert_eq!(features[2..4], [1.0, 1.0]);
+    assert_eq!(features[5..8], [1.0, 1.0, 1.0]);
+}
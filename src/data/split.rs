@@ -0,0 +1,283 @@
+//! Assignment of games to training, validation and test sets.
+//!
+//! Positions of a game share their outcome and are highly correlated, and archives hold
+//! some games more than once, so sets are split by game rather than by position. A
+//! [`Split`] assigns each game to a set by a stable hash of its moves: copies of a game
+//! always land in the same set, and every run gives the same split however games are
+//! batched into shards. Splitting by [players](SplitKey::Players) hashes the names of the
+//! players instead, for sets which share no player.
+
+use super::sampling::SplitMix64;
+use super::{convert_games_into_shards, EncodeOptions, FeatureEncoder};
+use crate::games::game::Game;
+use crate::progress::ProgressTracker;
+use eyre::{bail, ensure, Result, WrapErr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::info_span;
+
+/// Mixed into every hash, so splits do not follow the sampling of positions of the same
+/// seed, which is seeded by the moves as well.
+const SPLIT_SALT: u64 = u64::from_le_bytes(*b"split\0\0\0");
+
+/// One of the sets games are split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitSet {
+    Train,
+    Validation,
+    Test,
+}
+
+impl SplitSet {
+    pub const ALL: [SplitSet; 3] = [SplitSet::Train, SplitSet::Validation, SplitSet::Test];
+
+    /// Name of the directory of the set: `train`, `val` or `test`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SplitSet::Train => "train",
+            SplitSet::Validation => "val",
+            SplitSet::Test => "test",
+        }
+    }
+}
+
+/// What the set of a game is chosen by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SplitKey {
+    /// The moves of the game.
+    #[default]
+    Moves,
+    /// The names of the players. Each player belongs to one set, games between players of
+    /// different sets are left out. Only a share of about `r²` of the games of a set with
+    /// ratio `r` is kept, the cross-set games make up the rest.
+    Players,
+}
+
+const SPLIT_KEYS: [(&str, SplitKey); 2] =
+    [("moves", SplitKey::Moves), ("players", SplitKey::Players)];
+
+impl FromStr for SplitKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match SPLIT_KEYS.iter().find(|(name, _)| *name == s) {
+            Some(&(_, key)) => Ok(key),
+            None => bail!(
+                "Unknown split key {:?}, expected one of {:?}",
+                s,
+                SPLIT_KEYS.map(|(name, _)| name)
+            ),
+        }
+    }
+}
+
+/// Shares of the validation and test sets, the training set takes the rest. The default
+/// puts every game into the training set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Split {
+    pub validation: f64,
+    pub test: f64,
+    pub key: SplitKey,
+    /// Seed of the hashes, other seeds give other splits.
+    pub seed: u64,
+}
+
+impl Split {
+    pub fn check(&self) -> Result<()> {
+        ensure!(
+            self.validation >= 0.0 && self.test >= 0.0 && self.validation + self.test <= 1.0,
+            "Validation ratio {} and test ratio {} need to be non-negative and add up to at most 1",
+            self.validation,
+            self.test
+        );
+        Ok(())
+    }
+
+    /// Set of the hash of `values`, uniform in `(0, 1]`: test sets take the lowest ones.
+    fn set_of(&self, values: impl IntoIterator<Item = u64>) -> SplitSet {
+        let salted = std::iter::once(SPLIT_SALT).chain(values);
+        let u = SplitMix64::mixed(self.seed, salted).next_f64();
+        if u <= self.test {
+            SplitSet::Test
+        } else if u <= self.test + self.validation {
+            SplitSet::Validation
+        } else {
+            SplitSet::Train
+        }
+    }
+
+    fn player_set(&self, name: &str) -> SplitSet {
+        self.set_of(name.bytes().map(u64::from))
+    }
+
+    /// Set of `game`, `None` for games between players of different sets.
+    pub fn assign(&self, game: &Game) -> Option<SplitSet> {
+        match self.key {
+            SplitKey::Moves => {
+                Some(self.set_of(game.moves.iter().map(|m| u64::from(m.bitfield))))
+            }
+            SplitKey::Players => {
+                let white = self.player_set(&game.white_name);
+                (white == self.player_set(&game.black_name)).then_some(white)
+            }
+        }
+    }
+}
+
+/// Splits the games of a `.bin` archive into sets by `split` and encodes each set into
+/// shards of at most `max_games` games, in one pass over the archive. The shards of each
+/// set are saved in the directory `out_dir` gives for it as `000.bin`, `001.bin`, ..,
+/// see [`convert_games_and_save`](super::convert_games_and_save). Games left out by the
+/// split count as rejected. Returns the number of shards written per set, in the order of
+/// [`SplitSet::ALL`].
+pub fn convert_games_split_and_save(
+    path: &Path,
+    max_games: usize,
+    out_dir: impl Fn(SplitSet) -> PathBuf,
+    encoder: &dyn FeatureEncoder,
+    options: &EncodeOptions,
+    split: &Split,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<[usize; 3]> {
+    split.check()?;
+    let _span = info_span!("convert_games_split_and_save", file = %path.display()).entered();
+    let out_dirs = SplitSet::ALL.map(out_dir);
+    for dir in &out_dirs {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    }
+    let shards = convert_games_into_shards(
+        path,
+        max_games,
+        &out_dirs,
+        encoder,
+        options,
+        tracker,
+        |game| {
+            let game = bincode::deserialize::<Game>(game)?;
+            let set = split.assign(&game);
+            Ok(set.map(|set| SplitSet::ALL.iter().position(|&s| s == set).unwrap()))
+        },
+    )?;
+    Ok([shards[0], shards[1], shards[2]])
+}
+
+#[test]
+fn test_split() {
+    let game = |white: &str, black: &str, moves: &[u16]| Game {
+        white_name: white.to_string(),
+        black_name: black.to_string(),
+        ..Game::test_with_moves(moves)
+    };
+    let split = Split {
+        validation: 0.2,
+        test: 0.1,
+        ..Default::default()
+    };
+    // Copies of a game land in the same set, whoever plays them
+    let games: Vec<Game> = (0..1000u16)
+        .map(|i| {
+            game(
+                &format!("p{}", i % 37),
+                &format!("p{}", i % 11),
+                &[i, i / 7, 3],
+            )
+        })
+        .collect();
+    for g in &games {
+        let copy = game(
+            "someone",
+            "else",
+            &g.moves.iter().map(|m| m.bitfield).collect::<Vec<_>>(),
+        );
+        assert_eq!(split.assign(g), split.assign(&copy));
+    }
+    let count = |split: &Split, set| {
+        games
+            .iter()
+            .filter(|g| split.assign(g) == Some(set))
+            .count()
+    };
+    assert!((50..150).contains(&count(&split, SplitSet::Test)));
+    assert!((150..250).contains(&count(&split, SplitSet::Validation)));
+    let reseeded = Split {
+        seed: 1,
+        ..split.clone()
+    };
+    assert!(games.iter().any(|g| split.assign(g) != reseeded.assign(g)));
+    assert_eq!(count(&Split::default(), SplitSet::Train), games.len());
+
+    // No player takes part in games of two sets
+    let by_players = Split {
+        key: SplitKey::Players,
+        ..split.clone()
+    };
+    let mut sets = std::collections::HashMap::new();
+    for g in &games {
+        if let Some(set) = by_players.assign(g) {
+            for name in [&g.white_name, &g.black_name] {
+                assert_eq!(*sets.entry(name.clone()).or_insert(set), set);
+            }
+        }
+    }
+    assert!(games.iter().any(|g| by_players.assign(g).is_none()));
+    assert_eq!("players".parse::<SplitKey>().unwrap(), SplitKey::Players);
+    assert!(Split {
+        test: 0.7,
+        validation: 0.4,
+        ..Default::default()
+    }
+    .check()
+    .is_err());
+}
+
+#[test]
+fn test_convert_split() {
+    use super::TrainData;
+    use crate::games::serialization::Decoder;
+
+    let archive = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/games/testfiles/test.bin");
+    let dir = std::env::temp_dir().join(format!("chessers-split-{}", std::process::id()));
+    let split = Split {
+        validation: 0.2,
+        test: 0.2,
+        ..Default::default()
+    };
+    let options = EncodeOptions {
+        provenance: true,
+        sampling: super::sampling::Sampling {
+            max_per_game: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut tracker = ProgressTracker::new();
+    let shards = convert_games_split_and_save(
+        &archive,
+        30,
+        |set| dir.join(set.name()),
+        &super::encoder::V1Encoder,
+        &options,
+        &split,
+        &mut tracker,
+    )
+    .unwrap();
+
+    // Every game ends up in exactly the set it is assigned to, numbered as in the archive
+    let games: Vec<Game> = Decoder::open(&archive).unwrap().collect();
+    let mut seen = vec![false; games.len()];
+    for (set, shards) in SplitSet::ALL.into_iter().zip(shards) {
+        for shard in 0..shards {
+            let path = dir.join(set.name()).join(format!("{:03}.bin", shard));
+            let data = TrainData::load(&path).unwrap();
+            for &game in data.provenance.unwrap().games.iter() {
+                assert_eq!(split.assign(&games[game as usize]), Some(set));
+                seen[game as usize] = true;
+            }
+        }
+    }
+    assert!(seen.iter().all(|&seen| seen));
+    assert_eq!(tracker.progress().games_accepted, games.len());
+    let _ = std::fs::remove_dir_all(&dir);
+}
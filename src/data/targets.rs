@@ -0,0 +1,162 @@
+//! Value targets softer than the one-hot game result.
+//!
+//! A one-hot result labels the fifth move of a won game as a certain win. The
+//! [`ValueTargets`] of a position blend its result `R` with the expectation `P` before the
+//! game, the WDL of the Elo expected score:
+//!
+//! `α R + (1 - α) P`, with `α = (1 - elo_weight) discount^n`
+//!
+//! for a position `n` plies before the end of the game. Where an engine eval of the
+//! position is given, its WDL `E` is already an estimate for that position and is not
+//! discounted: the target is `(1 - elo_weight) E + elo_weight P`. Targets can also hold
+//! the number of plies left in the game, as an auxiliary moves left target.
+
+use crate::games::annotate::eval_score;
+use crate::games::game::{Eval, Game, Outcome};
+use eyre::{ensure, Result};
+
+/// How the value targets of positions are built, see the [module](self) documentation.
+/// The default gives the one-hot result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueTargets {
+    /// Weight kept by the result per ply before the end of the game, in `(0, 1]`.
+    pub discount: Option<f32>,
+    /// Weight of the Elo expectation in every target, in `[0, 1]`.
+    pub elo_weight: f32,
+    /// Share of draws in WDLs derived from expected scores, of Elos and evals.
+    pub draw_rate: f32,
+    /// Also record the plies left until the end of the game.
+    pub moves_left: bool,
+}
+
+impl Default for ValueTargets {
+    fn default() -> Self {
+        Self {
+            discount: None,
+            elo_weight: 0.0,
+            draw_rate: 0.1,
+            moves_left: false,
+        }
+    }
+}
+
+/// Elo expected score of white, 1/2 unless both ratings are known.
+pub fn elo_score(white_elo: i32, black_elo: i32) -> f32 {
+    if white_elo <= 0 || black_elo <= 0 {
+        return 0.5;
+    }
+    1.0 / (1.0 + 10f32.powf((black_elo - white_elo) as f32 / 400.0))
+}
+
+impl ValueTargets {
+    /// Whether the targets are the one-hot results.
+    pub fn is_one_hot(&self) -> bool {
+        self.discount.is_none() && self.elo_weight == 0.0
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if let Some(discount) = self.discount {
+            ensure!(
+                discount > 0.0 && discount <= 1.0,
+                "Discount {} is not in (0, 1]",
+                discount
+            );
+        }
+        ensure!(
+            (0.0..=1.0).contains(&self.elo_weight),
+            "Elo weight {} is not between 0 and 1",
+            self.elo_weight
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.draw_rate),
+            "Draw rate {} is not between 0 and 1",
+            self.draw_rate
+        );
+        Ok(())
+    }
+
+    /// `[white win, draw, black win]` with the expected score `score` of white: draws take
+    /// `draw_rate`, less where the score leaves no room for them.
+    pub fn score_wdl(&self, score: f32) -> [f32; 3] {
+        let score = score.clamp(0.0, 1.0);
+        let draw = self.draw_rate.min(2.0 * score.min(1.0 - score));
+        [score - draw / 2.0, draw, 1.0 - score - draw / 2.0]
+    }
+
+    /// Target as `[white win, draw, black win]` of the position after move `ply + 1` of
+    /// `game`, with `eval` the engine eval of that position if known.
+    pub fn target(&self, game: &Game, ply: usize, eval: Option<Eval>) -> [f32; 3] {
+        let (result, alpha) = match eval {
+            Some(eval) => (self.score_wdl(eval_score(eval)), 1.0 - self.elo_weight),
+            None => {
+                let result = match game.outcome {
+                    Outcome::WhiteWin => [1.0, 0.0, 0.0],
+                    Outcome::Draw => [0.0, 1.0, 0.0],
+                    Outcome::BlackWin => [0.0, 0.0, 1.0],
+                };
+                let plies_left = game.moves.len().saturating_sub(ply + 1);
+                let discount = self.discount.map_or(1.0, |d| d.powi(plies_left as i32));
+                (result, (1.0 - self.elo_weight) * discount)
+            }
+        };
+        if self.is_one_hot() {
+            return result;
+        }
+        let prior = self.score_wdl(elo_score(game.white_elo, game.black_elo));
+        [0, 1, 2].map(|i| alpha * result[i] + (1.0 - alpha) * prior[i])
+    }
+}
+
+#[test]
+fn test_value_targets() {
+    let game = Game {
+        white_elo: 2400,
+        black_elo: 2000,
+        outcome: Outcome::BlackWin,
+        ..Game::test_with_moves(&[0; 11])
+    };
+    let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+
+    let one_hot = ValueTargets::default();
+    assert_eq!(one_hot.target(&game, 0, None), [0.0, 0.0, 1.0]);
+    let eval = one_hot.target(&game, 0, Some(Eval::Mate(3)));
+    assert_eq!(eval, [1.0, 0.0, 0.0]);
+    assert!(close(one_hot.score_wdl(0.5), [0.45, 0.1, 0.45]));
+    assert!(close(one_hot.score_wdl(0.98), [0.96, 0.04, 0.0]));
+
+    let score = elo_score(2400, 2000);
+    assert!((score - 0.909).abs() < 1e-3);
+    assert_eq!(elo_score(0, 2000), 0.5);
+
+    // The last position keeps its result, earlier ones fade towards the Elo expectation
+    let discounted = ValueTargets {
+        discount: Some(0.5),
+        ..Default::default()
+    };
+    assert_eq!(discounted.target(&game, 10, None), [0.0, 0.0, 1.0]);
+    let prior = one_hot.score_wdl(score);
+    let expected = [0, 1, 2].map(|i| 0.5 * prior[i] + [0.0, 0.0, 0.5][i]);
+    assert!(close(discounted.target(&game, 9, None), expected));
+    let blended = ValueTargets {
+        elo_weight: 1.0,
+        ..Default::default()
+    };
+    assert!(close(blended.target(&game, 10, None), prior));
+    // Evals are not discounted, only blended with the Elo expectation
+    let eval = Eval::Pawns(1.5);
+    let wdl = one_hot.score_wdl(eval_score(eval));
+    assert_eq!(discounted.target(&game, 0, Some(eval)), wdl);
+    let half = ValueTargets {
+        discount: Some(0.5),
+        elo_weight: 0.5,
+        ..Default::default()
+    };
+    let expected = [0, 1, 2].map(|i| 0.5 * wdl[i] + 0.5 * prior[i]);
+    assert!(close(half.target(&game, 0, Some(eval)), expected));
+    assert!(ValueTargets {
+        discount: Some(0.0),
+        ..Default::default()
+    }
+    .check()
+    .is_err());
+}
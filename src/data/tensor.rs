@@ -0,0 +1,210 @@
+//! Element types and axis orders in which encoded positions are handed out.
+//!
+//! Positions are always encoded as `f32` in `[position][file][7 - rank][plane]` order. A
+//! [`TensorFormat`] chosen at encode time is recorded with the data and applied when the
+//! inputs are handed to a model: smaller element types for mixed precision training and
+//! channels first for PyTorch.
+
+use super::NNInputBatch;
+use crate::error::FormatError;
+use eyre::{bail, Result};
+use half::f16;
+use ndarray::{Array4, Axis};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Element type of the inputs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Dtype {
+    #[default]
+    Float32,
+    /// Half precision, values are rounded to the nearest `f16`.
+    Float16,
+    /// Integers from 0 to 255, every value has to be one.
+    Uint8,
+    /// Every value has to be 0 or 1.
+    Bool,
+}
+
+/// Order of the axes of a batch of inputs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Layout {
+    /// `[position][file][7 - rank][plane]`, channels last as used by TensorFlow.
+    #[default]
+    Nhwc,
+    /// `[position][plane][file][7 - rank]`, channels first as used by PyTorch.
+    Nchw,
+}
+
+/// How a batch of inputs is handed out, see the [module](self) documentation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TensorFormat {
+    pub dtype: Dtype,
+    pub layout: Layout,
+}
+
+/// A batch of inputs in one of the [`Dtype`]s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputTensor {
+    Float32(Array4<f32>),
+    Float16(Array4<f16>),
+    Uint8(Array4<u8>),
+    Bool(Array4<bool>),
+}
+
+const DTYPES: [(&str, Dtype); 4] = [
+    ("float32", Dtype::Float32),
+    ("float16", Dtype::Float16),
+    ("uint8", Dtype::Uint8),
+    ("bool", Dtype::Bool),
+];
+const LAYOUTS: [(&str, Layout); 2] = [("nhwc", Layout::Nhwc), ("nchw", Layout::Nchw)];
+
+impl Dtype {
+    /// Whether `value` is stored exactly, or for `Float16` at all.
+    fn holds(self, value: f32) -> bool {
+        match self {
+            Dtype::Float32 | Dtype::Float16 => true,
+            Dtype::Uint8 => value.fract() == 0.0 && (0.0..=255.0).contains(&value),
+            Dtype::Bool => value == 0.0 || value == 1.0,
+        }
+    }
+}
+
+impl fmt::Display for Dtype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = DTYPES.iter().find(|(_, d)| d == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Dtype {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match DTYPES.iter().find(|(name, _)| *name == s) {
+            Some(&(_, dtype)) => Ok(dtype),
+            None => bail!(
+                "Unknown dtype {:?}, expected one of {:?}",
+                s,
+                DTYPES.map(|(name, _)| name)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = LAYOUTS.iter().find(|(_, l)| l == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Layout {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match LAYOUTS.iter().find(|(name, _)| *name == s) {
+            Some(&(_, layout)) => Ok(layout),
+            None => bail!(
+                "Unknown layout {:?}, expected one of {:?}",
+                s,
+                LAYOUTS.map(|(name, _)| name)
+            ),
+        }
+    }
+}
+
+impl TensorFormat {
+    /// Checks that every value of `ins` fits the dtype, `plane_names` name the planes in
+    /// the error.
+    pub fn check(&self, ins: &NNInputBatch, plane_names: &[String]) -> Result<()> {
+        for (plane, values) in ins.axis_iter(Axis(3)).enumerate() {
+            if let Some(&value) = values.iter().find(|&&v| !self.dtype.holds(v)) {
+                let name = plane_names.get(plane).map_or("?", |name| name.as_str());
+                bail!(FormatError::new(format!(
+                    "Plane {} holds {}, which is not a valid {}",
+                    name, value, self.dtype
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a batch of inputs into this format, see [`TensorFormat::check`].
+    pub fn apply(&self, ins: &NNInputBatch, plane_names: &[String]) -> Result<InputTensor> {
+        self.check(ins, plane_names)?;
+        let ins = match self.layout {
+            Layout::Nhwc => ins.view(),
+            Layout::Nchw => ins.view().permuted_axes([0, 3, 1, 2]),
+        };
+        Ok(match self.dtype {
+            Dtype::Float32 => InputTensor::Float32(ins.as_standard_layout().into_owned()),
+            Dtype::Float16 => InputTensor::Float16(ins.mapv(f16::from_f32)),
+            Dtype::Uint8 => InputTensor::Uint8(ins.mapv(|v| v as u8)),
+            Dtype::Bool => InputTensor::Bool(ins.mapv(|v| v != 0.0)),
+        })
+    }
+}
+
+impl InputTensor {
+    /// Converts back to `f32` in `[position][file][7 - rank][plane]` order, the tensor
+    /// being in `layout`.
+    pub fn into_f32(self, layout: Layout) -> NNInputBatch {
+        let ins = match self {
+            InputTensor::Float32(ins) => ins,
+            InputTensor::Float16(ins) => ins.mapv(f16::to_f32),
+            InputTensor::Uint8(ins) => ins.mapv(f32::from),
+            InputTensor::Bool(ins) => ins.mapv(|v| if v { 1.0 } else { 0.0 }),
+        };
+        match layout {
+            Layout::Nhwc => ins,
+            Layout::Nchw => ins
+                .permuted_axes([0, 2, 3, 1])
+                .as_standard_layout()
+                .into_owned(),
+        }
+    }
+
+    pub fn dtype(&self) -> Dtype {
+        match self {
+            InputTensor::Float32(_) => Dtype::Float32,
+            InputTensor::Float16(_) => Dtype::Float16,
+            InputTensor::Uint8(_) => Dtype::Uint8,
+            InputTensor::Bool(_) => Dtype::Bool,
+        }
+    }
+}
+
+#[test]
+fn test_tensor_format() {
+    let mut ins = NNInputBatch::zeros((2, 8, 8, 3));
+    ins[[1, 4, 6, 0]] = 1.0;
+    ins[[1, 2, 3, 2]] = 2.0;
+    let names: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+
+    let format = TensorFormat {
+        dtype: Dtype::Uint8,
+        layout: Layout::Nchw,
+    };
+    let tensor = format.apply(&ins, &names).unwrap();
+    let InputTensor::Uint8(ref planes) = tensor else {
+        panic!("expected uint8, got {:?}", tensor.dtype());
+    };
+    assert_eq!(planes.shape(), [2, 3, 8, 8]);
+    assert_eq!(planes[[1, 0, 4, 6]], 1);
+    assert_eq!(planes[[1, 2, 2, 3]], 2);
+    assert_eq!(tensor.into_f32(Layout::Nchw), ins);
+
+    let bools = TensorFormat {
+        dtype: Dtype::Bool,
+        layout: Layout::Nhwc,
+    };
+    let err = bools.apply(&ins, &names).unwrap_err();
+    assert_eq!(err.to_string(), "Plane c holds 2, which is not a valid bool");
+    assert_eq!("float16".parse::<Dtype>().unwrap(), Dtype::Float16);
+    assert!("nwhc".parse::<Layout>().is_err());
+}
@@ -0,0 +1,116 @@
+//! Error types which callers may want to tell apart.
+//!
+//! Functions of this crate return [`eyre::Result`], these types are found by
+//! downcasting the report or walking its chain.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A `.bin` archive or training data shard which is not in the expected format.
+#[derive(Debug)]
+pub struct FormatError {
+    pub message: String,
+}
+
+impl FormatError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// A PGN game which could not be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    /// Index of the game in its file, starting at 0.
+    pub game: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "game {}: {}", self.game, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A move which is not legal in the position it is played in.
+#[derive(Debug)]
+pub struct IllegalMoveError {
+    /// The ply of the move, starting at 1.
+    pub ply: usize,
+    pub uci: String,
+}
+
+impl fmt::Display for IllegalMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "illegal move {} at ply {}", self.uci, self.ply)
+    }
+}
+
+impl std::error::Error for IllegalMoveError {}
+
+/// A FEN which is malformed or describes an illegal position.
+#[derive(Debug)]
+pub struct FenError {
+    pub fen: String,
+    pub message: String,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid FEN {:?}: {}", self.fen, self.message)
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// An I/O error on a file.
+#[derive(Debug)]
+pub struct IoError {
+    pub path: PathBuf,
+    pub source: std::io::Error,
+}
+
+impl IoError {
+    pub fn new(path: &Path, source: std::io::Error) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "I/O error on {}", self.path.display())
+    }
+}
+
+impl std::error::Error for IoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// An operation stopped through its [`CancelToken`](crate::progress::CancelToken).
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
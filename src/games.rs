@@ -1,199 +1,213 @@
+//! Chess games and their compact binary archive format.
+//!
+//! Games are parsed from PGN with [`pgn::PgnVisitor`], stored in `.bin` archives with
+//! [`serialization::Encoder`] and read back with [`serialization::Decoder`].
+
+use crate::error::IoError;
+use crate::progress::{Progress, ProgressTracker};
 use eyre::{Context, Result};
 use pgn_reader::BufferedReader;
-use pyo3::exceptions::PyValueError;
-use pyo3::prelude::*;
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use super::data;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::info_span;
 
+pub mod annotate;
 pub mod game;
 pub mod pgn;
 pub mod serialization;
 
-fn pgn_to_bin_impl(
-    pgn_path: &Path,
-    bin_path: &Path,
-    min_elo: i32,
-    max_elo_diff: i32,
-) -> Result<()> {
-    let f = File::open(pgn_path)?;
-    let reader = BufReader::new(f);
-    let mut reader = BufferedReader::new(reader);
-    let mut writer = serialization::Encoder::open(bin_path)?;
-    let mut visitor = pgn::PgnVisitor::new();
-    while let Some(game) = reader.read_game(&mut visitor)? {
-        if let Some(game) = game? {
-            if ((game.black_elo - game.white_elo).abs() <= max_elo_diff)
-                && (game.black_elo > min_elo)
-                && (game.white_elo > min_elo)
-            {
-                writer.write_game(&game)?;
-            }
-        }
-    }
-    Ok(())
+/// Whether both players are rated above `min_elo` and at most `max_elo_diff` apart.
+pub fn elo_filter(game: &game::Game, min_elo: i32, max_elo_diff: i32) -> bool {
+    ((game.black_elo - game.white_elo).abs() <= max_elo_diff)
+        && (game.black_elo > min_elo)
+        && (game.white_elo > min_elo)
 }
 
-#[pyfunction]
-fn pgn_to_bin(pgn_path: &str, bin_path: &str, min_elo: i32, max_elo_diff: i32) -> PyResult<()> {
-    let pgn_path = PathBuf::from(pgn_path.to_string());
-    let bin_path = PathBuf::from(bin_path.to_string());
-    pgn_to_bin_impl(&pgn_path, &bin_path, min_elo, max_elo_diff)
-        .map_err(|e| PyValueError::new_err(format!("{:?}", e)))
+/// Criteria for selecting games, see [`bin_to_bin`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameFilter {
+    pub min_elo: i32,
+    pub max_elo_diff: i32,
+    pub min_plies: usize,
+    /// Only keep games with this time control base in seconds
+    pub timectl_sec: Option<i32>,
 }
 
-fn bin_to_pgn_impl(
-    bin_path: &Path,
-    pgn_path: &Path,
-    min_elo: i32,
-    max_elo_diff: i32,
-) -> Result<()> {
-    let f = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(pgn_path)
-        .wrap_err("failed to open target pgn file")?;
-
-    let mut writer = BufWriter::new(f);
-    let mut decoder =
-        serialization::Decoder::open(bin_path).wrap_err("failed to open source bin file")?;
-
-    while let Some(game) = decoder.read_game()? {
-        if ((game.black_elo - game.white_elo).abs() <= max_elo_diff)
-            && (game.black_elo > min_elo)
-            && (game.white_elo > min_elo)
-        {
-            game.write_pgn(&mut writer)?;
-        }
+impl GameFilter {
+    pub fn accepts(&self, game: &game::Game) -> bool {
+        elo_filter(game, self.min_elo, self.max_elo_diff)
+            && game.moves.len() >= self.min_plies
+            && self.timectl_sec.is_none_or(|sec| game.timectl_sec == sec)
     }
-    Ok(())
-}
-
-#[pyfunction]
-fn bin_to_pgn(bin_path: &str, pgn_path: &str, min_elo: i32, max_elo_diff: i32) -> PyResult<()> {
-    let pgn_path = PathBuf::from(pgn_path.to_string());
-    let bin_path = PathBuf::from(bin_path.to_string());
-    bin_to_pgn_impl(&bin_path, &pgn_path, min_elo, max_elo_diff)
-        .map_err(|e| PyValueError::new_err(format!("{:?}", e)))
 }
 
-#[pyclass]
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Game {
-    pub(crate) inner: game::Game,
-    pub(crate) outcome: String,
-}
-
-impl Game {
-    fn new(inner: game::Game) -> Self {
+impl Default for GameFilter {
+    fn default() -> Self {
         Self {
-            outcome: match inner.outcome {
-                game::Outcome::BlackWin => "0-1".to_string(),
-                game::Outcome::WhiteWin => "1-0".to_string(),
-                game::Outcome::Draw => "1/2-1/2".to_string(),
-            },
-            inner,
+            min_elo: 0,
+            max_elo_diff: 5000,
+            min_plies: 0,
+            timectl_sec: None,
         }
     }
 }
 
-#[pymethods]
-impl Game {
-    fn white(&self) -> &str {
-        &self.inner.white_name
-    }
-
-    fn black(&self) -> &str {
-        &self.inner.black_name
-    }
-
-    fn white_elo(&self) -> i32 {
-        self.inner.white_elo
-    }
-
-    fn black_elo(&self) -> i32 {
-        self.inner.black_elo
-    }
-
-    fn outcome(&self) -> &str {
-        &self.outcome
-    }
-
-    fn timectl_sec(&self) -> i32 {
-        self.inner.timectl_sec
-    }
+/// Reader counting the bytes read through it, for progress reports.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
 
-    fn timectl_inc(&self) -> i32 {
-        self.inner.timectl_inc
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
     }
+}
 
-    fn moves(&self) -> Vec<String> {
-        self.inner.moves.iter().map(|m| m.to_uci()).collect()
+/// Runs `write` on the temporary file `{path}.part`, which replaces `path` once `write`
+/// succeeded and is removed otherwise, so failed or cancelled runs never leave a
+/// truncated file behind.
+pub(crate) fn write_atomically<T>(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T> {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    match write(&part) {
+        Ok(value) => {
+            std::fs::rename(&part, path).map_err(|e| IoError::new(path, e))?;
+            Ok(value)
+        }
+        Err(e) => {
+            // The write already failed, a leftover part file is the lesser problem
+            let _ = std::fs::remove_file(&part);
+            Err(e)
+        }
     }
 }
 
-#[pyclass]
-struct GameLoader {
-    decoder: serialization::Decoder,
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|m| m.len())
 }
 
-#[pymethods]
-impl GameLoader {
-    #[new]
-    fn new(file_path: &str) -> PyResult<Self> {
-        (|| -> Result<Self> {
-            let decoder = serialization::Decoder::open(&PathBuf::from(file_path))?;
-            Ok(Self { decoder })
-        }())
-        .map_err(|e| PyValueError::new_err(format!("{:#?}", e)))
-    }
-
-    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
-        slf
+fn count_game(progress: &mut Progress, bytes_processed: u64, accepted: bool) {
+    progress.bytes_processed = bytes_processed;
+    if accepted {
+        progress.games_accepted += 1;
+    } else {
+        progress.games_rejected += 1;
     }
+}
 
-    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyResult<Game>> {
-        match slf.decoder.read_game() {
-            Err(e) => Some(Err(PyValueError::new_err(format!("{:?}", e)))),
-            Ok(Some(g)) => Some(Ok(Game::new(g))),
-            Ok(None) => None,
-        }
-    }
-    
-    fn read_games(mut slf: PyRefMut<'_, Self>, max_games: usize) -> PyResult<Vec<Game>> {
-        (|| -> Result<Vec<Game>> {
-            let mut games = Vec::with_capacity(max_games);
-            for _ in 0..max_games {
-                match slf.decoder.read_game()? {
-                    Some(g) => games.push(Game::new(g)),
-                    None => break,
+/// Converts a PGN file into a `.bin` archive, keeping the games which pass [`elo_filter`].
+///
+/// Unfinished games and games with an abnormal termination count as rejected.
+pub fn pgn_to_bin(
+    pgn_path: &Path,
+    bin_path: &Path,
+    min_elo: i32,
+    max_elo_diff: i32,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<()> {
+    let _span = info_span!("pgn_to_bin", file = %pgn_path.display()).entered();
+    let f = File::open(pgn_path).map_err(|e| IoError::new(pgn_path, e))?;
+    tracker.set_bytes_total(file_size(pgn_path));
+    let count = Arc::new(AtomicU64::new(0));
+    let reader = BufReader::new(CountingReader {
+        inner: f,
+        count: Arc::clone(&count),
+    });
+    let mut reader = BufferedReader::new(reader);
+    write_atomically(bin_path, |part| {
+        let mut writer = serialization::Encoder::open(part)?;
+        let mut visitor = pgn::PgnVisitor::new();
+        while let Some(game) = reader.read_game(&mut visitor)? {
+            let accepted = match game? {
+                Some(game) if elo_filter(&game, min_elo, max_elo_diff) => {
+                    writer.write_game(&game)?;
+                    true
                 }
-            }
-            Ok(games)
-        }()).map_err(|e| PyValueError::new_err(format!("{:#?}", e)))
-    }
-
-    fn convert_games<'py>(
-        mut slf: PyRefMut<'py, Self>,
-        max_games: usize,
-    ) -> Option<data::TrainData> {
-        let games: Vec<Vec<u8>> = slf.decoder.raw_iter().take(max_games).collect();
-        if games.is_empty() {
-            None
-        } else {
-            Some(data::TrainData::from_games_py(slf.py(), games))
+                _ => false,
+            };
+            let bytes = count.load(Ordering::Relaxed);
+            tracker.update(|p| count_game(p, bytes, accepted))?;
         }
-    }
+        writer.finish()
+    })?;
+    tracker.finish()
 }
 
-pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(pgn_to_bin, m)?)?;
-    m.add_function(wrap_pyfunction!(bin_to_pgn, m)?)?;
-
-    m.add_class::<Game>()?;
-    m.add_class::<GameLoader>()?;
+/// Converts a `.bin` archive into a PGN file, keeping the games which pass [`elo_filter`].
+pub fn bin_to_pgn(
+    bin_path: &Path,
+    pgn_path: &Path,
+    min_elo: i32,
+    max_elo_diff: i32,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<()> {
+    let _span = info_span!("bin_to_pgn", file = %bin_path.display()).entered();
+    let mut decoder =
+        serialization::Decoder::open(bin_path).wrap_err("failed to open source bin file")?;
+    tracker.set_bytes_total(file_size(bin_path));
+    write_atomically(pgn_path, |part| {
+        let f = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(part)
+            .wrap_err("failed to open target pgn file")?;
+
+        let mut writer = BufWriter::new(f);
+        while let Some(game) = decoder.read_game()? {
+            let accepted = elo_filter(&game, min_elo, max_elo_diff);
+            if accepted {
+                game.write_pgn(&mut writer)?;
+            }
+            let bytes = decoder.bytes_read();
+            tracker.update(|p| count_game(p, bytes, accepted))?;
+        }
+        writer.flush()?;
+        Ok(())
+    })?;
+    tracker.finish()
+}
 
-    Ok(())
+/// Copies the games of a `.bin` archive accepted by `filter` into a new archive.
+///
+/// Returns the number of games read and written.
+pub fn bin_to_bin(
+    src_path: &Path,
+    dst_path: &Path,
+    mut filter: impl FnMut(&game::Game) -> bool,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<(usize, usize)> {
+    let _span = info_span!("bin_to_bin", file = %src_path.display()).entered();
+    let mut decoder =
+        serialization::Decoder::open(src_path).wrap_err("failed to open source bin file")?;
+    tracker.set_bytes_total(file_size(src_path));
+    let counts = write_atomically(dst_path, |part| {
+        let mut encoder =
+            serialization::Encoder::open(part).wrap_err("failed to open target bin file")?;
+        let (mut read, mut written) = (0, 0);
+        while let Some(game) = decoder.read_game()? {
+            read += 1;
+            let accepted = filter(&game);
+            if accepted {
+                encoder.write_game(&game)?;
+                written += 1;
+            }
+            let bytes = decoder.bytes_read();
+            tracker.update(|p| count_game(p, bytes, accepted))?;
+        }
+        encoder.finish()?;
+        Ok((read, written))
+    })?;
+    tracker.finish()?;
+    Ok(counts)
 }
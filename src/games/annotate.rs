@@ -0,0 +1,172 @@
+use super::game::*;
+use eyre::{ensure, Result};
+
+pub const NAG_GOOD: u8 = 1;
+pub const NAG_MISTAKE: u8 = 2;
+pub const NAG_BLUNDER: u8 = 4;
+
+/// Changes in expected score, from the mover's point of view, which mark a ply with a NAG.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwingThresholds {
+    /// Minimum gain for a good move (`!`).
+    pub good: f32,
+    /// Minimum loss for a mistake (`?`).
+    pub mistake: f32,
+    /// Minimum loss for a blunder (`??`).
+    pub blunder: f32,
+}
+
+impl Default for SwingThresholds {
+    fn default() -> Self {
+        Self {
+            good: 0.15,
+            mistake: 0.1,
+            blunder: 0.2,
+        }
+    }
+}
+
+/// Expected score for white of a `[white win, draw, black win]` prediction.
+fn expected_score(wdl: &[f32; 3]) -> f32 {
+    let total = wdl.iter().sum::<f32>().max(f32::EPSILON);
+    (wdl[0] + 0.5 * wdl[1]) / total
+}
+
+/// Converts an expected score to pawns with the inverse of lichess' winning chances curve.
+fn score_to_pawns(score: f32) -> f32 {
+    let chances = 2.0 * score.clamp(0.001, 0.999) - 1.0;
+    let centipawns = -(2.0 / (chances + 1.0) - 1.0).ln() / 0.003_682_08;
+    centipawns / 100.0
+}
+
+/// Expected score for white of an eval, on lichess' winning chances curve. Mates are
+/// certain wins.
+pub fn eval_score(eval: Eval) -> f32 {
+    match eval {
+        Eval::Pawns(pawns) => 1.0 / (1.0 + (-0.003_682_08 * pawns * 100.0).exp()),
+        Eval::Mate(n) if n < 0 => 0.0,
+        Eval::Mate(_) => 1.0,
+    }
+}
+
+/// Builds per-ply annotations from model predictions.
+///
+/// `wdl[i]` is the `[white win, draw, black win]` prediction for the position after
+/// ply `i`, i.e. the order of `data::encode_game_positions`. Each ply gets an eval,
+/// the predicted probabilities from white's point of view and the expected score change for
+/// the side which moved, plus a NAG when that change crosses one of the `thresholds`. The
+/// first ply is compared against an even start position, an expected score of 1/2.
+pub fn annotate_wdl(
+    game: &Game,
+    wdl: &[[f32; 3]],
+    thresholds: &SwingThresholds,
+) -> Result<Vec<Annotation>> {
+    ensure!(
+        wdl.len() == game.moves.len(),
+        "Got {} evaluations for a game of {} plies",
+        wdl.len(),
+        game.moves.len()
+    );
+
+    let mut annotations = Vec::with_capacity(wdl.len());
+    let mut previous = 0.5;
+    for (ply, prediction) in wdl.iter().enumerate() {
+        let score = expected_score(prediction);
+        let mut annotation = Annotation {
+            eval: Some(Eval::Pawns(score_to_pawns(score))),
+            ..Default::default()
+        };
+
+        let mut comment = format!(
+            "White {:.1}% Draw {:.1}% Black {:.1}%",
+            prediction[0] * 100.0,
+            prediction[1] * 100.0,
+            prediction[2] * 100.0
+        );
+        let delta = if ply % 2 == 0 {
+            score - previous
+        } else {
+            previous - score
+        };
+        comment.push_str(&format!(" ({:+.1}%)", delta * 100.0));
+
+        if delta <= -thresholds.blunder {
+            annotation.nags.push(NAG_BLUNDER);
+        } else if delta <= -thresholds.mistake {
+            annotation.nags.push(NAG_MISTAKE);
+        } else if delta >= thresholds.good {
+            annotation.nags.push(NAG_GOOD);
+        }
+        annotation.comment = Some(comment);
+        annotations.push(annotation);
+        previous = score;
+    }
+    Ok(annotations)
+}
+
+#[test]
+fn test_annotate_wdl() {
+    let pgn = include_str!("testfiles/single.pgn");
+    let mut visitor = super::pgn::PgnVisitor::new();
+    let mut reader = pgn_reader::BufferedReader::new_cursor(pgn);
+    let game = reader
+        .read_game(&mut visitor)
+        .unwrap()
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    let mut wdl = vec![[0.4, 0.3, 0.3]; game.moves.len()];
+    wdl[3] = [0.65, 0.3, 0.05];
+    wdl[4] = [0.52, 0.3, 0.18];
+    wdl[5] = [0.9, 0.1, 0.0];
+    wdl[6] = [0.9, 0.1, 0.0];
+    wdl[7] = [0.55, 0.3, 0.15];
+
+    let annotations = annotate_wdl(&game, &wdl, &SwingThresholds::default()).unwrap();
+    let nags: Vec<&[u8]> = annotations[..8].iter().map(|a| a.nags.as_slice()).collect();
+    assert_eq!(
+        nags,
+        &[
+            &[][..],
+            &[],
+            &[],
+            &[NAG_BLUNDER],
+            &[NAG_MISTAKE],
+            &[NAG_BLUNDER],
+            &[],
+            &[NAG_GOOD]
+        ]
+    );
+    assert_eq!(annotations[0].eval, Some(Eval::Pawns(score_to_pawns(0.55))));
+    assert_eq!(score_to_pawns(0.5), 0.0);
+
+    let options = PgnWriteOptions {
+        nag_glyphs: true,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    game.write_pgn_with(&mut out, &options, &annotations)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let movetext = out.split_whitespace().collect::<Vec<_>>().join(" ");
+    assert!(movetext.contains("2... d5?? {[%eval 3.76] White 65.0% Draw 30.0% Black 5.0% (-25.0%)}"));
+
+    let mut reader = pgn_reader::BufferedReader::new_cursor(out.as_bytes());
+    let exported = reader
+        .read_game(&mut visitor)
+        .unwrap()
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(game, exported);
+
+    assert!(annotate_wdl(&game, &wdl[1..], &SwingThresholds::default()).is_err());
+    // The first move is judged against the start position
+    wdl[0] = [0.1, 0.3, 0.6];
+    let annotations = annotate_wdl(&game, &wdl, &SwingThresholds::default()).unwrap();
+    assert_eq!(annotations[0].nags, [NAG_BLUNDER]);
+    assert!((eval_score(Eval::Pawns(score_to_pawns(0.8))) - 0.8).abs() < 1e-4);
+    assert_eq!(eval_score("#-2".parse().unwrap()), 0.0);
+    assert!("#x".parse::<Eval>().is_err());
+}
@@ -1,11 +1,13 @@
-use eyre::Result;
+use crate::error::IllegalMoveError;
+use eyre::{ensure, Result};
 use serde::{Deserialize, Serialize};
-use shakmaty::{san::San, uci::UciMove, Chess, Position};
+use shakmaty::{san::SanPlus, uci::UciMove, Chess, Position};
 use std::io::Write;
+use std::time::Duration;
 
 /// Bit field representing a UCI move:
 /// the MSB is 0
-/// bits [14:12] indicate promotion:
+/// bits \[14:12\] indicate promotion:
 ///     - 0 = none
 ///     - 1 = Pawn
 ///     - 2 = Knight
@@ -14,12 +16,12 @@ use std::io::Write;
 ///     - 5 = Queen
 ///     - 6 = King
 ///
-/// bits [11:6] are the from encoding:
-///     - bits [11:9] are the file (a=0, ..)
-///     - bits [ 8:6] are the rank
-/// bits [ 5:0] are the to encoding:
-///     - bits [5:3] are the file (a=0, ..)
-///     - bits [2:0] are the rank
+/// bits \[11:6\] are the from encoding:
+///     - bits \[11:9\] are the file (a=0, ..)
+///     - bits \[ 8:6\] are the rank
+/// bits \[ 5:0\] are the to encoding:
+///     - bits \[5:3\] are the file (a=0, ..)
+///     - bits \[2:0\] are the rank
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub struct Move {
     pub(crate) bitfield: u16,
@@ -97,7 +99,30 @@ impl Move {
         shakmaty::Square::from_coords(file, rank)
     }
 
-    pub fn to_uci(&self) -> String {
+    pub fn to_uci_move(self) -> UciMove {
+        UciMove::Normal {
+            from: self.move_from(),
+            to: self.move_to(),
+            promotion: self.promotion(),
+        }
+    }
+
+    /// The move in `pos` this move stands for, failing if it is not legal there.
+    /// `ply` only serves the error.
+    pub fn to_legal_move(
+        self,
+        pos: &Chess,
+        ply: usize,
+    ) -> std::result::Result<shakmaty::Move, IllegalMoveError> {
+        self.to_uci_move()
+            .to_move(pos)
+            .map_err(|_| IllegalMoveError {
+                ply,
+                uci: self.to_uci(),
+            })
+    }
+
+    pub fn to_uci(self) -> String {
         format!("{}{}{}", self.move_from(), self.move_to(), match self.promotion() {
             Some(piece) => match piece {
                 shakmaty::Role::Bishop => "b",
@@ -112,6 +137,14 @@ impl Move {
     }
 }
 
+/// Castling is stored as the king moving onto its rook, the way PGN games are parsed.
+impl From<&shakmaty::Move> for Move {
+    fn from(m: &shakmaty::Move) -> Self {
+        let from = m.from().expect("standard chess moves have a from square");
+        Move::new(from, m.to(), m.promotion())
+    }
+}
+
 fn file_from_int(x: u8) -> shakmaty::File {
     match x {
         0 => shakmaty::File::A,
@@ -206,6 +239,9 @@ impl std::fmt::Display for Outcome {
     }
 }
 
+/// A game as stored in `.bin` archives.
+///
+/// A time control of `-` in PGN is stored as `i32::MAX` for both fields.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Game {
     pub white_name: String,
@@ -218,54 +254,371 @@ pub struct Game {
     pub moves: Vec<Move>,
 }
 
+/// Evaluation attached to a ply, written as a `[%eval ...]` comment command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eval {
+    /// Advantage in pawns from white's point of view.
+    Pawns(f32),
+    /// Forced mate in the given number of moves, negative if black mates.
+    Mate(i32),
+}
+
+impl std::fmt::Display for Eval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pawns(pawns) => write!(f, "{:.2}", pawns),
+            Self::Mate(n) => write!(f, "#{}", n),
+        }
+    }
+}
+
+impl std::str::FromStr for Eval {
+    type Err = eyre::Report;
+
+    /// Parses evals as written in `[%eval ...]`, `1.25` or `#-3`.
+    fn from_str(s: &str) -> Result<Self> {
+        let eval = match s.trim().strip_prefix('#') {
+            Some(mate) => mate.parse().map(Self::Mate).ok(),
+            None => s.trim().parse().ok().filter(|p: &f32| p.is_finite()).map(Self::Pawns),
+        };
+        eval.ok_or_else(|| eyre::eyre!("Invalid eval {:?}, expected pawns or #moves", s))
+    }
+}
+
+/// Annotations written after a single ply by [`Game::write_pgn_with`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotation {
+    /// Numeric annotation glyphs, written as `$n` right after the move.
+    pub nags: Vec<u8>,
+    pub eval: Option<Eval>,
+    /// Remaining time on the mover's clock, written as `[%clk h:mm:ss]`.
+    pub clock: Option<Duration>,
+    pub comment: Option<String>,
+}
+
+impl Annotation {
+    fn comment_text(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(eval) = self.eval {
+            parts.push(format!("[%eval {}]", eval));
+        }
+        if let Some(clock) = self.clock {
+            let secs = clock.as_secs();
+            parts.push(format!(
+                "[%clk {}:{:02}:{:02}]",
+                secs / 3600,
+                (secs / 60) % 60,
+                secs % 60
+            ));
+        }
+        if let Some(comment) = &self.comment {
+            // A comment is terminated by the first closing brace, there is no escape
+            let comment = comment.replace('}', "");
+            if !comment.trim().is_empty() {
+                parts.push(comment.trim().to_string());
+            }
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" "))
+        }
+    }
+}
+
+/// Options for [`Game::write_pgn_with`].
+///
+/// The tags of the Seven Tag Roster which are not stored in a [`Game`] are taken
+/// from here and default to the "unknown" values defined by the PGN standard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnWriteOptions {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    /// Value of the `Termination` tag, the tag is omitted when `None`.
+    pub termination: Option<String>,
+    /// Maximum length of a movetext line, `0` disables wrapping.
+    pub line_width: usize,
+    /// Whether comments, evals and clocks of the annotations are written.
+    pub comments: bool,
+    /// Whether NAGs 1 to 6 are written as the traditional suffixes (`!`, `?`, ..) of the
+    /// move instead of `$n`. This is only valid in import format PGN.
+    pub nag_glyphs: bool,
+}
+
+impl Default for PgnWriteOptions {
+    fn default() -> Self {
+        Self {
+            event: "?".to_string(),
+            site: "?".to_string(),
+            date: "????.??.??".to_string(),
+            round: "?".to_string(),
+            termination: None,
+            line_width: 80,
+            comments: true,
+            nag_glyphs: false,
+        }
+    }
+}
+
+/// Writes movetext tokens separated by spaces, breaking lines before they exceed `width`.
+struct MovetextWriter<W: Write> {
+    inner: W,
+    width: usize,
+    line_len: usize,
+}
+
+impl<W: Write> MovetextWriter<W> {
+    fn push(&mut self, token: &str) -> Result<()> {
+        if self.line_len > 0 {
+            if self.width > 0 && self.line_len + 1 + token.len() > self.width {
+                writeln!(self.inner)?;
+                self.line_len = 0;
+            } else {
+                write!(self.inner, " ")?;
+                self.line_len += 1;
+            }
+        }
+        write!(self.inner, "{}", token)?;
+        self.line_len += token.len();
+        Ok(())
+    }
+
+    fn push_comment(&mut self, text: &str) -> Result<()> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        for (i, word) in words.iter().enumerate() {
+            let mut token = String::with_capacity(word.len() + 2);
+            if i == 0 {
+                token.push('{');
+            }
+            token.push_str(word);
+            if i == words.len() - 1 {
+                token.push('}');
+            }
+            self.push(&token)?;
+        }
+        Ok(())
+    }
+}
+
+fn nag_glyph(nag: u8) -> Option<&'static str> {
+    match nag {
+        1 => Some("!"),
+        2 => Some("?"),
+        3 => Some("!!"),
+        4 => Some("??"),
+        5 => Some("!?"),
+        6 => Some("?!"),
+        _ => None,
+    }
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
 impl Game {
-    pub fn write_pgn(&self, mut w: impl Write) -> Result<()> {
-        writeln!(w, "[White \"{}\"]", self.white_name)?;
-        writeln!(w, "[WhiteElo \"{}\"]", self.white_elo)?;
-        writeln!(w, "[Black \"{}\"]", self.black_name)?;
-        writeln!(w, "[BlackElo \"{}\"]", self.black_elo)?;
-        writeln!(
-            w,
-            "[TimeControl \"{}+{}\"]",
-            self.timectl_sec, self.timectl_inc
-        )?;
-        writeln!(w, "[Termination \"Normal\"]")?;
-        writeln!(w, "")?;
+    /// A drawn game between unnamed, unrated players without time control, with moves given
+    /// by their bitfields. Tests override other fields with struct update syntax.
+    pub(crate) fn test_with_moves(moves: &[u16]) -> Self {
+        Self {
+            white_name: String::new(),
+            black_name: String::new(),
+            white_elo: 0,
+            black_elo: 0,
+            outcome: Outcome::Draw,
+            timectl_sec: 0,
+            timectl_inc: 0,
+            moves: moves.iter().map(|&bitfield| Move { bitfield }).collect(),
+        }
+    }
+}
+
+impl Game {
+    /// Plays all moves from the starting position, failing on the first illegal one.
+    pub fn replay(&self) -> Result<Chess> {
+        let mut pos = Chess::new();
+        for (ply, mv) in self.moves.iter().enumerate() {
+            let m = mv.to_legal_move(&pos, ply + 1)?;
+            pos.play_unchecked(&m);
+        }
+        Ok(pos)
+    }
+
+    /// Writes the game as export format PGN with default [`PgnWriteOptions`] and no annotations.
+    pub fn write_pgn(&self, w: impl Write) -> Result<()> {
+        self.write_pgn_with(w, &PgnWriteOptions::default(), &[])
+    }
+
+    /// Writes the game as export format PGN.
+    ///
+    /// `annotations` is indexed by ply and may be shorter than the game.
+    pub fn write_pgn_with(
+        &self,
+        mut w: impl Write,
+        options: &PgnWriteOptions,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        ensure!(
+            annotations.len() <= self.moves.len(),
+            "Got {} annotations for a game of {} plies",
+            annotations.len(),
+            self.moves.len()
+        );
+
+        let timectl = if self.timectl_sec == i32::MAX {
+            "-".to_string()
+        } else {
+            format!("{}+{}", self.timectl_sec, self.timectl_inc)
+        };
+        let outcome = self.outcome.to_string();
+        let mut tags = vec![
+            ("Event", options.event.as_str()),
+            ("Site", options.site.as_str()),
+            ("Date", options.date.as_str()),
+            ("Round", options.round.as_str()),
+            ("White", self.white_name.as_str()),
+            ("Black", self.black_name.as_str()),
+            ("Result", outcome.as_str()),
+        ];
+        let white_elo = self.white_elo.to_string();
+        let black_elo = self.black_elo.to_string();
+        tags.push(("WhiteElo", white_elo.as_str()));
+        tags.push(("BlackElo", black_elo.as_str()));
+        tags.push(("TimeControl", timectl.as_str()));
+        if let Some(termination) = &options.termination {
+            tags.push(("Termination", termination.as_str()));
+        }
+        for (name, value) in tags {
+            writeln!(w, "[{} \"{}\"]", name, escape_tag_value(value))?;
+        }
+        writeln!(w)?;
 
+        let mut movetext = MovetextWriter {
+            inner: &mut w,
+            width: options.line_width,
+            line_len: 0,
+        };
         let mut pos = Chess::new();
+        let mut needs_number = true;
+        for (ply, mv) in self.moves.iter().enumerate() {
+            let number = ply / 2 + 1;
+            if ply % 2 == 0 {
+                movetext.push(&format!("{}.", number))?;
+            } else if needs_number {
+                movetext.push(&format!("{}...", number))?;
+            }
 
-        for (i, move_pair) in self.moves.chunks(2).enumerate() {
-            write!(w, "{}. ", i + 1)?;
-
-            let mut move_pair = move_pair.iter();
-            let white_move = move_pair.next().unwrap();
-            let white_move = UciMove::Normal {
-                from: white_move.move_from(),
-                to: white_move.move_to(),
-                promotion: white_move.promotion(),
-            };
-            let white_move = white_move.to_move(&pos).unwrap();
-            let white_san = San::from_move(&pos, &white_move);
-
-            write!(w, "{} ", white_san)?;
-            pos = pos.play(&white_move).unwrap();
-
-            if let Some(black_move) = move_pair.next() {
-                let black_move = UciMove::Normal {
-                    from: black_move.move_from(),
-                    to: black_move.move_to(),
-                    promotion: black_move.promotion(),
-                };
-                let black_move = black_move.to_move(&pos)?;
-                let black_san = San::from_move(&pos, &black_move);
-                write!(w, "{} ", black_san)?;
-                pos = pos.play(&black_move).unwrap();
+            let m = mv.to_legal_move(&pos, ply + 1)?;
+            let mut san = SanPlus::from_move_and_play_unchecked(&mut pos, &m).to_string();
+            let annotation = annotations.get(ply);
+            let mut nags = annotation.map(|a| a.nags.as_slice()).unwrap_or_default();
+            if options.nag_glyphs {
+                if let Some(glyph) = nags.first().and_then(|&nag| nag_glyph(nag)) {
+                    san.push_str(glyph);
+                    nags = &nags[1..];
+                }
+            }
+            movetext.push(&san)?;
+            needs_number = false;
+
+            if let Some(annotation) = annotation {
+                for nag in nags {
+                    movetext.push(&format!("${}", nag))?;
+                }
+                if options.comments {
+                    if let Some(text) = annotation.comment_text() {
+                        movetext.push_comment(&text)?;
+                        needs_number = true;
+                    }
+                }
             }
         }
+        movetext.push(&outcome)?;
 
-        writeln!(w, "{}", self.outcome)?;
         writeln!(w)?;
-
+        writeln!(w)?;
         Ok(())
     }
 }
+
+#[test]
+fn test_write_pgn_roundtrip() {
+    let pgn = include_str!("testfiles/test.pgn");
+    let mut visitor = super::pgn::PgnVisitor::new();
+    let mut reader = pgn_reader::BufferedReader::new_cursor(pgn);
+    let mut games = Vec::new();
+    while let Some(game) = reader.read_game(&mut visitor).unwrap() {
+        if let Some(g) = game.unwrap() {
+            games.push(g);
+        }
+    }
+    assert!(!games.is_empty());
+
+    let mut out = Vec::new();
+    for game in &games {
+        game.write_pgn(&mut out).unwrap();
+    }
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.lines().all(|l| l.len() <= 80));
+
+    let mut reader = pgn_reader::BufferedReader::new_cursor(out.as_bytes());
+    let mut exported = Vec::new();
+    while let Some(game) = reader.read_game(&mut visitor).unwrap() {
+        exported.push(game.unwrap().unwrap());
+    }
+    assert_eq!(games, exported);
+}
+
+#[test]
+fn test_write_pgn_tags_and_annotations() {
+    let pgn = include_str!("testfiles/single.pgn");
+    let mut visitor = super::pgn::PgnVisitor::new();
+    let mut reader = pgn_reader::BufferedReader::new_cursor(pgn);
+    let mut game = reader.read_game(&mut visitor).unwrap().unwrap().unwrap().unwrap();
+    game.white_name = "Mr \"Quoted\" \\ Player".to_string();
+
+    let annotations = vec![
+        Annotation::default(),
+        Annotation {
+            nags: vec![2],
+            eval: Some(Eval::Pawns(0.5)),
+            clock: Some(Duration::from_secs(303)),
+            comment: Some("Caro-Kann {Defence}".to_string()),
+        },
+    ];
+    let mut out = Vec::new();
+    game.write_pgn_with(&mut out, &PgnWriteOptions::default(), &annotations)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let tags: Vec<&str> = out.lines().take(7).collect();
+    assert_eq!(
+        tags,
+        &[
+            "[Event \"?\"]",
+            "[Site \"?\"]",
+            "[Date \"????.??.??\"]",
+            "[Round \"?\"]",
+            "[White \"Mr \\\"Quoted\\\" \\\\ Player\"]",
+            "[Black \"Navara, David\"]",
+            "[Result \"1-0\"]",
+        ]
+    );
+
+    let movetext = out.split_whitespace().collect::<Vec<_>>().join(" ");
+    assert!(movetext.contains("1. e4 c6 $2 {[%eval 0.50] [%clk 0:05:03] Caro-Kann {Defence} 2. d4"));
+    assert!(movetext.contains("10. Ne4 Qa5+ 11."));
+    assert!(movetext.ends_with("37. g3 1-0"));
+}
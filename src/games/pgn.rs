@@ -1,4 +1,5 @@
 use super::game::*;
+use crate::error::ParseError;
 use eyre::{ensure, Result};
 use pgn_reader::{SanPlus, Skip, Visitor};
 use shakmaty::{Chess, Position};
@@ -28,12 +29,19 @@ impl Headers {
     }
 }
 
+/// [`Visitor`] building a [`Game`] from a PGN game.
+///
+/// Games without a decisive or drawn result, or with a `Termination` other than
+/// `Normal`, are skipped and yield `Ok(None)`. Malformed games yield a [`ParseError`].
 #[derive(Debug, Clone)]
 pub struct PgnVisitor {
     headers: Headers,
     moves: Vec<Move>,
     board: Chess,
     skip: bool,
+    /// The first problem found in the current game
+    error: Option<String>,
+    games_started: usize,
 }
 
 impl PgnVisitor {
@@ -43,8 +51,37 @@ impl PgnVisitor {
             moves: vec![],
             skip: false,
             board: Chess::new(),
+            error: None,
+            games_started: 0,
         }
     }
+
+    fn parse_error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            game: self.games_started.saturating_sub(1),
+            message: message.into(),
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(message);
+        }
+    }
+
+    fn parse_header<T: std::str::FromStr>(&mut self, key: &str, value: &str) -> Option<T> {
+        let parsed = value.trim().parse().ok();
+        if parsed.is_none() {
+            self.fail(format!("Invalid {} header {:?}", key, value));
+        }
+        parsed
+    }
+}
+
+impl Default for PgnVisitor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Visitor for PgnVisitor {
@@ -55,44 +92,58 @@ impl Visitor for PgnVisitor {
         self.moves = Vec::new();
         self.skip = false;
         self.board = Chess::new();
+        self.error = None;
+        self.games_started += 1;
     }
 
     fn end_game(&mut self) -> Self::Result {
         if self.skip {
             return Ok(None);
         }
+        if let Some(message) = self.error.take() {
+            return Err(self.parse_error(message).into());
+        }
 
         ensure!(
             self.headers.black_name.is_some(),
-            "Black player name is missing"
+            self.parse_error("Black player name is missing")
         );
         let black_name = self.headers.black_name.as_ref().unwrap().to_owned();
 
         ensure!(
             self.headers.white_name.is_some(),
-            "White player name is missing"
+            self.parse_error("White player name is missing")
         );
         let white_name = self.headers.white_name.as_ref().unwrap().to_owned();
 
         ensure!(
             self.headers.black_elo.is_some(),
-            "Black player elo is missing"
+            self.parse_error("Black player elo is missing")
         );
         let black_elo = *self.headers.black_elo.as_ref().unwrap();
 
         ensure!(
             self.headers.white_elo.is_some(),
-            "White player elo is missing"
+            self.parse_error("White player elo is missing")
         );
         let white_elo = *self.headers.white_elo.as_ref().unwrap();
 
-        ensure!(self.headers.timectl_sec.is_some(), "Timecontrol missing");
+        ensure!(
+            self.headers.timectl_sec.is_some(),
+            self.parse_error("Timecontrol missing")
+        );
         let timectl_sec = *self.headers.timectl_sec.as_ref().unwrap();
 
-        ensure!(self.headers.timectl_inc.is_some(), "Timecontrol missing");
+        ensure!(
+            self.headers.timectl_inc.is_some(),
+            self.parse_error("Timecontrol missing")
+        );
         let timectl_inc = *self.headers.timectl_inc.as_ref().unwrap();
 
-        ensure!(self.headers.outcome.is_some(), "Outcome is missing");
+        ensure!(
+            self.headers.outcome.is_some(),
+            self.parse_error("Outcome is missing")
+        );
         let outcome = self.headers.outcome.as_ref().unwrap().to_owned();
 
         Ok(Some(Game {
@@ -110,18 +161,18 @@ impl Visitor for PgnVisitor {
     fn header(&mut self, key: &[u8], val: pgn_reader::RawHeader<'_>) {
         let value = val.decode_utf8_lossy().to_string();
         match key {
-            b"White" => self.headers.white_name = Some(value.into()),
-            b"Black" => self.headers.black_name = Some(value.into()),
-            b"WhiteElo" => self.headers.white_elo = Some(value.parse().unwrap()),
-            b"BlackElo" => self.headers.black_elo = Some(value.parse().unwrap()),
+            b"White" => self.headers.white_name = Some(value),
+            b"Black" => self.headers.black_name = Some(value),
+            b"WhiteElo" => self.headers.white_elo = self.parse_header("WhiteElo", &value),
+            b"BlackElo" => self.headers.black_elo = self.parse_header("BlackElo", &value),
             b"TimeControl" => {
                 if value != "-" {
                     let mut timectl = value.split('+');
                     let sec = timectl.next().unwrap_or("0");
                     let inc = timectl.next().unwrap_or("0");
 
-                    self.headers.timectl_sec = Some(sec.parse().unwrap());
-                    self.headers.timectl_inc = Some(inc.parse().unwrap());
+                    self.headers.timectl_sec = self.parse_header("TimeControl", sec);
+                    self.headers.timectl_inc = self.parse_header("TimeControl", inc);
                 } else {
                     self.headers.timectl_sec = Some(i32::MAX);
                     self.headers.timectl_inc = Some(i32::MAX);
@@ -133,11 +184,7 @@ impl Visitor for PgnVisitor {
                 "1/2-1/2" => self.headers.outcome = Some(Outcome::Draw),
                 _ => self.skip = true,
             },
-            b"Termination" => {
-                if value != "Normal" {
-                    self.skip = true;
-                }
-            }
+            b"Termination" if value != "Normal" => self.skip = true,
             _ => {}
         }
     }
@@ -151,14 +198,50 @@ impl Visitor for PgnVisitor {
     }
 
     fn san(&mut self, san_plus: SanPlus) {
-        let mv = san_plus.san.to_move(&self.board).unwrap();
-        let board = self.board.clone();
-        self.moves
-            .push(Move::new(mv.from().unwrap(), mv.to(), mv.promotion()));
-        self.board = board.play(&mv).unwrap();
+        if self.error.is_some() {
+            return;
+        }
+        match san_plus.san.to_move(&self.board) {
+            Ok(mv) => {
+                self.moves.push(Move::from(&mv));
+                self.board.play_unchecked(&mv);
+            }
+            Err(_) => {
+                let ply = self.moves.len() + 1;
+                self.fail(format!("Illegal move {} at ply {}", san_plus, ply));
+            }
+        }
     }
 }
 
+#[test]
+fn test_visitor_errors() {
+    let pgn = "[White \"a\"]\n[Black \"b\"]\n[WhiteElo \"?\"]\n[BlackElo \"1500\"]\n\
+               [TimeControl \"60+0\"]\n[Result \"1-0\"]\n\n1. e4 e5 1-0\n\n\
+               [White \"a\"]\n[Black \"b\"]\n[WhiteElo \"1500\"]\n[BlackElo \"1500\"]\n\
+               [TimeControl \"60+0\"]\n[Result \"1-0\"]\n\n1. e4 e4 1-0\n\n";
+    let mut visitor = PgnVisitor::new();
+    let mut reader = pgn_reader::BufferedReader::new_cursor(pgn);
+
+    let err = reader
+        .read_game(&mut visitor)
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    let err = err.downcast_ref::<ParseError>().unwrap();
+    assert_eq!(err.game, 0);
+    assert!(err.message.contains("WhiteElo"));
+
+    let err = reader
+        .read_game(&mut visitor)
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    let err = err.downcast_ref::<ParseError>().unwrap();
+    assert_eq!(err.game, 1);
+    assert_eq!(err.message, "Illegal move e4 at ply 2");
+}
+
 #[test]
 fn test_visitor() {
     let mut visitor = PgnVisitor::new();
@@ -1,11 +1,16 @@
 use super::game::*;
+use crate::error::{FormatError, IoError};
 use eyre::{ensure, Result};
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
 use std::path::Path;
 
 const MAGIC: &[u8] = b"PGNSUX";
+/// Upper bound on the serialized size of a game, guards against corrupted length prefixes.
+const MAX_GAME_LEN: usize = 1 << 20;
 
+/// Writes a `.bin` game archive: a magic header followed by length prefixed,
+/// bincode serialized [`Game`]s.
 pub struct Encoder<W: Write> {
     inner: W,
     written: usize,
@@ -17,7 +22,8 @@ impl Encoder<BufWriter<File>> {
             .write(true)
             .truncate(true)
             .create(true)
-            .open(p)?;
+            .open(p)
+            .map_err(|e| IoError::new(p, e))?;
         let w = BufWriter::new(f);
         Self::start(w)
     }
@@ -40,10 +46,15 @@ impl<W: Write> Encoder<W> {
         Ok(())
     }
 
-    #[allow(unused)]
     pub fn bytes_written(&self) -> usize {
         self.written
     }
+
+    /// Flushes the archive, reporting the errors which dropping the encoder would panic on.
+    pub fn finish(mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
 }
 
 impl<W: Write> Drop for Encoder<W> {
@@ -54,13 +65,18 @@ impl<W: Write> Drop for Encoder<W> {
 
 type DynReader = Box<dyn Read + Send>;
 
+/// Reads the games of a `.bin` archive written by [`Encoder`].
 pub struct Decoder {
     inner: DynReader,
+    read: u64,
 }
 
 impl Decoder {
     pub fn open(p: &Path) -> Result<Decoder> {
-        let f = OpenOptions::new().read(true).open(p)?;
+        let f = OpenOptions::new()
+            .read(true)
+            .open(p)
+            .map_err(|e| IoError::new(p, e))?;
         let r = BufReader::new(f);
         Decoder::start(Box::new(r))
     }
@@ -69,11 +85,23 @@ impl Decoder {
 impl Decoder {
     pub fn start(mut r: DynReader) -> Result<Self> {
         let mut buf = [0; MAGIC.len()];
-        r.read_exact(&mut buf)?;
-        ensure!(buf == MAGIC, "File format corrupted");
-        Ok(Self { inner: r })
+        r.read_exact(&mut buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => FormatError::new("File is too short").into(),
+            _ => eyre::Report::from(e),
+        })?;
+        ensure!(buf == MAGIC, FormatError::new("File format corrupted"));
+        Ok(Self {
+            inner: r,
+            read: MAGIC.len() as u64,
+        })
+    }
+
+    /// Bytes of the archive consumed so far, including the header.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
     }
 
+    /// Reads the bincode serialized bytes of the next game, `None` at the end of the archive.
     pub fn read_game_raw(&mut self) -> Result<Option<Vec<u8>>> {
         let mut lenbuf = [0; std::mem::size_of::<usize>()];
         if let Err(e) = self.inner.read_exact(&mut lenbuf) {
@@ -85,15 +113,26 @@ impl Decoder {
         }
 
         let game_len = usize::from_le_bytes(lenbuf);
+        ensure!(
+            game_len <= MAX_GAME_LEN,
+            FormatError::new(format!("Invalid game length {}", game_len))
+        );
 
         let mut gamebuf = vec![0; game_len];
-        self.inner.read_exact(&mut gamebuf)?;
+        self.inner.read_exact(&mut gamebuf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => FormatError::new("Game is truncated").into(),
+            _ => eyre::Report::from(e),
+        })?;
+        self.read += (lenbuf.len() + game_len) as u64;
         Ok(Some(gamebuf))
     }
 
     pub fn read_game(&mut self) -> Result<Option<Game>> {
         match self.read_game_raw() {
-            Ok(Some(gamebuf)) => Ok(Some(bincode::deserialize(&gamebuf)?)),
+            Ok(Some(gamebuf)) => Ok(Some(
+                bincode::deserialize(&gamebuf)
+                    .map_err(|e| FormatError::new(format!("Invalid game: {}", e)))?,
+            )),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
@@ -104,6 +143,7 @@ impl Decoder {
     }
 }
 
+/// Iterator over the serialized games of a [`Decoder`], panics on read errors.
 pub struct RawGameIter<'a> {
     inner: &'a mut Decoder
 }
@@ -184,7 +224,7 @@ fn test_identity() {
     let len = encoder.bytes_written();
     drop(encoder);
 
-    let cursor = Cursor::new((&buf[..len]).to_vec());
+    let cursor = Cursor::new(buf[..len].to_vec());
     let mut decoder = Decoder::start(Box::new(cursor)).unwrap();
     let mut games = original_games.into_iter();
     while let Some(g) = decoder.read_game().unwrap() {
@@ -1,15 +1,14 @@
-use pyo3::prelude::*;
+//! Chess game archives and neural network training data for chungus chess.
+//!
+//! [`games`] parses PGN and reads and writes the compact `.bin` game archives,
+//! [`data`] encodes games into training data. Long running conversions report to a
+//! [`progress::ProgressTracker`]. The Python extension module is built with the
+//! `python` feature.
 
-mod games;
-mod data;
+pub mod data;
+pub mod error;
+pub mod games;
+pub mod progress;
 
-#[pymodule]
-fn chessers(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    tracing_subscriber::fmt::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-
-    games::register(m)?;
-    data::register(m)?;
-    Ok(())
-}
\ No newline at end of file
+#[cfg(feature = "python")]
+mod python;
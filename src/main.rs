@@ -0,0 +1,484 @@
+use chessers::data::{self, encoder};
+use chessers::games::{self, game::Outcome, serialization::Decoder, GameFilter};
+use chessers::progress::{self, ProgressTracker};
+use clap::{Parser, Subcommand};
+use eyre::{bail, Result, WrapErr};
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+#[derive(Parser)]
+#[command(name = "chessers", about = "Data preparation for chungus chess")]
+struct Cli {
+    /// Seconds between progress reports of long running commands
+    #[arg(long, global = true, default_value_t = 10.0)]
+    progress_interval: f64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a .pgn file to a .bin archive or vice versa
+    Convert {
+        src: PathBuf,
+        dst: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        min_elo: i32,
+        #[arg(long, default_value_t = 5000)]
+        max_elo_diff: i32,
+    },
+    /// Print statistics about a .bin archive
+    Stat { file: PathBuf },
+    /// Copy the games of a .bin archive matching the given criteria into a new archive
+    Filter {
+        src: PathBuf,
+        dst: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        min_elo: i32,
+        #[arg(long, default_value_t = 5000)]
+        max_elo_diff: i32,
+        #[arg(long, default_value_t = 0)]
+        min_plies: usize,
+        /// Only keep games with this time control base in seconds
+        #[arg(long)]
+        timectl_sec: Option<i32>,
+    },
+    /// Encode a .bin archive into training data shards
+    Encode {
+        file: PathBuf,
+        out_dir: PathBuf,
+        /// Number of games per shard, without --dedup
+        #[arg(long, default_value_t = 1000)]
+        games: usize,
+        /// Name of the feature encoder
+        #[arg(long, default_value = encoder::DEFAULT_ENCODER)]
+        encoder: String,
+        /// Also store the move played next and the legal moves of each position
+        #[arg(long)]
+        policy: bool,
+        /// Element type the positions are loaded as
+        #[arg(long, value_enum, default_value_t)]
+        dtype: data::tensor::Dtype,
+        /// Axis order the positions are loaded in
+        #[arg(long, value_enum, default_value_t)]
+        layout: data::tensor::Layout,
+        /// Skip the positions of the first plies of each game
+        #[arg(long, default_value_t = 0)]
+        skip_plies: usize,
+        /// Keep each position with this probability
+        #[arg(long, default_value_t = 1.0)]
+        sample_probability: f64,
+        /// Keep at most this many positions per game
+        #[arg(long)]
+        max_per_game: Option<usize>,
+        /// Prefer later positions when capping positions per game
+        #[arg(long)]
+        ply_weighted: bool,
+        /// Seed of the position sampling
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Also write every position with colors swapped and the board flipped vertically
+        #[arg(long)]
+        color_flip: bool,
+        /// Also write every position without castling rights with the files mirrored
+        #[arg(long)]
+        mirror: bool,
+        /// Fade the result towards the Elo expectation by this factor per ply before the
+        /// end of the game
+        #[arg(long)]
+        discount: Option<f32>,
+        /// Weight of the Elo expectation blended into every outcome label
+        #[arg(long, default_value_t = 0.0)]
+        elo_weight: f32,
+        /// Share of draws in labels derived from expected scores
+        #[arg(long, default_value_t = 0.1)]
+        draw_rate: f32,
+        /// Also write the plies left until the end of the game of each position
+        #[arg(long)]
+        moves_left: bool,
+        /// Also write scalar features of each position: Elos, time control, ply, clocks and
+        /// material
+        #[arg(long)]
+        scalars: bool,
+        /// Also write the game ordinal, ply and Zobrist hash of each position, with a
+        /// weight of 1 per position
+        #[arg(long)]
+        provenance: bool,
+        /// Share of games written to the validation set, by a hash of each game. With
+        /// --val-ratio or --test-ratio `{set}` in the output directory is replaced by train,
+        /// val and test, without it these directories are created below it
+        #[arg(long, default_value_t = 0.0)]
+        val_ratio: f64,
+        /// Share of games written to the test set
+        #[arg(long, default_value_t = 0.0)]
+        test_ratio: f64,
+        /// What the set of a game is chosen by, moves unless given. Only with --val-ratio or
+        /// --test-ratio
+        #[arg(long, value_enum)]
+        split_by: Option<data::split::SplitKey>,
+        /// Seed of the split, independent of the seed of the sampling, 0 unless given. Only
+        /// with --val-ratio or --test-ratio
+        #[arg(long)]
+        split_seed: Option<u64>,
+        /// Write every distinct position once, labelled with its mean outcome and weighted
+        /// by its number of occurrences
+        #[arg(long)]
+        dedup: bool,
+        /// Number of positions per shard with --dedup
+        #[arg(long, default_value_t = 100_000)]
+        shard_positions: usize,
+        /// Distinct positions held in memory with --dedup before spilling them to disk
+        #[arg(long, default_value_t = 1_000_000)]
+        memory_positions: usize,
+    },
+    /// Print games of a .bin archive as PGN
+    Inspect {
+        file: PathBuf,
+        /// Index of the first game to print
+        #[arg(long, default_value_t = 0)]
+        index: usize,
+        #[arg(long, default_value_t = 1)]
+        count: usize,
+    },
+    /// Check that every game of a .bin archive decodes and consists of legal moves
+    Verify { file: PathBuf },
+}
+
+fn has_extension(path: &std::path::Path, ext: &str) -> bool {
+    path.extension().is_some_and(|e| e == ext)
+}
+
+/// Tracker logging the progress of a command every `interval` seconds.
+fn tracker(interval: f64) -> Result<ProgressTracker<'static>> {
+    let tracker = ProgressTracker::new()
+        .with_interval(progress::interval_from_secs(interval)?)
+        .with_callback(|progress| {
+            if !progress.done {
+                info!("{}", progress);
+            }
+            Ok(())
+        });
+    Ok(tracker)
+}
+
+fn convert(
+    src: PathBuf,
+    dst: PathBuf,
+    min_elo: i32,
+    max_elo_diff: i32,
+    tracker: &mut ProgressTracker<'_>,
+) -> Result<()> {
+    if has_extension(&src, "pgn") && has_extension(&dst, "bin") {
+        info!(
+            "Converting {} from PGN to binary {}",
+            src.display(),
+            dst.display()
+        );
+        games::pgn_to_bin(&src, &dst, min_elo, max_elo_diff, tracker)?;
+        info!("{}", tracker.progress());
+        Ok(())
+    } else if has_extension(&src, "bin") && has_extension(&dst, "pgn") {
+        info!(
+            "Converting {} from binary to PGN {}",
+            src.display(),
+            dst.display()
+        );
+        games::bin_to_pgn(&src, &dst, min_elo, max_elo_diff, tracker)?;
+        info!("{}", tracker.progress());
+        Ok(())
+    } else {
+        bail!("Invalid combination of inputs, you can only convert .bin to .pgn or vice versa")
+    }
+}
+
+/// Output directory of a split set, `{set}` in `out_dir` replaced by its name or below
+/// `out_dir` without one.
+fn split_dir(out_dir: &std::path::Path, set: data::split::SplitSet) -> PathBuf {
+    let template = out_dir.to_string_lossy();
+    if template.contains("{set}") {
+        PathBuf::from(template.replace("{set}", set.name()))
+    } else {
+        out_dir.join(set.name())
+    }
+}
+
+/// The split of the encode command, `None` unless a validation or test ratio is given.
+fn encode_split(
+    val_ratio: f64,
+    test_ratio: f64,
+    split_by: Option<data::split::SplitKey>,
+    split_seed: Option<u64>,
+) -> Result<Option<data::split::Split>> {
+    if val_ratio == 0.0 && test_ratio == 0.0 {
+        if split_by.is_some() || split_seed.is_some() {
+            bail!("--split-by and --split-seed need --val-ratio or --test-ratio");
+        }
+        return Ok(None);
+    }
+    let split = data::split::Split {
+        validation: val_ratio,
+        test: test_ratio,
+        key: split_by.unwrap_or_default(),
+        seed: split_seed.unwrap_or_default(),
+    };
+    split.check()?;
+    Ok(Some(split))
+}
+
+fn stat(file: PathBuf) -> Result<()> {
+    let size = std::fs::metadata(&file)
+        .wrap_err_with(|| format!("failed to stat {}", file.display()))?
+        .len();
+    let mut decoder = Decoder::open(&file).wrap_err("failed to open bin file")?;
+    let (mut games, mut moves, mut elo_sum) = (0usize, 0usize, 0i64);
+    let (mut white_wins, mut black_wins, mut draws) = (0usize, 0usize, 0usize);
+    while let Some(game) = decoder.read_game()? {
+        games += 1;
+        moves += game.moves.len();
+        elo_sum += game.white_elo as i64 + game.black_elo as i64;
+        match game.outcome {
+            Outcome::WhiteWin => white_wins += 1,
+            Outcome::BlackWin => black_wins += 1,
+            Outcome::Draw => draws += 1,
+        }
+    }
+
+    println!(
+        "name:    {}",
+        file.file_name().unwrap_or_default().to_string_lossy()
+    );
+    println!("size:    {}", size);
+    println!("games:   {}", games);
+    println!("moves:   {}", moves);
+    if games > 0 {
+        println!("avg elo: {:.0}", elo_sum as f64 / (2 * games) as f64);
+        println!(
+            "results: {} / {} / {} (1-0 / 1/2-1/2 / 0-1)",
+            white_wins, draws, black_wins
+        );
+    }
+    Ok(())
+}
+
+fn inspect(file: PathBuf, index: usize, count: usize) -> Result<()> {
+    let mut decoder = Decoder::open(&file).wrap_err("failed to open bin file")?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut games = 0;
+    while games < index.saturating_add(count) {
+        let Some(game) = decoder
+            .read_game()
+            .wrap_err_with(|| format!("failed to decode game {}", games))?
+        else {
+            break;
+        };
+        if games >= index {
+            game.write_pgn(&mut out)?;
+        }
+        games += 1;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn verify(file: PathBuf) -> Result<()> {
+    let mut decoder = Decoder::open(&file).wrap_err("failed to open bin file")?;
+    let (mut games, mut invalid) = (0usize, 0usize);
+    while let Some(game) = decoder
+        .read_game()
+        .wrap_err_with(|| format!("failed to decode game {}", games))?
+    {
+        if let Err(e) = game.replay() {
+            warn!("Game {} is invalid: {:?}", games, e);
+            invalid += 1;
+        }
+        games += 1;
+    }
+    println!("{} games, {} invalid", games, invalid);
+    if invalid > 0 {
+        bail!("{} of {} games are invalid", invalid, games);
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    let cli = Cli::parse();
+    let mut tracker = tracker(cli.progress_interval)?;
+    match cli.command {
+        Command::Convert {
+            src,
+            dst,
+            min_elo,
+            max_elo_diff,
+        } => convert(src, dst, min_elo, max_elo_diff, &mut tracker),
+        Command::Stat { file } => stat(file),
+        Command::Filter {
+            src,
+            dst,
+            min_elo,
+            max_elo_diff,
+            min_plies,
+            timectl_sec,
+        } => {
+            let filter = GameFilter {
+                min_elo,
+                max_elo_diff,
+                min_plies,
+                timectl_sec,
+            };
+            let (read, written) =
+                games::bin_to_bin(&src, &dst, |game| filter.accepts(game), &mut tracker)?;
+            info!("Kept {} of {} games", written, read);
+            Ok(())
+        }
+        Command::Encode {
+            file,
+            out_dir,
+            games,
+            encoder,
+            policy,
+            dtype,
+            layout,
+            skip_plies,
+            sample_probability,
+            max_per_game,
+            ply_weighted,
+            seed,
+            color_flip,
+            mirror,
+            discount,
+            elo_weight,
+            draw_rate,
+            moves_left,
+            scalars,
+            provenance,
+            val_ratio,
+            test_ratio,
+            split_by,
+            split_seed,
+            dedup,
+            shard_positions,
+            memory_positions,
+        } => {
+            let encoder = encoder::encoder(&encoder)?;
+            let format = data::tensor::TensorFormat { dtype, layout };
+            let sampling = data::sampling::Sampling {
+                skip_plies,
+                probability: sample_probability,
+                max_per_game,
+                weighting: if ply_weighted {
+                    data::sampling::Weighting::Ply
+                } else {
+                    data::sampling::Weighting::Uniform
+                },
+                seed,
+            };
+            let options = data::EncodeOptions {
+                policy,
+                format,
+                sampling,
+                augmentation: data::augment::Augmentation { color_flip, mirror },
+                targets: data::targets::ValueTargets {
+                    discount,
+                    elo_weight,
+                    draw_rate,
+                    moves_left,
+                },
+                scalars,
+                provenance,
+            };
+            if let Some(split) = encode_split(val_ratio, test_ratio, split_by, split_seed)? {
+                if dedup {
+                    bail!("Splits can not be combined with --dedup");
+                }
+                let shards = data::split::convert_games_split_and_save(
+                    &file,
+                    games,
+                    |set| split_dir(&out_dir, set),
+                    &*encoder,
+                    &options,
+                    &split,
+                    &mut tracker,
+                )?;
+                info!(
+                    "Wrote {} train, {} val and {} test shards to {}",
+                    shards[0],
+                    shards[1],
+                    shards[2],
+                    out_dir.display()
+                );
+                return Ok(());
+            }
+            std::fs::create_dir_all(&out_dir)
+                .wrap_err_with(|| format!("failed to create {}", out_dir.display()))?;
+            let shards = if dedup {
+                let dedup = data::dedup::DedupOptions {
+                    memory_positions,
+                    shard_positions,
+                };
+                data::dedup::convert_games_dedup_and_save(
+                    &file,
+                    &out_dir,
+                    &*encoder,
+                    &options,
+                    &dedup,
+                    &mut tracker,
+                )?
+            } else {
+                data::convert_games_and_save(
+                    &file,
+                    games,
+                    &out_dir,
+                    &*encoder,
+                    &options,
+                    &mut tracker,
+                )?
+            };
+            info!("Wrote {} shards to {}", shards, out_dir.display());
+            Ok(())
+        }
+        Command::Inspect { file, index, count } => inspect(file, index, count),
+        Command::Verify { file } => verify(file),
+    }
+}
+
+#[test]
+fn test_encode_split() {
+    use data::split::{Split, SplitKey};
+
+    let split = |args: &[&str]| {
+        let args = ["chessers", "encode", "games.bin", "out"].iter().chain(args);
+        match Cli::try_parse_from(args).unwrap().command {
+            Command::Encode {
+                val_ratio,
+                test_ratio,
+                split_by,
+                split_seed,
+                ..
+            } => encode_split(val_ratio, test_ratio, split_by, split_seed),
+            _ => unreachable!(),
+        }
+    };
+    assert_eq!(split(&[]).unwrap(), None);
+    // Split options without a ratio do not switch to split mode
+    assert!(split(&["--split-by", "players"]).is_err());
+    assert!(split(&["--split-seed", "3"]).is_err());
+    assert_eq!(
+        split(&["--test-ratio", "0.1", "--split-by", "players"]).unwrap(),
+        Some(Split {
+            test: 0.1,
+            key: SplitKey::Players,
+            ..Default::default()
+        })
+    );
+    assert!(split(&["--val-ratio=-0.1"]).is_err());
+    assert!(Cli::try_parse_from(["chessers", "encode", "a", "b", "--split-by", "names"]).is_err());
+}
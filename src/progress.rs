@@ -0,0 +1,224 @@
+//! Progress reporting and cancellation for long running operations.
+//!
+//! Conversions take a [`ProgressTracker`], which counts the work done, hands a
+//! [`Progress`] snapshot to its callback every interval and aborts the operation with
+//! [`Cancelled`] once its [`CancelToken`] is cancelled.
+
+use crate::error::Cancelled;
+use eyre::{ensure, Result, WrapErr};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Snapshot of the work done by an operation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    /// Bytes of the source file consumed so far.
+    pub bytes_processed: u64,
+    /// Size of the source file, if known.
+    pub bytes_total: Option<u64>,
+    /// Games written to the output.
+    pub games_accepted: usize,
+    /// Games skipped or filtered out.
+    pub games_rejected: usize,
+    /// Positions encoded into training data.
+    pub positions: usize,
+    pub elapsed: Duration,
+    /// Whether this is the final report of a successful operation.
+    pub done: bool,
+}
+
+impl Progress {
+    /// Fraction of the source file consumed, between 0 and 1.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.bytes_total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_processed as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+
+    /// Estimated time until completion, extrapolated from the bytes processed so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction()?;
+        if self.done || fraction >= 1.0 {
+            return Some(Duration::ZERO);
+        }
+        if fraction == 0.0 {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1.0 - fraction) / fraction))
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} games accepted, {} rejected",
+            self.games_accepted, self.games_rejected
+        )?;
+        if self.positions > 0 {
+            write!(f, ", {} positions", self.positions)?;
+        }
+        if let Some(fraction) = self.fraction() {
+            write!(f, ", {:.1}%", fraction * 100.0)?;
+        }
+        if let Some(eta) = self.eta().filter(|_| !self.done) {
+            write!(f, ", ETA {}s", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared flag for cancelling an operation from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+type Callback<'a> = Box<dyn FnMut(&Progress) -> Result<()> + Send + 'a>;
+
+/// The interval of `secs` seconds between progress reports, an error unless `secs` is a
+/// non-negative number of seconds a [`Duration`] can hold.
+pub fn interval_from_secs(secs: f64) -> Result<Duration> {
+    ensure!(
+        secs >= 0.0,
+        "Progress interval has to be a non-negative number of seconds, got {}",
+        secs
+    );
+    Duration::try_from_secs_f64(secs)
+        .wrap_err_with(|| format!("Invalid progress interval {}", secs))
+}
+
+/// Counts the work of an operation and reports it to an optional callback.
+///
+/// An error returned by the callback aborts the operation and is passed on to its caller.
+pub struct ProgressTracker<'a> {
+    progress: Progress,
+    start: Instant,
+    last_report: Instant,
+    interval: Duration,
+    callback: Option<Callback<'a>>,
+    cancel: Option<CancelToken>,
+}
+
+impl<'a> ProgressTracker<'a> {
+    /// A tracker without callback or cancel token, reporting every second once given one.
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            progress: Progress::default(),
+            start: now,
+            last_report: now,
+            interval: Duration::from_secs(1),
+            callback: None,
+            cancel: None,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_callback(
+        mut self,
+        callback: impl FnMut(&Progress) -> Result<()> + Send + 'a,
+    ) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    pub fn set_bytes_total(&mut self, bytes_total: Option<u64>) {
+        self.progress.bytes_total = bytes_total;
+    }
+
+    /// Applies `update` to the counters, then fails if the operation was cancelled and
+    /// calls the callback if the interval has passed since its last call.
+    pub fn update(&mut self, update: impl FnOnce(&mut Progress)) -> Result<()> {
+        update(&mut self.progress);
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(Cancelled.into());
+        }
+        if self.last_report.elapsed() >= self.interval {
+            self.report()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the operation as done and calls the callback a final time.
+    pub fn finish(&mut self) -> Result<()> {
+        self.progress.done = true;
+        self.report()
+    }
+
+    fn report(&mut self) -> Result<()> {
+        self.last_report = Instant::now();
+        self.progress.elapsed = self.start.elapsed();
+        match self.callback.as_mut() {
+            Some(callback) => callback(&self.progress),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for ProgressTracker<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_progress_tracker() {
+    let mut reports = Vec::new();
+    let cancel = CancelToken::new();
+    {
+        let mut tracker = ProgressTracker::new()
+            .with_interval(Duration::ZERO)
+            .with_callback(|p| {
+                reports.push(p.clone());
+                Ok(())
+            })
+            .with_cancel_token(cancel.clone());
+        tracker.set_bytes_total(Some(100));
+        tracker.update(|p| p.bytes_processed = 50).unwrap();
+        tracker.finish().unwrap();
+
+        cancel.cancel();
+        let err = tracker.update(|p| p.games_accepted += 1).unwrap_err();
+        assert!(err.downcast_ref::<Cancelled>().is_some());
+    }
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].fraction(), Some(0.5));
+    assert!(!reports[0].done);
+    assert!(reports[1].done);
+    assert_eq!(reports[1].eta(), Some(Duration::ZERO));
+
+    assert_eq!(interval_from_secs(0.5).unwrap(), Duration::from_millis(500));
+    for secs in [-1.0, f64::NAN, f64::INFINITY] {
+        assert!(interval_from_secs(secs).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Python bindings, compiled with the `python` feature.
+//!
+//! The extension module is `chessers._chessers`, the `chessers` package in
+//! `python/chessers` re-exports it and makes its submodules importable.
+
+use pyo3::prelude::*;
+
+mod data;
+mod errors;
+mod games;
+mod logging;
+mod progress;
+
+fn add_submodule(
+    parent: &Bound<'_, PyModule>,
+    name: &str,
+    register: fn(&Bound<'_, PyModule>) -> PyResult<()>,
+) -> PyResult<()> {
+    let m = PyModule::new_bound(parent.py(), name)?;
+    register(&m)?;
+    parent.add_submodule(&m)?;
+    m.setattr("__name__", format!("chessers.{}", name))
+}
+
+#[pymodule]
+#[pyo3(name = "_chessers")]
+fn chessers(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    logging::init();
+
+    errors::register(m)?;
+    logging::register(m)?;
+    progress::register(m)?;
+    add_submodule(m, "games", games::register)?;
+    add_submodule(m, "data", data::register)?;
+    Ok(())
+}
@@ -0,0 +1,892 @@
+use crate::data::sampling::SplitMix64;
+use crate::data::tensor::{Dtype, InputTensor, TensorFormat};
+use crate::data::{self, encoder, policy};
+use eyre::{eyre, Result, WrapErr};
+use numpy::{
+    PyArray1, PyArray2, PyArray3, PyArray4, PyArrayMethods, PyReadonlyArray1, PyReadonlyArray3,
+};
+use super::errors::to_py_err;
+use super::games::Game;
+use pyo3::exceptions::PyValueError;
+use super::progress::{tracker, CancelToken};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyBytesMethods, PyTuple};
+use shakmaty::uci::UciMove;
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Move indices and legal move masks of policy targets.
+type PolicyArrays = (Py<PyArray1<i32>>, Py<PyArray2<bool>>);
+
+/// Game ordinals, plies and hashes of the provenance of positions.
+type ProvenanceArrays = (Py<PyArray1<u64>>, Py<PyArray1<u32>>, Py<PyArray1<u64>>);
+
+/// A batch of inputs as NumPy array of its dtype.
+fn ins_to_py(py: Python<'_>, ins: InputTensor) -> PyObject {
+    match ins {
+        InputTensor::Float32(ins) => PyArray4::from_owned_array_bound(py, ins).into_py(py),
+        InputTensor::Float16(ins) => PyArray4::from_owned_array_bound(py, ins).into_py(py),
+        InputTensor::Uint8(ins) => PyArray4::from_owned_array_bound(py, ins).into_py(py),
+        InputTensor::Bool(ins) => PyArray4::from_owned_array_bound(py, ins).into_py(py),
+    }
+}
+
+fn ins_from_py(ins: &Bound<'_, PyAny>, dtype: Dtype) -> PyResult<InputTensor> {
+    Ok(match dtype {
+        Dtype::Float32 => InputTensor::Float32(ins.downcast::<PyArray4<f32>>()?.to_owned_array()),
+        Dtype::Float16 => InputTensor::Float16(ins.downcast::<PyArray4<half::f16>>()?.to_owned_array()),
+        Dtype::Uint8 => InputTensor::Uint8(ins.downcast::<PyArray4<u8>>()?.to_owned_array()),
+        Dtype::Bool => InputTensor::Bool(ins.downcast::<PyArray4<bool>>()?.to_owned_array()),
+    })
+}
+
+pub fn tensor_format(dtype: &str, layout: &str) -> PyResult<TensorFormat> {
+    let invalid = |e: eyre::Report| PyValueError::new_err(e.to_string());
+    Ok(TensorFormat {
+        dtype: dtype.parse().map_err(invalid)?,
+        layout: layout.parse().map_err(invalid)?,
+    })
+}
+
+/// Which positions of each game are encoded, see `data::sampling::Sampling`.
+#[pyclass(module = "chessers.data")]
+#[derive(Clone)]
+pub struct Sampling {
+    pub inner: data::sampling::Sampling,
+}
+
+#[pymethods]
+impl Sampling {
+    #[new]
+    #[pyo3(signature = (skip_plies=0, probability=1.0, max_per_game=None, ply_weighted=false, seed=0))]
+    fn new(
+        skip_plies: usize,
+        probability: f64,
+        max_per_game: Option<usize>,
+        ply_weighted: bool,
+        seed: u64,
+    ) -> PyResult<Self> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(PyValueError::new_err(format!(
+                "probability {} is not between 0 and 1",
+                probability
+            )));
+        }
+        let weighting = if ply_weighted {
+            data::sampling::Weighting::Ply
+        } else {
+            data::sampling::Weighting::Uniform
+        };
+        Ok(Self {
+            inner: data::sampling::Sampling {
+                skip_plies,
+                probability,
+                max_per_game,
+                weighting,
+                seed,
+            },
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        let s = &self.inner;
+        format!(
+            "Sampling(skip_plies={}, probability={:?}, max_per_game={}, ply_weighted={}, seed={})",
+            s.skip_plies,
+            s.probability,
+            s.max_per_game.map_or("None".to_string(), |m| m.to_string()),
+            if s.weighting == data::sampling::Weighting::Ply { "True" } else { "False" },
+            s.seed
+        )
+    }
+}
+
+/// How outcome labels are built, see `data::targets::ValueTargets`.
+#[pyclass(module = "chessers.data")]
+#[derive(Clone)]
+pub struct ValueTargets {
+    pub inner: data::targets::ValueTargets,
+}
+
+#[pymethods]
+impl ValueTargets {
+    #[new]
+    #[pyo3(signature = (discount=None, elo_weight=0.0, draw_rate=0.1, moves_left=false))]
+    fn new(discount: Option<f32>, elo_weight: f32, draw_rate: f32, moves_left: bool) -> PyResult<Self> {
+        let inner = data::targets::ValueTargets {
+            discount,
+            elo_weight,
+            draw_rate,
+            moves_left,
+        };
+        inner
+            .check()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    fn __repr__(&self) -> String {
+        let t = &self.inner;
+        format!(
+            "ValueTargets(discount={}, elo_weight={:?}, draw_rate={:?}, moves_left={})",
+            t.discount.map_or("None".to_string(), |d| format!("{:?}", d)),
+            t.elo_weight,
+            t.draw_rate,
+            if t.moves_left { "True" } else { "False" }
+        )
+    }
+}
+
+/// How games are split into train, validation and test sets, see `data::split::Split`.
+#[pyclass(module = "chessers.data")]
+#[derive(Clone)]
+pub struct Split {
+    pub inner: data::split::Split,
+}
+
+#[pymethods]
+impl Split {
+    #[new]
+    #[pyo3(signature = (validation=0.0, test=0.0, by="moves", seed=0))]
+    fn new(validation: f64, test: f64, by: &str, seed: u64) -> PyResult<Self> {
+        let inner = data::split::Split {
+            validation,
+            test,
+            key: by.parse().map_err(|e: eyre::Report| PyValueError::new_err(e.to_string()))?,
+            seed,
+        };
+        inner
+            .check()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    /// Set of a game, `"train"`, `"val"` or `"test"`, or `None` if the split leaves it out.
+    fn assign(&self, game: PyRef<'_, Game>) -> Option<&'static str> {
+        self.inner.assign(&game.inner).map(|set| set.name())
+    }
+
+    fn __repr__(&self) -> String {
+        let s = &self.inner;
+        let by = match s.key {
+            data::split::SplitKey::Moves => "moves",
+            data::split::SplitKey::Players => "players",
+        };
+        format!(
+            "Split(validation={:?}, test={:?}, by={:?}, seed={})",
+            s.validation, s.test, by, s.seed
+        )
+    }
+}
+
+#[pyclass(module = "chessers.data")]
+pub struct TrainData {
+    /// The inputs in `format`.
+    ins: PyObject,
+    outs: Py<PyArray2<f32>>,
+    encoder: String,
+    plane_names: Vec<String>,
+    history: usize,
+    policy: Option<PolicyArrays>,
+    format: TensorFormat,
+    weights: Option<Py<PyArray1<f32>>>,
+    moves_left: Option<Py<PyArray1<f32>>>,
+    scalars: Option<Py<PyArray2<f32>>>,
+    scalar_names: Vec<String>,
+    provenance: Option<ProvenanceArrays>,
+}
+
+impl TrainData {
+    pub fn new(py: Python<'_>, data: data::TrainData) -> PyResult<Self> {
+        let ins = ins_to_py(py, data.formatted_ins().map_err(to_py_err)?);
+        let outs = PyArray2::from_owned_array_bound(py, data.outs).unbind();
+        let policy = data.policy.map(|policy| {
+            (
+                PyArray1::from_owned_array_bound(py, policy.moves).unbind(),
+                PyArray2::from_owned_array_bound(py, policy.legal).unbind(),
+            )
+        });
+
+        Ok(TrainData {
+            ins,
+            outs,
+            encoder: data.encoder,
+            plane_names: data.plane_names,
+            history: data.history,
+            policy,
+            format: data.format,
+            weights: data
+                .weights
+                .map(|weights| PyArray1::from_owned_array_bound(py, weights).unbind()),
+            moves_left: data
+                .moves_left
+                .map(|moves_left| PyArray1::from_owned_array_bound(py, moves_left).unbind()),
+            scalars: data
+                .scalars
+                .map(|scalars| PyArray2::from_owned_array_bound(py, scalars).unbind()),
+            scalar_names: data.scalar_names,
+            provenance: data.provenance.map(|provenance| {
+                (
+                    PyArray1::from_owned_array_bound(py, provenance.games).unbind(),
+                    PyArray1::from_owned_array_bound(py, provenance.plies).unbind(),
+                    PyArray1::from_owned_array_bound(py, provenance.hashes).unbind(),
+                )
+            }),
+        })
+    }
+
+    fn to_data(&self, py: Python<'_>) -> PyResult<data::TrainData> {
+        let ins = ins_from_py(self.ins.bind(py), self.format.dtype)?.into_f32(self.format.layout);
+        let outs = self.outs.bind(py).readonly().as_array().to_owned();
+        let policy = self.policy.as_ref().map(|(moves, legal)| data::policy::Policy {
+            moves: moves.bind(py).readonly().as_array().to_owned(),
+            legal: legal.bind(py).readonly().as_array().to_owned(),
+        });
+        Ok(data::TrainData {
+            ins,
+            outs,
+            encoder: self.encoder.clone(),
+            plane_names: self.plane_names.clone(),
+            history: self.history,
+            policy,
+            format: self.format,
+            weights: self
+                .weights
+                .as_ref()
+                .map(|weights| weights.bind(py).readonly().as_array().to_owned()),
+            moves_left: self
+                .moves_left
+                .as_ref()
+                .map(|moves_left| moves_left.bind(py).readonly().as_array().to_owned()),
+            scalars: self
+                .scalars
+                .as_ref()
+                .map(|scalars| scalars.bind(py).readonly().as_array().to_owned()),
+            scalar_names: self.scalar_names.clone(),
+            provenance: self.provenance.as_ref().map(|(games, plies, hashes)| {
+                data::Provenance {
+                    games: games.bind(py).readonly().as_array().to_owned(),
+                    plies: plies.bind(py).readonly().as_array().to_owned(),
+                    hashes: hashes.bind(py).readonly().as_array().to_owned(),
+                }
+            }),
+        })
+    }
+}
+
+#[pymethods]
+impl TrainData {
+    fn get_ins(&self, py: Python<'_>) -> PyObject {
+        self.ins.clone_ref(py)
+    }
+
+    fn get_outs(slf: PyRef<'_, Self>) -> Bound<'_, PyArray2<f32>> {
+        let outs = slf.outs.clone_ref(slf.py());
+        outs.into_bound(slf.py())
+    }
+
+    /// The vocabulary indices of the moves played next and the legal move masks, if the
+    /// data has policy targets.
+    fn get_policy(slf: PyRef<'_, Self>) -> Option<PyPolicy<'_>> {
+        let py = slf.py();
+        slf.policy
+            .as_ref()
+            .map(|(moves, legal)| (moves.clone_ref(py).into_bound(py), legal.clone_ref(py).into_bound(py)))
+    }
+
+    /// Weight of each position, if the data has any.
+    fn get_weights(&self, py: Python<'_>) -> Option<Py<PyArray1<f32>>> {
+        self.weights.as_ref().map(|weights| weights.clone_ref(py))
+    }
+
+    /// Plies left until the end of the game of each position, if the data has them.
+    fn get_moves_left(&self, py: Python<'_>) -> Option<Py<PyArray1<f32>>> {
+        self.moves_left.as_ref().map(|moves_left| moves_left.clone_ref(py))
+    }
+
+    /// Scalar features of shape `(positions, features)`, if the data has them.
+    fn get_scalars(&self, py: Python<'_>) -> Option<Py<PyArray2<f32>>> {
+        self.scalars.as_ref().map(|scalars| scalars.clone_ref(py))
+    }
+
+    fn scalar_names(&self) -> Vec<String> {
+        self.scalar_names.clone()
+    }
+
+    /// The ordinal of the source game in its `.bin` archive, the number of moves played
+    /// before and the Zobrist hash of each position, if the data has them.
+    fn get_provenance(&self, py: Python<'_>) -> Option<ProvenanceArrays> {
+        self.provenance.as_ref().map(|(games, plies, hashes)| {
+            (games.clone_ref(py), plies.clone_ref(py), hashes.clone_ref(py))
+        })
+    }
+
+    fn encoder(&self) -> &str {
+        &self.encoder
+    }
+
+    fn plane_names(&self) -> Vec<String> {
+        self.plane_names.clone()
+    }
+
+    fn history(&self) -> usize {
+        self.history
+    }
+
+    fn dtype(&self) -> String {
+        self.format.dtype.to_string()
+    }
+
+    fn layout(&self) -> String {
+        self.format.layout.to_string()
+    }
+
+    fn to_bytes(slf: PyRef<'_, Self>) -> PyResult<Bound<'_, PyBytes>> {
+        let data = slf.to_data(slf.py())?.encode_bin();
+        PyBytes::new_bound_with(slf.py(), data.len(), |buf| {
+            buf.copy_from_slice(&data);
+            Ok(())
+        })
+    }
+
+    #[staticmethod]
+    fn from_bytes(py: Python<'_>, data: &Bound<'_, PyBytes>) -> PyResult<Self> {
+        let data = data::TrainData::decode_bin(data.as_bytes())
+            .map_err(to_py_err)?;
+        Self::new(py, data)
+    }
+
+    fn save(slf: PyRef<'_, Self>, path: &str) -> PyResult<()> {
+        let data = slf.to_data(slf.py())?;
+        slf.py()
+            .allow_threads(|| data.save(&PathBuf::from(path)))
+            .map_err(to_py_err)
+    }
+
+    #[staticmethod]
+    fn load(py: Python<'_>, path: &str) -> PyResult<Self> {
+        let data = py
+            .allow_threads(|| data::TrainData::load(&PathBuf::from(path)))
+            .map_err(to_py_err)?;
+        Self::new(py, data)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (
+        path, max_games, name, encoder=encoder::DEFAULT_ENCODER, policy=false, dtype="float32",
+        layout="nhwc", sampling=None, color_flip=false, mirror=false, targets=None,
+        scalars=false, provenance=false, progress=None, interval=1.0, cancel=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn convert_games_and_save(
+        py: Python<'_>,
+        path: PathBuf,
+        max_games: usize,
+        name: &str,
+        encoder: &str,
+        policy: bool,
+        dtype: &str,
+        layout: &str,
+        sampling: Option<Sampling>,
+        color_flip: bool,
+        mirror: bool,
+        targets: Option<ValueTargets>,
+        scalars: bool,
+        provenance: bool,
+        progress: Option<PyObject>,
+        interval: f64,
+        cancel: Option<CancelToken>,
+    ) -> PyResult<()> {
+        let out_dir = PathBuf::from("data/train").join(name);
+        let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+        let format = tensor_format(dtype, layout)?;
+        let options = data::EncodeOptions {
+            policy,
+            format,
+            sampling: sampling.map(|s| s.inner).unwrap_or_default(),
+            augmentation: data::augment::Augmentation { color_flip, mirror },
+            targets: targets.map(|t| t.inner).unwrap_or_default(),
+            scalars,
+            provenance,
+        };
+        let mut tracker = tracker(progress, interval, cancel)?;
+        py.allow_threads(|| {
+            data::convert_games_and_save(&path, max_games, &out_dir, &*encoder, &options, &mut tracker)
+        })
+        .map_err(to_py_err)?;
+        Ok(())
+    }
+
+    /// Like `convert_games_and_save`, but splits the games by `split` in the same pass and
+    /// writes the shards of each set to `data/{set}/{name}`, see
+    /// `data::split::convert_games_split_and_save`. Returns the number of train, validation
+    /// and test shards.
+    #[staticmethod]
+    #[pyo3(signature = (
+        path, max_games, name, split, encoder=encoder::DEFAULT_ENCODER, policy=false,
+        dtype="float32", layout="nhwc", sampling=None, color_flip=false, mirror=false,
+        targets=None, scalars=false, provenance=false, progress=None, interval=1.0, cancel=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn convert_games_split_and_save(
+        py: Python<'_>,
+        path: PathBuf,
+        max_games: usize,
+        name: &str,
+        split: Split,
+        encoder: &str,
+        policy: bool,
+        dtype: &str,
+        layout: &str,
+        sampling: Option<Sampling>,
+        color_flip: bool,
+        mirror: bool,
+        targets: Option<ValueTargets>,
+        scalars: bool,
+        provenance: bool,
+        progress: Option<PyObject>,
+        interval: f64,
+        cancel: Option<CancelToken>,
+    ) -> PyResult<(usize, usize, usize)> {
+        let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+        let options = data::EncodeOptions {
+            policy,
+            format: tensor_format(dtype, layout)?,
+            sampling: sampling.map(|s| s.inner).unwrap_or_default(),
+            augmentation: data::augment::Augmentation { color_flip, mirror },
+            targets: targets.map(|t| t.inner).unwrap_or_default(),
+            scalars,
+            provenance,
+        };
+        let mut tracker = tracker(progress, interval, cancel)?;
+        let [train, validation, test] = py
+            .allow_threads(|| {
+                data::split::convert_games_split_and_save(
+                    &path,
+                    max_games,
+                    |set| PathBuf::from("data").join(set.name()).join(name),
+                    &*encoder,
+                    &options,
+                    &split.inner,
+                    &mut tracker,
+                )
+            })
+            .map_err(to_py_err)?;
+        Ok((train, validation, test))
+    }
+
+    /// Like `convert_games_and_save`, but writes every distinct position once, see
+    /// `data::dedup::convert_games_dedup_and_save`.
+    #[staticmethod]
+    #[pyo3(signature = (
+        path, name, encoder=encoder::DEFAULT_ENCODER, dtype="float32", layout="nhwc",
+        sampling=None, targets=None, shard_positions=100_000, memory_positions=1_000_000,
+        progress=None, interval=1.0, cancel=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn convert_games_dedup_and_save(
+        py: Python<'_>,
+        path: PathBuf,
+        name: &str,
+        encoder: &str,
+        dtype: &str,
+        layout: &str,
+        sampling: Option<Sampling>,
+        targets: Option<ValueTargets>,
+        shard_positions: usize,
+        memory_positions: usize,
+        progress: Option<PyObject>,
+        interval: f64,
+        cancel: Option<CancelToken>,
+    ) -> PyResult<usize> {
+        let out_dir = PathBuf::from("data/train").join(name);
+        let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+        let options = data::EncodeOptions {
+            policy: false,
+            format: tensor_format(dtype, layout)?,
+            sampling: sampling.map(|s| s.inner).unwrap_or_default(),
+            augmentation: Default::default(),
+            targets: targets.map(|t| t.inner).unwrap_or_default(),
+            scalars: false,
+            provenance: false,
+        };
+        let dedup = data::dedup::DedupOptions {
+            memory_positions,
+            shard_positions,
+        };
+        let mut tracker = tracker(progress, interval, cancel)?;
+        py.allow_threads(|| {
+            std::fs::create_dir_all(&out_dir)
+                .wrap_err_with(|| format!("failed to create {}", out_dir.display()))?;
+            data::dedup::convert_games_dedup_and_save(
+                &path, &out_dir, &*encoder, &options, &dedup, &mut tracker,
+            )
+        })
+        .map_err(to_py_err)
+    }
+}
+
+type PyPolicy<'py> = (Bound<'py, PyArray1<i32>>, Bound<'py, PyArray2<bool>>);
+
+#[derive(Clone, Copy)]
+struct ReadSend {
+    read: usize,
+    sent: usize,
+}
+
+/// A loaded shard with its inputs already in their format.
+struct LoadedBatch {
+    ins: InputTensor,
+    data: data::TrainData,
+}
+
+#[pyclass(module = "chessers.data")]
+pub struct TrainDataLoader {
+    receiver: mpsc::Receiver<Result<LoadedBatch>>,
+    // This is a kinda hacky way to inform the worker thread
+    // when we want more data. CBA with a condvar right now
+    read_sent_count: Arc<Mutex<ReadSend>>,
+    policy: bool,
+    weights: bool,
+    moves_left: bool,
+    scalars: bool,
+    provenance: bool,
+}
+
+#[pymethods]
+impl TrainDataLoader {
+    #[new]
+    /// With `color_flip` or `mirror` each position is replaced by its variant under these
+    /// symmetries with probability 1/2, see `data::augment::randomize`. `seed` defaults to
+    /// the current time, so every loader draws other variants.
+    #[pyo3(signature = (
+        files, prefetch, policy=false, weights=false, moves_left=false, scalars=false,
+        provenance=false, color_flip=false, mirror=false, seed=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        files: Vec<PathBuf>,
+        prefetch: usize,
+        policy: bool,
+        weights: bool,
+        moves_left: bool,
+        scalars: bool,
+        provenance: bool,
+        color_flip: bool,
+        mirror: bool,
+        seed: Option<u64>,
+    ) -> Self {
+        let augmentation = data::augment::Augmentation { color_flip, mirror };
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        let (tx, receiver) = mpsc::channel();
+        let read_sent_count = Arc::new(Mutex::new(ReadSend { read: 0, sent: 0 }));
+        {
+            let files = Arc::new(Mutex::new(files));
+            for _ in 0..prefetch.max(1).min(std::thread::available_parallelism().unwrap().get()) {
+                let files = Arc::clone(&files);
+                let read_sent_count = Arc::clone(&read_sent_count);
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    loop {
+                        if files.lock().unwrap().is_empty() {
+                            break
+                        }
+                        let ReadSend { read, sent } = {
+                            let mg = read_sent_count.lock().unwrap();
+                            *mg
+                        };
+                        if read > sent || (sent - read) < prefetch {
+                            let file = {
+                                let mut mg = files.lock().unwrap();
+                                match mg.pop() {
+                                    Some(f) => f,
+                                    None => break,
+                                }
+                            };
+                            let start = Instant::now();
+                            // Converting here keeps the work off the Python thread
+                            let batch = data::TrainData::load(&file).and_then(|mut data| {
+                                if !augmentation.is_none() {
+                                    // Seeded by the file, workers pick up files in any order
+                                    let path = file.as_os_str().as_encoded_bytes();
+                                    let bytes = path.iter().map(|&b| u64::from(b));
+                                    let seed = SplitMix64::mixed(seed, bytes).next_u64();
+                                    data::augment::randomize(&mut data, &augmentation, seed)?;
+                                }
+                                let ins = data.formatted_ins()?;
+                                data.ins = Default::default();
+                                Ok(LoadedBatch { ins, data })
+                            });
+                            let delta = start.elapsed().as_millis() as f64 / 1000.0;
+                            info!("Loaded {} in {:.2} seconds", file.display(), delta);
+                            if tx.send(batch).is_err() {
+                                break;
+                            }
+                            {
+                                let mut mg = read_sent_count.lock().unwrap();
+                                mg.sent += 1;
+                            }
+                        } else {
+                            std::thread::sleep(Duration::from_millis(50));
+                        }
+                    }
+                });
+            }
+        }
+        Self { receiver, read_sent_count, policy, weights, moves_left, scalars, provenance }
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Yields `(ins, outs)` batches, followed by `moves, legal` with policy targets, by
+    /// `weights` with sample weights, by the plies left with `moves_left`, by the scalar
+    /// features with `scalars` and by `games, plies, hashes` with `provenance`.
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        debug!("Reading next batch");
+        let now = Instant::now();
+        {
+            let mut mg = slf.read_sent_count.lock().unwrap();
+            debug!("Read {} batches total. Sent {} batches total", mg.read, mg.sent);
+            mg.read += 1;
+        }
+        let py = slf.py();
+        let (policy, weights) = (slf.policy, slf.weights);
+        let (moves_left, scalars, provenance) = (slf.moves_left, slf.scalars, slf.provenance);
+        // The receiver is `Send` but not `Sync`, so wait on it through the exclusive borrow
+        let receiver = &mut slf.receiver;
+        match py.allow_threads(move || receiver.recv()) {
+            Ok(batch) => {
+                let batch = batch
+                    .wrap_err("failed to load train data")
+                    .map_err(to_py_err)?;
+                debug!("Read batch in {:.2}s", now.elapsed().as_secs() as f64 / 1000.0);
+                let now = Instant::now();
+                let mut items = vec![
+                    ins_to_py(py, batch.ins),
+                    PyArray2::from_owned_array_bound(py, batch.data.outs).into_py(py),
+                ];
+                if policy {
+                    let p = batch
+                        .data
+                        .policy
+                        .ok_or_else(|| to_py_err(eyre!("train data has no policy targets")))?;
+                    items.push(PyArray1::from_owned_array_bound(py, p.moves).into_py(py));
+                    items.push(PyArray2::from_owned_array_bound(py, p.legal).into_py(py));
+                }
+                if weights {
+                    let w = batch
+                        .data
+                        .weights
+                        .ok_or_else(|| to_py_err(eyre!("train data has no sample weights")))?;
+                    items.push(PyArray1::from_owned_array_bound(py, w).into_py(py));
+                }
+                if moves_left {
+                    let m = batch
+                        .data
+                        .moves_left
+                        .ok_or_else(|| to_py_err(eyre!("train data has no moves left targets")))?;
+                    items.push(PyArray1::from_owned_array_bound(py, m).into_py(py));
+                }
+                if scalars {
+                    let s = batch
+                        .data
+                        .scalars
+                        .ok_or_else(|| to_py_err(eyre!("train data has no scalar features")))?;
+                    items.push(PyArray2::from_owned_array_bound(py, s).into_py(py));
+                }
+                if provenance {
+                    let p = batch
+                        .data
+                        .provenance
+                        .ok_or_else(|| to_py_err(eyre!("train data has no provenance")))?;
+                    items.push(PyArray1::from_owned_array_bound(py, p.games).into_py(py));
+                    items.push(PyArray1::from_owned_array_bound(py, p.plies).into_py(py));
+                    items.push(PyArray1::from_owned_array_bound(py, p.hashes).into_py(py));
+                }
+                let ret = Some(PyTuple::new_bound(py, items).into_py(py));
+                debug!("Conversion to python took {:.3}", now.elapsed().as_secs() as f64 / 1000.0);
+                Ok(ret)
+            },
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Names of the registered feature encoders.
+#[pyfunction]
+fn encoders() -> Vec<String> {
+    encoder::encoder_names()
+}
+
+/// Names of the planes produced by a feature encoder.
+#[pyfunction]
+#[pyo3(signature = (encoder=encoder::DEFAULT_ENCODER))]
+fn plane_names(encoder: &str) -> PyResult<Vec<String>> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    Ok(encoder.plane_names())
+}
+
+/// Names of the scalar features of positions encoded by a feature encoder.
+#[pyfunction]
+#[pyo3(signature = (encoder=encoder::DEFAULT_ENCODER))]
+fn scalar_names(encoder: &str) -> PyResult<Vec<String>> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    Ok(data::scalars::scalar_names(encoder.relative()))
+}
+
+/// Number of earlier positions a feature encoder stacks onto each position.
+#[pyfunction]
+#[pyo3(signature = (encoder=encoder::DEFAULT_ENCODER))]
+fn history(encoder: &str) -> PyResult<usize> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    Ok(encoder.history())
+}
+
+fn parse_turn(turn: &str) -> PyResult<Color> {
+    turn.parse()
+        .map_err(|_| PyValueError::new_err(format!("expected \"white\" or \"black\", got {:?}", turn)))
+}
+
+/// The UCI moves of the policy vocabulary for `turn` to move, `None` where an index leaves
+/// the board. With `flip` the board was mirrored for the side to move.
+#[pyfunction]
+#[pyo3(signature = (turn="white", flip=false))]
+fn move_vocabulary(turn: &str, flip: bool) -> PyResult<Vec<Option<String>>> {
+    let turn = parse_turn(turn)?;
+    Ok(policy::vocabulary(turn, flip)
+        .into_iter()
+        .map(|uci| uci.map(|uci| uci.to_string()))
+        .collect())
+}
+
+/// Policy vocabulary index of a UCI move, castling given by the king's destination.
+#[pyfunction]
+#[pyo3(signature = (uci, flip=false))]
+fn move_index(uci: &str, flip: bool) -> PyResult<usize> {
+    let parsed = uci
+        .parse::<UciMove>()
+        .map_err(|e| PyValueError::new_err(format!("invalid UCI move {:?}: {}", uci, e)))?;
+    policy::uci_index(&parsed, flip)
+        .ok_or_else(|| PyValueError::new_err(format!("{} is not in the move vocabulary", uci)))
+}
+
+/// The UCI move of a policy vocabulary index, `None` if it leaves the board.
+#[pyfunction]
+#[pyo3(signature = (index, turn="white", flip=false))]
+fn index_move(index: usize, turn: &str, flip: bool) -> PyResult<Option<String>> {
+    let turn = parse_turn(turn)?;
+    Ok(policy::index_uci(index, turn, flip).map(|uci| uci.to_string()))
+}
+
+/// Legal moves of a position, given as FEN or as the final position of a game, ranked by
+/// the probabilities a model predicted over the move vocabulary of `encoder`.
+#[pyfunction]
+#[pyo3(signature = (position, probabilities, encoder=encoder::DEFAULT_ENCODER))]
+fn rank_moves(
+    position: &Bound<'_, PyAny>,
+    probabilities: PyReadonlyArray1<'_, f32>,
+    encoder: &str,
+) -> PyResult<Vec<(String, f32)>> {
+    let pos: Chess = if let Ok(game) = position.extract::<PyRef<'_, Game>>() {
+        game.inner.replay().map_err(to_py_err)?
+    } else {
+        data::parse_fen(position.extract()?).map_err(to_py_err)?
+    };
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    let flip = encoder.relative() && pos.turn() == Color::Black;
+    let ranked = policy::rank_moves(&pos, probabilities.as_array(), flip).map_err(to_py_err)?;
+    Ok(ranked
+        .into_iter()
+        .map(|(m, p)| {
+            let m = m.to_uci_move().to_move(&pos).expect("ranked moves are legal");
+            (m.to_uci(CastlingMode::Standard).to_string(), p)
+        })
+        .collect())
+}
+
+/// Encodes a FEN of shape `(8, 8, planes)` the way positions of games are encoded for
+/// training. `history` are the FENs of the positions before it, oldest first.
+#[pyfunction]
+#[pyo3(signature = (fen, encoder=encoder::DEFAULT_ENCODER, history=None))]
+fn encode_fen<'py>(
+    py: Python<'py>,
+    fen: &str,
+    encoder: &str,
+    history: Option<Vec<String>>,
+) -> PyResult<Bound<'py, PyArray3<f32>>> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    let history = history.unwrap_or_default();
+    let encoded = data::encode_fen(fen, &history, &*encoder).map_err(to_py_err)?;
+    Ok(PyArray3::from_owned_array_bound(py, encoded))
+}
+
+/// Encodes a batch of FENs of shape `(len(fens), 8, 8, planes)` on `threads` threads,
+/// all cores by default. `histories` holds the FENs before each position, oldest first,
+/// encoders with history fail without them.
+#[pyfunction]
+#[pyo3(signature = (fens, encoder=encoder::DEFAULT_ENCODER, threads=None, histories=None))]
+fn encode_fens<'py>(
+    py: Python<'py>,
+    fens: Vec<String>,
+    encoder: &str,
+    threads: Option<usize>,
+    histories: Option<Vec<Vec<String>>>,
+) -> PyResult<Bound<'py, PyArray4<f32>>> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    let histories = histories.unwrap_or_default();
+    let batch = py
+        .allow_threads(|| {
+            data::encode_fens(&fens, &histories, &*encoder, threads.unwrap_or(0))
+        })
+        .map_err(to_py_err)?;
+    Ok(PyArray4::from_owned_array_bound(py, batch))
+}
+
+/// The FEN of an encoded position of shape `(8, 8, planes)`, failing if re-encoding it
+/// does not give the same planes.
+#[pyfunction]
+#[pyo3(signature = (planes, encoder=encoder::DEFAULT_ENCODER))]
+fn decode_planes(planes: PyReadonlyArray3<'_, f32>, encoder: &str) -> PyResult<String> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    let pos = data::decode::decode_planes(planes.as_array(), &*encoder).map_err(to_py_err)?;
+    Ok(Fen::from_position(pos, EnPassantMode::Legal).to_string())
+}
+
+/// Every plane of an encoded position as an ASCII board, rank 8 at the top.
+#[pyfunction]
+#[pyo3(signature = (planes, encoder=encoder::DEFAULT_ENCODER))]
+fn dump_planes(planes: PyReadonlyArray3<'_, f32>, encoder: &str) -> PyResult<String> {
+    let encoder = encoder::encoder(encoder).map_err(to_py_err)?;
+    Ok(data::decode::dump_planes(planes.as_array(), &encoder.plane_names()))
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decode_planes, m)?)?;
+    m.add_function(wrap_pyfunction!(dump_planes, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_fen, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_fens, m)?)?;
+    m.add_function(wrap_pyfunction!(move_vocabulary, m)?)?;
+    m.add_function(wrap_pyfunction!(move_index, m)?)?;
+    m.add_function(wrap_pyfunction!(index_move, m)?)?;
+    m.add_function(wrap_pyfunction!(rank_moves, m)?)?;
+    m.add_function(wrap_pyfunction!(encoders, m)?)?;
+    m.add_function(wrap_pyfunction!(plane_names, m)?)?;
+    m.add_function(wrap_pyfunction!(scalar_names, m)?)?;
+    m.add_function(wrap_pyfunction!(history, m)?)?;
+    m.add_class::<Sampling>()?;
+    m.add_class::<ValueTargets>()?;
+    m.add_class::<Split>()?;
+    m.add_class::<TrainData>()?;
+    m.add_class::<TrainDataLoader>()?;
+    Ok(())
+}
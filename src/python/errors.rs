@@ -0,0 +1,92 @@
+use crate::error;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+create_exception!(chessers, ChessersError, PyException, "Base class of all chessers errors.");
+create_exception!(chessers, FormatError, ChessersError, "Malformed game archive or train data.");
+create_exception!(chessers, ParseError, ChessersError, "Malformed PGN game.");
+create_exception!(chessers, IllegalMoveError, ChessersError, "Move which is not legal in its position.");
+create_exception!(chessers, FenError, ChessersError, "Malformed FEN or illegal position.");
+create_exception!(chessers, IoError, ChessersError, "Failed file operation.");
+create_exception!(chessers, CancelledError, ChessersError, "Operation stopped through its CancelToken.");
+
+fn with_attrs(py: Python<'_>, err: PyErr, attrs: &[(&str, PyObject)]) -> PyErr {
+    let value = err.value_bound(py);
+    for (name, attr) in attrs {
+        // Setting attributes on a fresh exception instance can't fail
+        value.setattr(*name, attr).unwrap();
+    }
+    err
+}
+
+/// Converts an error report into the most specific `ChessersError` found in its chain.
+///
+/// Python exceptions raised by callbacks are passed on unchanged.
+pub fn to_py_err(report: eyre::Report) -> PyErr {
+    let report = match report.downcast::<PyErr>() {
+        Ok(err) => return err,
+        Err(report) => report,
+    };
+    let message = format!("{:#}", report);
+    Python::with_gil(|py| {
+        for cause in report.chain() {
+            if let Some(e) = cause.downcast_ref::<error::IllegalMoveError>() {
+                return with_attrs(
+                    py,
+                    IllegalMoveError::new_err(message),
+                    &[("ply", e.ply.into_py(py)), ("uci", e.uci.clone().into_py(py))],
+                );
+            }
+            if let Some(e) = cause.downcast_ref::<error::ParseError>() {
+                return with_attrs(
+                    py,
+                    ParseError::new_err(message),
+                    &[("game", e.game.into_py(py))],
+                );
+            }
+            if let Some(e) = cause.downcast_ref::<error::FenError>() {
+                return with_attrs(py, FenError::new_err(message), &[("fen", e.fen.clone().into_py(py))]);
+            }
+            if cause.downcast_ref::<error::Cancelled>().is_some() {
+                return CancelledError::new_err(message);
+            }
+            if cause.downcast_ref::<error::FormatError>().is_some() {
+                return FormatError::new_err(message);
+            }
+            if let Some(e) = cause.downcast_ref::<error::IoError>() {
+                return with_attrs(
+                    py,
+                    IoError::new_err(message),
+                    &[
+                        ("filename", e.path.clone().into_py(py)),
+                        ("errno", e.source.raw_os_error().into_py(py)),
+                    ],
+                );
+            }
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return with_attrs(
+                    py,
+                    IoError::new_err(message),
+                    &[
+                        ("filename", py.None()),
+                        ("errno", e.raw_os_error().into_py(py)),
+                    ],
+                );
+            }
+        }
+        ChessersError::new_err(message)
+    })
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("ChessersError", py.get_type_bound::<ChessersError>())?;
+    m.add("FormatError", py.get_type_bound::<FormatError>())?;
+    m.add("ParseError", py.get_type_bound::<ParseError>())?;
+    m.add("IllegalMoveError", py.get_type_bound::<IllegalMoveError>())?;
+    m.add("FenError", py.get_type_bound::<FenError>())?;
+    m.add("IoError", py.get_type_bound::<IoError>())?;
+    m.add("CancelledError", py.get_type_bound::<CancelledError>())?;
+    Ok(())
+}
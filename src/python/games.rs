@@ -0,0 +1,374 @@
+use crate::games::{self, annotate, game, serialization};
+use eyre::{ensure, Result};
+use numpy::PyReadonlyArray2;
+use super::errors::to_py_err;
+use pyo3::prelude::*;
+use std::path::PathBuf;
+use std::time::Duration;
+use super::data;
+use super::progress::{tracker, CancelToken};
+
+#[pyfunction]
+#[pyo3(signature = (pgn_path, bin_path, min_elo, max_elo_diff, progress=None, interval=1.0, cancel=None))]
+#[allow(clippy::too_many_arguments)]
+fn pgn_to_bin(
+    py: Python<'_>,
+    pgn_path: &str,
+    bin_path: &str,
+    min_elo: i32,
+    max_elo_diff: i32,
+    progress: Option<PyObject>,
+    interval: f64,
+    cancel: Option<CancelToken>,
+) -> PyResult<()> {
+    let pgn_path = PathBuf::from(pgn_path.to_string());
+    let bin_path = PathBuf::from(bin_path.to_string());
+    let mut tracker = tracker(progress, interval, cancel)?;
+    py.allow_threads(|| games::pgn_to_bin(&pgn_path, &bin_path, min_elo, max_elo_diff, &mut tracker))
+        .map_err(to_py_err)
+}
+
+#[pyfunction]
+#[pyo3(signature = (bin_path, pgn_path, min_elo, max_elo_diff, progress=None, interval=1.0, cancel=None))]
+#[allow(clippy::too_many_arguments)]
+fn bin_to_pgn(
+    py: Python<'_>,
+    bin_path: &str,
+    pgn_path: &str,
+    min_elo: i32,
+    max_elo_diff: i32,
+    progress: Option<PyObject>,
+    interval: f64,
+    cancel: Option<CancelToken>,
+) -> PyResult<()> {
+    let pgn_path = PathBuf::from(pgn_path.to_string());
+    let bin_path = PathBuf::from(bin_path.to_string());
+    let mut tracker = tracker(progress, interval, cancel)?;
+    py.allow_threads(|| games::bin_to_pgn(&bin_path, &pgn_path, min_elo, max_elo_diff, &mut tracker))
+        .map_err(to_py_err)
+}
+
+/// Copies the games of `src_path` passing the filter into `dst_path`, returns the number
+/// of games read and written.
+#[pyfunction]
+#[pyo3(signature = (
+    src_path, dst_path, min_elo=0, max_elo_diff=5000, min_plies=0, timectl_sec=None,
+    progress=None, interval=1.0, cancel=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn filter_bin(
+    py: Python<'_>,
+    src_path: PathBuf,
+    dst_path: PathBuf,
+    min_elo: i32,
+    max_elo_diff: i32,
+    min_plies: usize,
+    timectl_sec: Option<i32>,
+    progress: Option<PyObject>,
+    interval: f64,
+    cancel: Option<CancelToken>,
+) -> PyResult<(usize, usize)> {
+    let filter = games::GameFilter {
+        min_elo,
+        max_elo_diff,
+        min_plies,
+        timectl_sec,
+    };
+    let mut tracker = tracker(progress, interval, cancel)?;
+    py.allow_threads(|| {
+        games::bin_to_bin(&src_path, &dst_path, |game| filter.accepts(game), &mut tracker)
+    })
+    .map_err(to_py_err)
+}
+
+#[pyclass(module = "chessers.games")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub(crate) inner: game::Game,
+    pub(crate) outcome: String,
+}
+
+impl Game {
+    fn new(inner: game::Game) -> Self {
+        Self {
+            outcome: match inner.outcome {
+                game::Outcome::BlackWin => "0-1".to_string(),
+                game::Outcome::WhiteWin => "1-0".to_string(),
+                game::Outcome::Draw => "1/2-1/2".to_string(),
+            },
+            inner,
+        }
+    }
+}
+
+#[pymethods]
+impl Game {
+    fn white(&self) -> &str {
+        &self.inner.white_name
+    }
+
+    fn black(&self) -> &str {
+        &self.inner.black_name
+    }
+
+    fn white_elo(&self) -> i32 {
+        self.inner.white_elo
+    }
+
+    fn black_elo(&self) -> i32 {
+        self.inner.black_elo
+    }
+
+    fn outcome(&self) -> &str {
+        &self.outcome
+    }
+
+    fn timectl_sec(&self) -> i32 {
+        self.inner.timectl_sec
+    }
+
+    fn timectl_inc(&self) -> i32 {
+        self.inner.timectl_inc
+    }
+
+    fn moves(&self) -> Vec<String> {
+        self.inner.moves.iter().map(|m| m.to_uci()).collect()
+    }
+
+    #[pyo3(signature = (line_width=80, event=None, site=None, date=None, round=None, termination=None))]
+    fn to_pgn(
+        &self,
+        line_width: usize,
+        event: Option<String>,
+        site: Option<String>,
+        date: Option<String>,
+        round: Option<String>,
+        termination: Option<String>,
+    ) -> PyResult<String> {
+        let defaults = game::PgnWriteOptions::default();
+        let options = game::PgnWriteOptions {
+            event: event.unwrap_or(defaults.event),
+            site: site.unwrap_or(defaults.site),
+            date: date.unwrap_or(defaults.date),
+            round: round.unwrap_or(defaults.round),
+            termination,
+            line_width,
+            ..defaults
+        };
+        let mut buf = Vec::new();
+        self.inner
+            .write_pgn_with(&mut buf, &options, &[])
+            .map_err(to_py_err)?;
+        String::from_utf8(buf).map_err(|e| to_py_err(e.into()))
+    }
+
+    /// `wdl` holds one `[white win, draw, black win]` row per ply, as predicted by the
+    /// model for the positions of `data::encode_game_positions`.
+    #[pyo3(signature = (wdl, good=0.15, mistake=0.1, blunder=0.2, line_width=80, nag_glyphs=true))]
+    fn to_annotated_pgn(
+        &self,
+        wdl: PyReadonlyArray2<'_, f32>,
+        good: f32,
+        mistake: f32,
+        blunder: f32,
+        line_width: usize,
+        nag_glyphs: bool,
+    ) -> PyResult<String> {
+        (|| -> Result<String> {
+            let wdl = wdl.as_array();
+            ensure!(wdl.shape()[1] == 3, "Expected WDL rows of length 3, got {}", wdl.shape()[1]);
+            let wdl: Vec<[f32; 3]> = wdl.rows().into_iter().map(|r| [r[0], r[1], r[2]]).collect();
+
+            let thresholds = annotate::SwingThresholds {
+                good,
+                mistake,
+                blunder,
+            };
+            let annotations = annotate::annotate_wdl(&self.inner, &wdl, &thresholds)?;
+            let options = game::PgnWriteOptions {
+                line_width,
+                nag_glyphs,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            self.inner.write_pgn_with(&mut buf, &options, &annotations)?;
+            Ok(String::from_utf8(buf)?)
+        }())
+        .map_err(to_py_err)
+    }
+
+    /// Encodes every position of the game. `evals` holds the engine eval of the position
+    /// after each ply as in `[%eval ...]`, e.g. `"0.35"` or `"#-2"`, or `None` where there
+    /// is none; the WDL of an eval takes the place of the result in the outcome label.
+    /// `clocks` holds the seconds left on the mover's clock after each ply, for the scalar
+    /// features.
+    #[pyo3(signature = (
+        encoder=crate::data::encoder::DEFAULT_ENCODER, evals=None, clocks=None, policy=false,
+        dtype="float32", layout="nhwc", targets=None, scalars=false
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn encode(
+        &self,
+        py: Python<'_>,
+        encoder: &str,
+        evals: Option<Vec<Option<String>>>,
+        clocks: Option<Vec<Option<f64>>>,
+        policy: bool,
+        dtype: &str,
+        layout: &str,
+        targets: Option<data::ValueTargets>,
+        scalars: bool,
+    ) -> PyResult<data::TrainData> {
+        let encoder = crate::data::encoder::encoder(encoder).map_err(to_py_err)?;
+        let annotations = (|| -> Result<Vec<game::Annotation>> {
+            let (evals, clocks) = (evals.unwrap_or_default(), clocks.unwrap_or_default());
+            ensure!(
+                evals.is_empty() || clocks.is_empty() || evals.len() == clocks.len(),
+                "Got {} evals but {} clocks",
+                evals.len(),
+                clocks.len()
+            );
+            (0..evals.len().max(clocks.len()))
+                .map(|ply| {
+                    let eval = evals.get(ply).cloned().flatten();
+                    let clock = clocks.get(ply).copied().flatten();
+                    Ok(game::Annotation {
+                        eval: eval.map(|eval| eval.parse()).transpose()?,
+                        clock: clock.map(Duration::try_from_secs_f64).transpose()?,
+                        ..Default::default()
+                    })
+                })
+                .collect()
+        })()
+        .map_err(to_py_err)?;
+        let options = crate::data::EncodeOptions {
+            policy,
+            format: data::tensor_format(dtype, layout)?,
+            targets: targets.map(|t| t.inner).unwrap_or_default(),
+            scalars,
+            ..Default::default()
+        };
+        let encoded = py
+            .allow_threads(|| {
+                crate::data::TrainData::from_game(&self.inner, &annotations, &*encoder, &options)
+            })
+            .map_err(to_py_err)?;
+        data::TrainData::new(py, encoded)
+    }
+}
+
+#[pyclass(module = "chessers.games")]
+struct GameLoader {
+    decoder: serialization::Decoder,
+    /// Number of games read so far, the ordinal of the next game.
+    games_read: u64,
+}
+
+#[pymethods]
+impl GameLoader {
+    #[new]
+    fn new(file_path: &str) -> PyResult<Self> {
+        (|| -> Result<Self> {
+            let decoder = serialization::Decoder::open(&PathBuf::from(file_path))?;
+            Ok(Self { decoder, games_read: 0 })
+        }())
+        .map_err(to_py_err)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyResult<Game>> {
+        match slf.decoder.read_game() {
+            Err(e) => Some(Err(to_py_err(e))),
+            Ok(Some(g)) => {
+                slf.games_read += 1;
+                Some(Ok(Game::new(g)))
+            }
+            Ok(None) => None,
+        }
+    }
+    
+    fn read_games(mut slf: PyRefMut<'_, Self>, max_games: usize) -> PyResult<Vec<Game>> {
+        let py = slf.py();
+        let decoder = &mut slf.decoder;
+        let games = py.allow_threads(|| -> Result<Vec<Game>> {
+            let mut games = Vec::with_capacity(max_games);
+            for _ in 0..max_games {
+                match decoder.read_game()? {
+                    Some(g) => games.push(Game::new(g)),
+                    None => break,
+                }
+            }
+            Ok(games)
+        }).map_err(to_py_err)?;
+        slf.games_read += games.len() as u64;
+        Ok(games)
+    }
+
+    #[pyo3(signature = (
+        max_games, encoder=crate::data::encoder::DEFAULT_ENCODER, policy=false, dtype="float32",
+        layout="nhwc", sampling=None, color_flip=false, mirror=false, targets=None, scalars=false,
+        provenance=false
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn convert_games(
+        mut slf: PyRefMut<'_, Self>,
+        max_games: usize,
+        encoder: &str,
+        policy: bool,
+        dtype: &str,
+        layout: &str,
+        sampling: Option<data::Sampling>,
+        color_flip: bool,
+        mirror: bool,
+        targets: Option<data::ValueTargets>,
+        scalars: bool,
+        provenance: bool,
+    ) -> PyResult<Option<data::TrainData>> {
+        let py = slf.py();
+        let encoder = crate::data::encoder::encoder(encoder).map_err(to_py_err)?;
+        let format = data::tensor_format(dtype, layout)?;
+        let options = crate::data::EncodeOptions {
+            policy,
+            format,
+            sampling: sampling.map(|s| s.inner).unwrap_or_default(),
+            augmentation: crate::data::augment::Augmentation { color_flip, mirror },
+            targets: targets.map(|t| t.inner).unwrap_or_default(),
+            scalars,
+            provenance,
+        };
+        let first_game = slf.games_read;
+        let GameLoader { decoder, games_read } = &mut *slf;
+        let data = py.allow_threads(|| -> Result<Option<crate::data::TrainData>> {
+            let mut games = Vec::new();
+            while games.len() < max_games {
+                match decoder.read_game_raw()? {
+                    Some(g) => games.push(g),
+                    None => break,
+                }
+            }
+            *games_read += games.len() as u64;
+            if games.is_empty() {
+                Ok(None)
+            } else {
+                crate::data::TrainData::from_games(games, first_game, &*encoder, &options)
+                    .map(Some)
+            }
+        })
+        .map_err(to_py_err)?;
+        data.map(|data| data::TrainData::new(py, data)).transpose()
+    }
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(pgn_to_bin, m)?)?;
+    m.add_function(wrap_pyfunction!(bin_to_pgn, m)?)?;
+    m.add_function(wrap_pyfunction!(filter_bin, m)?)?;
+
+    m.add_class::<Game>()?;
+    m.add_class::<GameLoader>()?;
+
+    Ok(())
+}
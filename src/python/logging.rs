@@ -0,0 +1,289 @@
+//! Forwarding of `tracing` events to Python's `logging` module.
+//!
+//! Events of target `chessers::data` go to the logger `chessers.data`, with the fields of
+//! the event and its enclosing spans in the `fields` attribute of the log record. The
+//! filter directives and the optional stderr output can be changed at runtime through
+//! `configure_logging`.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::dynamic_filter_fn;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt as tracing_fmt, reload, EnvFilter, Layer, Registry};
+
+use super::errors::ChessersError;
+
+/// Filter directives used when `RUST_LOG` is not set.
+const DEFAULT_DIRECTIVES: &str = "info";
+
+static FORWARD_TO_PYTHON: AtomicBool = AtomicBool::new(true);
+static WRITE_TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// Set if our subscriber became the global default.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+enum FieldValue {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Int(v) => write!(f, "{}", v),
+            FieldValue::UInt(v) => write!(f, "{}", v),
+            FieldValue::Float(v) => write!(f, "{}", v),
+            FieldValue::Bool(v) => write!(f, "{}", v),
+            FieldValue::Str(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl IntoPy<PyObject> for FieldValue {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self {
+            FieldValue::Int(v) => v.into_py(py),
+            FieldValue::UInt(v) => v.into_py(py),
+            FieldValue::Float(v) => v.into_py(py),
+            FieldValue::Bool(v) => v.into_py(py),
+            FieldValue::Str(v) => v.into_py(py),
+        }
+    }
+}
+
+/// The recorded fields of an event or span, the event message is kept apart.
+#[derive(Debug, Clone, Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(&'static str, FieldValue)>,
+}
+
+impl Fields {
+    fn set(&mut self, field: &Field, value: FieldValue) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else if let Some(slot) = self.values.iter_mut().find(|(k, _)| *k == field.name()) {
+            slot.1 = value;
+        } else {
+            self.values.push((field.name(), value));
+        }
+    }
+
+    fn write_values(&self, out: &mut String) {
+        for (i, (name, value)) in self.values.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            let _ = write!(out, "{}{}={}", sep, name, value);
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, FieldValue::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, FieldValue::UInt(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, FieldValue::Float(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, FieldValue::Str(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field, FieldValue::Str(format!("{:?}", value)));
+    }
+}
+
+/// Python `logging` level of a tracing level.
+fn python_level(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 40,
+        Level::WARN => 30,
+        Level::INFO => 20,
+        Level::DEBUG => 10,
+        Level::TRACE => 5,
+    }
+}
+
+/// Python logger name of a tracing target, always below `chessers`.
+fn logger_name(target: &str) -> String {
+    let name = target.replace("::", ".");
+    if name == "chessers" || name.starts_with("chessers.") {
+        name
+    } else {
+        format!("chessers.{}", name)
+    }
+}
+
+/// Layer handing every event to the Python logger of its target.
+struct PyLoggingLayer;
+
+impl<S> Layer<S> for PyLoggingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        // Formatted like the stderr output: `span{a=1}:span{b=2}: message c=3`
+        let mut message = String::new();
+        let mut all_fields = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                message.push_str(span.name());
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    if !span_fields.values.is_empty() {
+                        message.push('{');
+                        span_fields.write_values(&mut message);
+                        message.push('}');
+                    }
+                    all_fields.extend(span_fields.values.iter().cloned());
+                }
+                message.push_str(": ");
+            }
+        }
+        message.push_str(fields.message.as_deref().unwrap_or_default());
+        if !fields.values.is_empty() {
+            message.push(' ');
+            fields.write_values(&mut message);
+        }
+        all_fields.extend(fields.values);
+
+        let metadata = event.metadata();
+        Python::with_gil(|py| {
+            let result = (|| -> PyResult<()> {
+                let logger = py
+                    .import_bound("logging")?
+                    .call_method1("getLogger", (logger_name(metadata.target()),))?;
+                let level = python_level(metadata.level());
+                if !logger.call_method1("isEnabledFor", (level,))?.is_truthy()? {
+                    return Ok(());
+                }
+                let record = logger.call_method1(
+                    "makeRecord",
+                    (
+                        logger.getattr("name")?,
+                        level,
+                        metadata.file().unwrap_or("<unknown>"),
+                        metadata.line().unwrap_or(0),
+                        message,
+                        (),
+                        py.None(),
+                    ),
+                )?;
+                let dict = PyDict::new_bound(py);
+                for (name, value) in all_fields {
+                    dict.set_item(name, value.into_py(py))?;
+                }
+                record.setattr("fields", dict)?;
+                logger.call_method1("handle", (record,))?;
+                Ok(())
+            })();
+            if let Err(e) = result {
+                e.write_unraisable_bound(py, None);
+            }
+        });
+    }
+}
+
+fn env_filter(directives: &str) -> Result<EnvFilter, tracing_subscriber::filter::ParseError> {
+    EnvFilter::builder().parse(directives)
+}
+
+/// Installs the forwarding subscriber as global default, unless the process already has
+/// one. Logging then stays with the existing subscriber and `configure_logging` fails.
+pub fn init() {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_DIRECTIVES.into());
+    let filter = env_filter(&directives).unwrap_or_else(|_| EnvFilter::new(DEFAULT_DIRECTIVES));
+    let (filter, handle) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(PyLoggingLayer.with_filter(dynamic_filter_fn(|_, _| {
+            FORWARD_TO_PYTHON.load(Ordering::Relaxed)
+        })))
+        .with(
+            tracing_fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(dynamic_filter_fn(|_, _| {
+                    WRITE_TO_STDERR.load(Ordering::Relaxed)
+                })),
+        );
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// Changes what is logged where. `level` takes `RUST_LOG` style directives such as
+/// `"debug"` or `"info,chessers::data=trace"`, arguments left at `None` are unchanged.
+#[pyfunction]
+#[pyo3(signature = (level=None, stderr=None, python=None))]
+fn configure_logging(level: Option<&str>, stderr: Option<bool>, python: Option<bool>) -> PyResult<()> {
+    let handle = FILTER.get().ok_or_else(|| {
+        ChessersError::new_err("logging is handled by another tracing subscriber of this process")
+    })?;
+    if let Some(level) = level {
+        let filter = env_filter(level)
+            .map_err(|e| PyValueError::new_err(format!("invalid log directives {:?}: {}", level, e)))?;
+        handle
+            .reload(filter)
+            .map_err(|e| ChessersError::new_err(e.to_string()))?;
+    }
+    if let Some(stderr) = stderr {
+        WRITE_TO_STDERR.store(stderr, Ordering::Relaxed);
+    }
+    if let Some(python) = python {
+        FORWARD_TO_PYTHON.store(python, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(configure_logging, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use crate::progress::{self, ProgressTracker};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::time::{Duration, Instant};
+
+/// How often running operations check for `KeyboardInterrupt`, independent of the
+/// progress callback interval.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[pyclass(module = "chessers", frozen, get_all)]
+#[derive(Debug, Clone)]
+pub struct Progress {
+    bytes_processed: u64,
+    bytes_total: Option<u64>,
+    games_accepted: usize,
+    games_rejected: usize,
+    positions: usize,
+    elapsed: f64,
+    eta: Option<f64>,
+    done: bool,
+}
+
+impl From<&progress::Progress> for Progress {
+    fn from(p: &progress::Progress) -> Self {
+        Self {
+            bytes_processed: p.bytes_processed,
+            bytes_total: p.bytes_total,
+            games_accepted: p.games_accepted,
+            games_rejected: p.games_rejected,
+            positions: p.positions,
+            elapsed: p.elapsed.as_secs_f64(),
+            eta: p.eta().map(|eta| eta.as_secs_f64()),
+            done: p.done,
+        }
+    }
+}
+
+#[pymethods]
+impl Progress {
+    fn __repr__(&self) -> String {
+        format!(
+            "Progress(bytes_processed={}, games_accepted={}, games_rejected={}, positions={}, done={})",
+            self.bytes_processed, self.games_accepted, self.games_rejected, self.positions, self.done
+        )
+    }
+}
+
+#[pyclass(module = "chessers")]
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: progress::CancelToken,
+}
+
+#[pymethods]
+impl CancelToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel()
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// Tracker calling `callback` with a [`Progress`] every `interval` seconds and once when
+/// done. Exceptions raised by the callback, pending signals such as `KeyboardInterrupt`
+/// and a cancelled `cancel` token abort the operation. Raises `ValueError` for an
+/// `interval` which is negative, NaN or too large.
+pub fn tracker(
+    callback: Option<PyObject>,
+    interval: f64,
+    cancel: Option<CancelToken>,
+) -> PyResult<ProgressTracker<'static>> {
+    let interval =
+        progress::interval_from_secs(interval).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let mut last_call = Instant::now();
+    let tracker = ProgressTracker::new()
+        .with_interval(interval.min(SIGNAL_POLL_INTERVAL))
+        .with_callback(move |progress| {
+            Python::with_gil(|py| -> PyResult<()> {
+                py.check_signals()?;
+                if let Some(callback) = &callback {
+                    if progress.done || last_call.elapsed() >= interval {
+                        last_call = Instant::now();
+                        callback.call1(py, (Progress::from(progress),))?;
+                    }
+                }
+                Ok(())
+            })
+            .map_err(Into::into)
+        });
+    Ok(match cancel {
+        Some(cancel) => tracker.with_cancel_token(cancel.inner),
+        None => tracker,
+    })
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Progress>()?;
+    m.add_class::<CancelToken>()?;
+    Ok(())
+}